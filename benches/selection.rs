@@ -0,0 +1,303 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use cardano_multiplatform_lib::builders::tx_builder::TransactionBuilder;
+use cardano_multiplatform_lib::ledger::common::value::BigNum;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use dcspark_core::multisig_plan::MultisigPlan;
+use dcspark_core::network_id::NetworkInfo;
+use dcspark_core::tx::{CardanoPaymentCredentials, UTxOBuilder, UTxODetails};
+use dcspark_core::{Address, UTxOStore};
+use serde::Deserialize;
+use utxo_selection::algorithms::{
+    FeeChangeBalancer, LargestFirst, SingleOutputChangeBalancer, Thermostat, ThermostatAlgoConfig,
+};
+use utxo_selection::estimators::{CmlFeeEstimator, ThermostatFeeEstimator};
+use utxo_selection::{InputSelectionAlgorithm, TransactionFeeEstimator, UTxOStoreSupport};
+use utxo_selection_benchmark::bench::select_inputs;
+use utxo_selection_benchmark::bench_utils::network_parameters::{
+    transaction_builder_config, CardanoNetworkParameters,
+};
+use utxo_selection_benchmark::bench_utils::selection_snapshot::read_snapshots;
+
+// the config is passed through the environment, since criterion owns the command line
+const CONFIG_ENV: &str = "SELECTION_BENCH_CONFIG";
+const DEFAULT_CONFIG: &str = "configs/selection_bench.yml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThermostatEstimatorConfig {
+    network: NetworkInfo,
+    plan_path: PathBuf,
+    coins_per_utxo_byte: BigNum,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum BalanceChangeAlgoConfig {
+    #[default]
+    Fee,
+    SingleChange,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // written by run_benchmark with paths.selection_snapshots
+    snapshots_path: PathBuf,
+    // a snapshot belongs to the largest size which is not above the number of its inputs
+    #[serde(default = "default_sizes")]
+    sizes: Vec<usize>,
+    #[serde(default = "default_snapshots_per_size")]
+    snapshots_per_size: usize,
+
+    // the change of unbalanced selections is balanced as in run_benchmark
+    #[serde(default = "default_allow_balance_change")]
+    allow_balance_change: bool,
+    #[serde(default)]
+    change_balance_algo: BalanceChangeAlgoConfig,
+
+    #[serde(default)]
+    thermostat_estimator: Option<ThermostatEstimatorConfig>,
+    // payment key witnesses
+    #[serde(default)]
+    cml_estimator: Option<CardanoNetworkParameters>,
+}
+
+fn default_sizes() -> Vec<usize> {
+    vec![10, 100, 1_000, 10_000]
+}
+
+fn default_snapshots_per_size() -> usize {
+    10
+}
+
+fn default_allow_balance_change() -> bool {
+    true
+}
+
+struct Case {
+    available_inputs: UTxOStore,
+    fixed_outputs: Vec<UTxOBuilder>,
+    change_address: Address,
+}
+
+fn load_config() -> anyhow::Result<Config> {
+    let path =
+        PathBuf::from(std::env::var(CONFIG_ENV).unwrap_or_else(|_| DEFAULT_CONFIG.to_string()));
+    let file = File::open(&path)
+        .with_context(|| format!("Cannot read config file {path}", path = path.display()))?;
+    Ok(serde_yaml::from_reader(file)?)
+}
+
+fn load_cases(config: &Config) -> anyhow::Result<BTreeMap<usize, Vec<Case>>> {
+    let mut sizes = config.sizes.clone();
+    sizes.sort_unstable();
+
+    let mut cases = BTreeMap::<usize, Vec<Case>>::new();
+    for snapshot in read_snapshots(config.snapshots_path.clone())? {
+        let size = match sizes
+            .iter()
+            .rev()
+            .find(|size| **size <= snapshot.available_inputs.len())
+        {
+            Some(size) => *size,
+            None => continue,
+        };
+        let bucket = cases.entry(size).or_default();
+        if bucket.len() < config.snapshots_per_size {
+            bucket.push(Case {
+                available_inputs: snapshot.available_inputs()?,
+                fixed_outputs: snapshot.fixed_outputs(),
+                change_address: snapshot.change_address(),
+            });
+        }
+    }
+    Ok(cases)
+}
+
+fn bench_algorithm<
+    Estimator: TransactionFeeEstimator<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder>,
+    Algo: InputSelectionAlgorithm<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder> + UTxOStoreSupport,
+    ChangeBalanceAlgo: InputSelectionAlgorithm<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder> + UTxOStoreSupport,
+    EstimatorCreator,
+>(
+    c: &mut Criterion,
+    name: &str,
+    mut algorithm: Algo,
+    mut balance_change_algo: ChangeBalanceAlgo,
+    estimator_creator: EstimatorCreator,
+    allow_balance_change: bool,
+    cases: &BTreeMap<usize, Vec<Case>>,
+) where
+    EstimatorCreator: Fn() -> anyhow::Result<Estimator>,
+{
+    let mut group = c.benchmark_group(name);
+    for (size, cases) in cases.iter() {
+        // a measurement selects the inputs of every snapshot of the size,
+        // the stores and the estimators are prepared outside of it
+        group.bench_with_input(BenchmarkId::from_parameter(size), cases, |b, cases| {
+            b.iter_batched(
+                || {
+                    cases
+                        .iter()
+                        .map(|case| {
+                            (
+                                case.available_inputs.clone(),
+                                case.fixed_outputs.clone(),
+                                RefCell::new(Some(estimator_creator().unwrap())),
+                                &case.change_address,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                },
+                |inputs| {
+                    for (available_inputs, fixed_outputs, estimator, change_address) in inputs {
+                        // failed selections are timed as well, the algorithm did the work before giving up
+                        let selection = select_inputs(
+                            &mut algorithm,
+                            &mut balance_change_algo,
+                            &|| {
+                                estimator
+                                    .take()
+                                    .ok_or_else(|| anyhow!("the estimator is already used"))
+                            },
+                            available_inputs,
+                            fixed_outputs,
+                            change_address,
+                            allow_balance_change,
+                        )
+                        .unwrap();
+                        black_box(selection.is_ok());
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_change_algo<
+    Estimator: TransactionFeeEstimator<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder>,
+    ChangeBalanceAlgo: InputSelectionAlgorithm<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder>
+        + UTxOStoreSupport
+        + Default,
+    EstimatorCreator,
+>(
+    c: &mut Criterion,
+    estimator_name: &str,
+    estimator_creator: EstimatorCreator,
+    config: &Config,
+    cases: &BTreeMap<usize, Vec<Case>>,
+) where
+    EstimatorCreator: Fn() -> anyhow::Result<Estimator>,
+{
+    bench_algorithm(
+        c,
+        &format!("largest_first/{estimator_name}"),
+        LargestFirst::try_from(UTxOStore::new()).unwrap(),
+        ChangeBalanceAlgo::default(),
+        &estimator_creator,
+        config.allow_balance_change,
+        cases,
+    );
+    bench_algorithm(
+        c,
+        &format!("thermostat/{estimator_name}"),
+        Thermostat::new(ThermostatAlgoConfig::default()),
+        ChangeBalanceAlgo::default(),
+        &estimator_creator,
+        config.allow_balance_change,
+        cases,
+    );
+}
+
+fn bench_estimator<
+    Estimator: TransactionFeeEstimator<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder>,
+    EstimatorCreator,
+>(
+    c: &mut Criterion,
+    estimator_name: &str,
+    estimator_creator: EstimatorCreator,
+    config: &Config,
+    cases: &BTreeMap<usize, Vec<Case>>,
+) where
+    EstimatorCreator: Fn() -> anyhow::Result<Estimator>,
+{
+    match config.change_balance_algo {
+        BalanceChangeAlgoConfig::Fee => bench_change_algo::<_, FeeChangeBalancer, _>(
+            c,
+            estimator_name,
+            estimator_creator,
+            config,
+            cases,
+        ),
+        BalanceChangeAlgoConfig::SingleChange => {
+            bench_change_algo::<_, SingleOutputChangeBalancer, _>(
+                c,
+                estimator_name,
+                estimator_creator,
+                config,
+                cases,
+            )
+        }
+    }
+}
+
+fn selection(c: &mut Criterion) {
+    let config = load_config().unwrap();
+    if !config.snapshots_path.is_file() {
+        println!(
+            "skipping the selection benchmarks: no snapshots at {}, write them with paths.selection_snapshots of run_benchmark",
+            config.snapshots_path.display()
+        );
+        return;
+    }
+    let cases = load_cases(&config).unwrap();
+    for (size, cases) in cases.iter() {
+        println!("size {}: {} snapshots", size, cases.len());
+    }
+
+    if let Some(estimator) = config.thermostat_estimator.clone() {
+        let plan = MultisigPlan::load(estimator.plan_path).unwrap();
+        bench_estimator(
+            c,
+            "thermostat_estimator",
+            || {
+                Ok(ThermostatFeeEstimator::new(
+                    estimator.network.clone(),
+                    &plan,
+                    estimator.coins_per_utxo_byte,
+                ))
+            },
+            &config,
+            &cases,
+        );
+    }
+
+    if let Some(parameters) = config.cml_estimator.clone() {
+        let builder_config = transaction_builder_config(&parameters).unwrap();
+        bench_estimator(
+            c,
+            "cml_estimator",
+            || {
+                CmlFeeEstimator::new(
+                    TransactionBuilder::new(&builder_config),
+                    CardanoPaymentCredentials::PaymentKey,
+                    false,
+                    parameters.coins_per_utxo_byte,
+                )
+            },
+            &config,
+            &cases,
+        );
+    }
+}
+
+criterion_group!(benches, selection);
+criterion_main!(benches);
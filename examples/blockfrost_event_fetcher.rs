@@ -0,0 +1,201 @@
+use anyhow::{anyhow, Context};
+use cardano_multiplatform_lib::address::StakeCredential;
+use cardano_multiplatform_lib::PolicyID;
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use utxo_selection_benchmark::blockfrost::{
+    blockfrost_tx_to_events, BlockfrostBackend, BlockfrostClient, FixtureBackend, HttpBackend,
+    RequestPolicy,
+};
+use utxo_selection_benchmark::generation_utils::clean_events;
+use utxo_selection_benchmark::mapper::DataMapper;
+use utxo_selection_benchmark::network::NetworkProfile;
+use utxo_selection_benchmark::utils::dump_hashset_to_file;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // overrides the endpoint of the network
+    #[serde(default)]
+    endpoint: Option<String>,
+    #[serde(default)]
+    network: NetworkProfile,
+    #[serde(default)]
+    key: Option<String>,
+    // read the responses from the fixture files instead of blockfrost
+    #[serde(default)]
+    fixtures_path: Option<PathBuf>,
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    addresses: Vec<String>,
+    // fetch all the addresses of the stake address
+    #[serde(default)]
+    stake_address: Option<String>,
+    retries: u64,
+    #[serde(default)]
+    requests_per_second: Option<f64>,
+
+    payment_creds_mapping: PathBuf,
+    staking_creds_mapping: PathBuf,
+    policy_mapping: PathBuf,
+    asset_name_mapping: PathBuf,
+
+    banned_addresses: PathBuf,
+    // hashes of the transactions that can't be converted, not readable by the carp tools
+    failed_transactions: PathBuf,
+
+    events_output_path: PathBuf,
+    cleaned_events_output_path: PathBuf,
+}
+
+impl Config {
+    fn endpoint(&self) -> String {
+        self.endpoint
+            .clone()
+            .unwrap_or_else(|| self.network.blockfrost_endpoint())
+    }
+}
+
+#[derive(Parser, Debug)]
+#[clap(version)]
+pub struct Cli {
+    /// path to config file
+    #[clap(long, value_parser)]
+    config_path: PathBuf,
+}
+
+#[tokio::main]
+async fn main() {
+    let result = _main().await;
+    result.unwrap();
+}
+
+async fn _main() -> anyhow::Result<()> {
+    // Start logging setup block
+    let fmt_layer = tracing_subscriber::fmt::layer().with_test_writer();
+
+    tracing_subscriber::registry().with(fmt_layer).init();
+
+    let Cli { config_path } = Cli::parse();
+
+    tracing::info!("Config file {:?}", config_path);
+    let file = File::open(&config_path).with_context(|| {
+        format!(
+            "Cannot read config file {path}",
+            path = config_path.display()
+        )
+    })?;
+    let config: Config = serde_yaml::from_reader(file).with_context(|| {
+        format!(
+            "Cannot read config file {path}",
+            path = config_path.display()
+        )
+    })?;
+
+    let policy = RequestPolicy::new(config.retries, config.requests_per_second);
+    match (config.fixtures_path.clone(), config.key.clone()) {
+        (Some(fixtures_path), _) => {
+            let client = BlockfrostClient::new(FixtureBackend::new(fixtures_path), policy);
+            fetch_events(config, client).await
+        }
+        (None, Some(key)) => {
+            let client = BlockfrostClient::new(HttpBackend::new(config.endpoint(), &key)?, policy);
+            fetch_events(config, client).await
+        }
+        (None, None) => Err(anyhow!("either key or fixtures_path must be set")),
+    }
+}
+
+async fn fetch_events<Backend: BlockfrostBackend>(
+    config: Config,
+    client: BlockfrostClient<Backend>,
+) -> anyhow::Result<()> {
+    let mut stake_address_to_num = DataMapper::<StakeCredential>::new();
+    let mut payment_address_to_num = DataMapper::<StakeCredential>::new();
+    let mut policy_id_to_num = DataMapper::<PolicyID>::new();
+    let mut asset_name_to_num = DataMapper::<String>::new();
+    let mut banned_addresses = HashSet::<(u64, Option<u64>)>::new();
+
+    let mut failed_transactions = HashSet::<String>::new();
+
+    let addresses: Vec<String> = config
+        .address
+        .iter()
+        .chain(config.addresses.iter())
+        .cloned()
+        .collect();
+    let addresses = client
+        .resolve_addresses(&addresses, config.stake_address.as_deref())
+        .await?;
+    if addresses.is_empty() {
+        return Err(anyhow!("no addresses to fetch"));
+    }
+    let transactions = client.addresses_transactions(&addresses).await?;
+    tracing::info!(
+        "Total addresses: {:?}, total transactions: {:?}",
+        addresses.len(),
+        transactions.len()
+    );
+
+    let mut out_file = File::create(config.events_output_path.clone())?;
+
+    for (num, tx) in transactions.into_iter().enumerate() {
+        let hash = tx.tx_hash.to_string();
+        let utxos = client.tx_utxos(&hash).await?;
+        let details = client.tx_details(&hash).await?;
+        match blockfrost_tx_to_events(
+            &utxos,
+            &details,
+            &mut stake_address_to_num,
+            &mut payment_address_to_num,
+            &mut policy_id_to_num,
+            &mut asset_name_to_num,
+            &mut banned_addresses,
+        ) {
+            Ok(Some(tx_event)) => {
+                out_file
+                    .write_all(format!("{}\n", serde_json::to_string(&tx_event)?).as_bytes())?;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                tracing::warn!("Can't convert tx: {:?}, err: {:?}", hash, err);
+                failed_transactions.insert(hash);
+            }
+        }
+        if num % 1000 == 0 {
+            tracing::info!("Processed {:?} transactions", num + 1);
+        }
+    }
+
+    drop(out_file);
+
+    tracing::info!("Fetching finished, dumping files");
+    tracing::info!("Total failed transactions: {:?}", failed_transactions.len());
+
+    dump_hashset_to_file(&failed_transactions, config.failed_transactions)?;
+
+    payment_address_to_num.dump_to_file(config.payment_creds_mapping)?;
+    stake_address_to_num.dump_to_file(config.staking_creds_mapping)?;
+    policy_id_to_num.dump_to_file(config.policy_mapping)?;
+    asset_name_to_num.dump_to_file(config.asset_name_mapping)?;
+    dump_hashset_to_file(&banned_addresses, config.banned_addresses)?;
+
+    tracing::info!("Dumping finished, cleaning events");
+
+    clean_events(
+        config.events_output_path,
+        config.cleaned_events_output_path,
+        &banned_addresses,
+    )?;
+
+    tracing::info!("Cleaning finished");
+
+    Ok(())
+}
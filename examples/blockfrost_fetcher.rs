@@ -1,42 +1,48 @@
 use anyhow::{anyhow, Context};
 use clap::Parser;
-use dcspark_core::tx::TransactionId;
-use dcspark_core::BlockNumber;
-use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::{header, Client, StatusCode};
 use serde::Deserialize;
-use std::fmt;
-use std::fs::File;
-use std::io::Write;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::str::FromStr;
 
 use std::path::PathBuf;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
+use utxo_selection_benchmark::blockfrost::{
+    BlockfrostClient, HttpBackend, RequestPolicy, PAGE_SIZE,
+};
+use utxo_selection_benchmark::network::NetworkProfile;
+use utxo_selection_benchmark::utils::{dump_hashmap_to_file, read_hashmap_from_file};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
-    endpoint: String,
+    // overrides the endpoint of the network
+    #[serde(default)]
+    endpoint: Option<String>,
+    #[serde(default)]
+    network: NetworkProfile,
     key: String,
-    address: String,
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    addresses: Vec<String>,
+    // fetch all the addresses of the stake address
+    #[serde(default)]
+    stake_address: Option<String>,
     txs_output_path: PathBuf,
     retries: u64,
+    #[serde(default)]
+    requests_per_second: Option<f64>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "snake_case")]
-pub struct BlockfrostTransaction {
-    pub tx_hash: TransactionId,
-    pub tx_index: u64,
-    pub block_time: u64,
-    pub block_height: BlockNumber,
-}
-
-#[derive(Clone, Debug, Deserialize)]
-pub struct BlockFrostError {
-    pub status_code: usize,
-    pub error: String,
-    pub message: String,
+impl Config {
+    fn endpoint(&self) -> String {
+        self.endpoint
+            .clone()
+            .unwrap_or_else(|| self.network.blockfrost_endpoint())
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -75,82 +81,91 @@ async fn _main() -> anyhow::Result<()> {
         )
     })?;
 
-    let mut headers = HeaderMap::new();
-    headers.append(
-        header::CONTENT_TYPE,
-        HeaderValue::from_static("application/cbor"),
-    );
-    headers.append(
-        "project_id",
-        HeaderValue::from_str(&config.key)
-            .context("The project_id (authentication key) is not in a valid format")?,
+    let client = BlockfrostClient::new(
+        HttpBackend::new(config.endpoint(), &config.key)?,
+        RequestPolicy::new(config.retries, config.requests_per_second),
     );
 
-    let client = reqwest::Client::builder()
-        .default_headers(headers)
-        .build()
-        .context("Failed to build HTTP Client")?;
-
-    let mut output = File::create(config.txs_output_path.clone())?;
-    let mut last_transactions_count = 1;
-    let mut page = 1;
-    while last_transactions_count > 0 {
-        let mut retires = config.retries;
-        let mut transactions = vec![];
-        while retires > 0 {
-            let txs = get_txs(&client, &config, page).await;
-            if let Ok(txs) = txs {
-                transactions = txs;
-                break;
-            } else {
-                retires -= 1;
-                if retires == 0 {
-                    return Err(anyhow!("retires limit reached at page {}", page));
-                }
+    let addresses: Vec<String> = config
+        .address
+        .iter()
+        .chain(config.addresses.iter())
+        .cloned()
+        .collect();
+    let addresses = client
+        .resolve_addresses(&addresses, config.stake_address.as_deref())
+        .await?;
+    if addresses.is_empty() {
+        return Err(anyhow!("no addresses to fetch"));
+    }
+    tracing::info!("Total addresses: {:?}", addresses.len());
+
+    // next page to fetch for every address, updated after every written page
+    let progress_path = config.txs_output_path.with_extension("progress");
+    let mut progress: HashMap<String, u64> = if progress_path.is_file() {
+        tracing::info!("resuming from {:?}", progress_path);
+        read_hashmap_from_file(progress_path.clone())?
+    } else {
+        HashMap::new()
+    };
+    let mut output = if progress.is_empty() {
+        File::create(config.txs_output_path.clone())?
+    } else {
+        OpenOptions::new()
+            .append(true)
+            .open(config.txs_output_path.clone())?
+    };
+
+    for address in addresses.iter() {
+        let mut page = progress.get(address).cloned().unwrap_or(1);
+        loop {
+            let transactions = client.address_transactions(address, page).await?;
+            let count = transactions.len();
+            for tx in transactions {
+                output.write_all(
+                    format!("{} {} {}\n", tx.block_height, tx.tx_index, tx.tx_hash).as_bytes(),
+                )?;
             }
-        }
-
-        last_transactions_count = transactions.len();
+            output.flush()?;
+            tracing::info!("address {}: fetched page {:?}", address, page);
 
-        for tx in transactions {
-            output.write_all(format!("{} {}\n", tx.block_height, tx.tx_hash).as_bytes())?;
+            // the last page can get new transactions, so it is fetched again on resume
+            if count < PAGE_SIZE {
+                progress.insert(address.clone(), page);
+                dump_hashmap_to_file(&progress, progress_path.clone())?;
+                break;
+            }
+            page += 1;
+            progress.insert(address.clone(), page);
+            dump_hashmap_to_file(&progress, progress_path.clone())?;
         }
-        page += 1;
     }
+    drop(output);
 
-    Ok(())
-}
+    sort_transactions(config.txs_output_path)?;
 
-fn url(endpoint: &String, api: impl fmt::Display) -> String {
-    format!("{endpoint}/{api}")
+    Ok(())
 }
 
-async fn get_txs(
-    client: &Client,
-    config: &Config,
-    page: u64,
-) -> anyhow::Result<Vec<BlockfrostTransaction>> {
-    let req = client
-        .get(url(
-            &config.endpoint,
-            format!(
-                "api/v0/addresses/{}/transactions?page={}",
-                config.address, page
-            ),
-        ))
-        .send()
-        .await
-        .context("Failed to get transactions from blockfrost endpoint")?;
-
-    match req.status() {
-        StatusCode::OK => {
-            let txs: Vec<BlockfrostTransaction> = req
-                .json()
-                .await
-                .context("Expect the endpoint to return transaction data")?;
-
-            Ok(txs)
+// transactions of several addresses (or of a resumed run) are put in the chain order without duplicates
+fn sort_transactions(path: PathBuf) -> anyhow::Result<()> {
+    let mut transactions = BTreeSet::<(u64, u64, String)>::new();
+    for line in BufReader::new(File::open(path.clone())?).lines() {
+        let line = line?;
+        let split: Vec<&str> = line.split(' ').collect();
+        if split.len() != 3 {
+            return Err(anyhow!("unexpected line: {}", line));
         }
-        code => Err(anyhow!("error: {:?}", code)),
+        transactions.insert((
+            u64::from_str(split[0])?,
+            u64::from_str(split[1])?,
+            split[2].to_string(),
+        ));
+    }
+
+    let mut output = File::create(path)?;
+    for (block_height, tx_index, tx_hash) in transactions {
+        output.write_all(format!("{} {} {}\n", block_height, tx_index, tx_hash).as_bytes())?;
     }
+    Ok(())
 }
@@ -6,24 +6,31 @@ use cardano_multiplatform_lib::address::StakeCredential;
 use cardano_multiplatform_lib::PolicyID;
 use clap::Parser;
 
-use entity::sea_orm::Database;
 use entity::sea_orm::QueryFilter;
+use entity::sea_orm::{Database, DatabaseConnection};
 use entity::{
     prelude::*,
     sea_orm::{prelude::*, ColumnTrait, Condition, EntityTrait, QueryOrder, QuerySelect},
 };
-use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::cmp::min;
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
+use std::fs::{File, OpenOptions};
+use std::hash::Hash;
 use std::io::Write;
 use tracing_subscriber::prelude::*;
 use utxo_selection_benchmark::generation_utils::{
-    carp_tx_to_events, clean_events, dump_unparsed_transactions_to_file,
+    carp_tx_to_events, clean_events, clean_events_tail, count_events,
+    dump_previous_outputs_to_file, dump_unparsed_transactions_to_file, events_contain_addresses,
+    read_previous_outputs_from_file, read_unparsed_transactions_from_file, truncate_events,
+    PointerResolver, UnparsedTransaction,
 };
-use utxo_selection_benchmark::mapper::DataMapper;
+use utxo_selection_benchmark::mapper::{DataMapper, MappingFormat};
+use utxo_selection_benchmark::network::NetworkProfile;
 use utxo_selection_benchmark::tx_event::TxOutput;
-use utxo_selection_benchmark::utils::dump_hashset_to_file;
+use utxo_selection_benchmark::utils::{dump_hashset_to_file, read_hashset_from_file};
+use utxo_selection_benchmark::utxo_index::{OnDiskUtxoIndex, UtxoIndex, UtxoIndexConfig};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -51,6 +58,63 @@ pub struct Config {
     events_output_path: PathBuf,
     cleaned_events_output_path: PathBuf,
     tx_per_page: i64,
+    #[serde(default)]
+    network: NetworkProfile,
+    // text or binary, the mappings of both formats can be loaded
+    #[serde(default)]
+    mapping_format: MappingFormat,
+    // map byron addresses instead of treating them as unknown ones
+    #[serde(default)]
+    byron_address_mapping: Option<PathBuf>,
+    // start from the first transaction instead of the shelley era start, requires byron mapping
+    #[serde(default)]
+    replay_from_genesis: bool,
+    // stake registrations used to resolve pointer addresses
+    #[serde(default)]
+    pointers_mapping: Option<PathBuf>,
+    // unspent outputs after the last transaction, used to retry the unparsed transactions
+    #[serde(default)]
+    previous_outputs: Option<PathBuf>,
+    // where the unspent outputs are kept while fetching
+    #[serde(default)]
+    utxo_index: UtxoIndexConfig,
+    // extend the existing events, mappings, banned addresses and utxo index after the last processed tx
+    #[serde(default)]
+    resume: bool,
+    // id of the last processed tx and the number of events, written after every run and required to resume
+    #[serde(default)]
+    last_processed_tx_path: Option<PathBuf>,
+    // clean all the events again when the addresses banned by the resumed run are used by the previous events
+    #[serde(default)]
+    reclean_on_new_bans: bool,
+}
+
+// state of the last completed run, a run interrupted before writing it leaves the previous one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessedCheckpoint {
+    last_processed_tx: i64,
+    events: usize,
+    cleaned_events: usize,
+    unparsed_transactions: usize,
+}
+
+impl ProcessedCheckpoint {
+    fn read(path: PathBuf) -> anyhow::Result<Self> {
+        let file = File::open(&path)
+            .with_context(|| format!("Cannot read last processed tx {}", path.display()))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    // written through a temporary file, so an interrupted write keeps the previous checkpoint
+    fn write(&self, path: PathBuf) -> anyhow::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(format!("{}\n", serde_json::to_string(self)?).as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -113,7 +177,7 @@ async fn _main() -> anyhow::Result<()> {
 
     /////////
     let shelley_first_blocks = Block::find()
-        .filter(BlockColumn::Epoch.eq(208))
+        .filter(BlockColumn::Epoch.eq(config.network.shelley_start_epoch()))
         .order_by_asc(BlockColumn::Id)
         .limit(256)
         .all(&conn)
@@ -147,10 +211,110 @@ async fn _main() -> anyhow::Result<()> {
         .ok_or_else(|| anyhow!("Can't find first tx"))?;
     tracing::info!("Shelley first tx, {:?}", shelley_first_tx);
 
-    //////////////
+    let first_tx = if config.replay_from_genesis {
+        if config.byron_address_mapping.is_none() {
+            return Err(anyhow!(
+                "byron_address_mapping is required to replay from genesis"
+            ));
+        }
+        let genesis_first_tx = Transaction::find()
+            .order_by_asc(TransactionColumn::Id)
+            .limit(1)
+            .all(&conn)
+            .await?
+            .first()
+            .map(|tx| tx.id)
+            .ok_or_else(|| anyhow!("Can't find first tx"))?;
+        tracing::info!("Genesis first tx, {:?}", genesis_first_tx);
+        genesis_first_tx
+    } else {
+        shelley_first_tx
+    };
+
+    let checkpoint = if config.resume {
+        let path = config
+            .last_processed_tx_path
+            .clone()
+            .ok_or_else(|| anyhow!("last_processed_tx_path is required to resume"))?;
+        let checkpoint = ProcessedCheckpoint::read(path)?;
+        tracing::info!(
+            "Resuming after tx {:?}, events: {:?}",
+            checkpoint.last_processed_tx,
+            checkpoint.events
+        );
+        Some(checkpoint)
+    } else {
+        None
+    };
+    let first_tx = match &checkpoint {
+        Some(checkpoint) => checkpoint.last_processed_tx + 1,
+        None => first_tx,
+    };
+
+    let checkpoint_path = config.last_processed_tx_path.clone();
+    let processed = match config.utxo_index.clone() {
+        UtxoIndexConfig::InMemory => {
+            let previous_outputs_path = config.previous_outputs.clone();
+            let previous_outputs = if config.resume {
+                let path = previous_outputs_path.clone().ok_or_else(|| {
+                    anyhow!("previous_outputs is required to resume with the in-memory index")
+                })?;
+                read_previous_outputs_from_file(path)?
+            } else {
+                HashMap::<String, HashMap<u64, TxOutput>>::new()
+            };
+            let (previous_outputs, processed) =
+                fetch_events(config, conn, first_tx, checkpoint, previous_outputs).await?;
+            if let Some(path) = previous_outputs_path {
+                dump_previous_outputs_to_file(&previous_outputs, path)?;
+            }
+            processed
+        }
+        UtxoIndexConfig::OnDisk { path, cache_size } => {
+            let previous_outputs_path = config.previous_outputs.clone();
+            let previous_outputs = OnDiskUtxoIndex::open(path.clone(), cache_size)?;
+            if !config.resume && !previous_outputs.is_empty() {
+                return Err(anyhow!("utxo index {:?} is not empty", path));
+            }
+            // the index is changed as the transactions are processed, so an interrupted run leaves it ahead
+            if let (Some(checkpoint), Some(index_tx)) =
+                (&checkpoint, previous_outputs.last_processed_tx()?)
+            {
+                if index_tx > checkpoint.last_processed_tx {
+                    return Err(anyhow!(
+                        "utxo index {:?} has transactions up to {} while the last completed run stopped at {}, restore or rebuild the index",
+                        path,
+                        index_tx,
+                        checkpoint.last_processed_tx
+                    ));
+                }
+            }
+            let (previous_outputs, processed) =
+                fetch_events(config, conn, first_tx, checkpoint, previous_outputs).await?;
+            if let Some(path) = previous_outputs_path {
+                dump_previous_outputs_to_file(&previous_outputs, path)?;
+            }
+            processed
+        }
+    };
+
+    // written last: everything above is redone when the run is interrupted
+    if let Some(path) = checkpoint_path {
+        processed.write(path)?;
+    }
+
+    Ok(())
+}
 
+async fn fetch_events<Index: UtxoIndex>(
+    config: Config,
+    conn: DatabaseConnection,
+    first_tx: i64,
+    checkpoint: Option<ProcessedCheckpoint>,
+    mut previous_outputs: Index,
+) -> anyhow::Result<(Index, ProcessedCheckpoint)> {
     let transactions = Transaction::find()
-        .filter(TransactionColumn::Id.gte(shelley_first_tx))
+        .filter(TransactionColumn::Id.gte(first_tx))
         .order_by_asc(TransactionColumn::Id)
         .paginate(&conn, config.tx_per_page as usize);
     let total_transactions = transactions.num_items().await?;
@@ -158,20 +322,41 @@ async fn _main() -> anyhow::Result<()> {
     tracing::info!("Total transactions: {:?}", total_transactions);
     tracing::info!("Total pages: {:?}", total_pages);
 
-    let mut out_file = if config.events_output_path.exists() && config.events_output_path.is_file()
-    {
+    // the events of the previous runs are kept, the new ones are added to the end.
+    // events written after the checkpoint belong to an interrupted run and are dropped
+    let existing_events = match &checkpoint {
+        Some(checkpoint) => {
+            let dropped = truncate_events(config.events_output_path.clone(), checkpoint.events)?;
+            if config.cleaned_events_output_path.exists() {
+                truncate_events(
+                    config.cleaned_events_output_path.clone(),
+                    checkpoint.cleaned_events,
+                )?;
+            }
+            if dropped > 0 {
+                tracing::warn!("Dropped {:?} events of an interrupted run", dropped);
+            }
+            checkpoint.events
+        }
+        None => 0,
+    };
+    let mut written_events = 0;
+    let mut out_file = if config.resume {
         tracing::info!(
-            "file {:?} already exists, adding lines to the end",
-            config.events_output_path
+            "file {:?} has {:?} events, adding lines to the end",
+            config.events_output_path,
+            existing_events
         );
-        File::open(config.events_output_path.clone())
+        OpenOptions::new()
+            .append(true)
+            .open(config.events_output_path.clone())
     } else {
         File::create(config.events_output_path.clone())
     }?;
 
-    let mut current_start = shelley_first_tx;
-    let mut current_end = shelley_first_tx + config.tx_per_page;
-    let max_end = shelley_first_tx + total_transactions as i64;
+    let mut current_start = first_tx;
+    let mut current_end = first_tx + config.tx_per_page;
+    let max_end = first_tx + total_transactions as i64;
 
     let mut current_query = Transaction::find()
         .filter(
@@ -183,15 +368,49 @@ async fn _main() -> anyhow::Result<()> {
         .all(&conn)
         .await?;
 
-    let mut previous_outputs = HashMap::<String, HashMap<u64, TxOutput>>::new();
-
-    let mut stake_address_to_num = DataMapper::<StakeCredential>::new();
-    let mut payment_address_to_num = DataMapper::<StakeCredential>::new();
-    let mut policy_id_to_num = DataMapper::<PolicyID>::new();
-    let mut asset_name_to_num = DataMapper::<String>::new();
-    let mut banned_addresses = HashSet::<(u64, Option<u64>)>::new();
+    let mut stake_address_to_num =
+        load_mapper::<StakeCredential>(config.resume, config.staking_creds_mapping.clone())?;
+    let mut payment_address_to_num =
+        load_mapper::<StakeCredential>(config.resume, config.payment_creds_mapping.clone())?;
+    let mut policy_id_to_num =
+        load_mapper::<PolicyID>(config.resume, config.policy_mapping.clone())?;
+    let mut asset_name_to_num =
+        load_mapper::<String>(config.resume, config.asset_name_mapping.clone())?;
+    let mut byron_address_to_num = match config.byron_address_mapping.clone() {
+        Some(path) => Some(load_mapper::<String>(config.resume, path)?),
+        None => None,
+    };
+    let mut banned_addresses = if config.resume {
+        read_hashset_from_file(config.banned_addresses.clone())?
+    } else {
+        HashSet::<(u64, Option<u64>)>::new()
+    };
+    let previously_banned = banned_addresses.clone();
+    let mut pointer_resolver = match (config.resume, config.pointers_mapping.clone()) {
+        (true, Some(path)) => PointerResolver::load_from_file(path)?,
+        _ => PointerResolver::default(),
+    };
 
-    let mut unparsed_transactions = Vec::<TransactionModel>::new();
+    // the unparsed transactions are dumped in order, so the ones of an interrupted run are at the end
+    let mut unparsed_transactions = match &checkpoint {
+        Some(checkpoint) => {
+            let mut unparsed =
+                read_unparsed_transactions_from_file(config.unparsed_transactions.clone())?;
+            let dropped = unparsed
+                .len()
+                .saturating_sub(checkpoint.unparsed_transactions);
+            unparsed.truncate(checkpoint.unparsed_transactions);
+            if dropped > 0 {
+                tracing::warn!(
+                    "Dropped {:?} unparsed transactions of an interrupted run",
+                    dropped
+                );
+            }
+            unparsed
+        }
+        None => Vec::<UnparsedTransaction>::new(),
+    };
+    let mut last_processed_tx = first_tx - 1;
 
     while !current_query.is_empty() {
         let tx_count = current_query.len();
@@ -202,7 +421,21 @@ async fn _main() -> anyhow::Result<()> {
             tx_count,
             max_end
         );
+        let block_ids: HashSet<i32> = current_query.iter().map(|tx| tx.block_id).collect();
+        let block_slots = Block::find()
+            .filter(BlockColumn::Id.is_in(block_ids))
+            .all(&conn)
+            .await?
+            .into_iter()
+            .map(|block| (block.id, block.slot as u64))
+            .collect();
+        pointer_resolver.set_block_slots(block_slots);
+        if let Some(last_tx) = current_query.last() {
+            previous_outputs.set_last_processed_tx(last_tx.id)?;
+        }
+
         for tx in current_query {
+            last_processed_tx = tx.id;
             let tx_event = carp_tx_to_events(
                 &tx,
                 &mut previous_outputs,
@@ -210,12 +443,15 @@ async fn _main() -> anyhow::Result<()> {
                 &mut payment_address_to_num,
                 &mut policy_id_to_num,
                 &mut asset_name_to_num,
+                byron_address_to_num.as_mut(),
+                &mut pointer_resolver,
                 &mut banned_addresses,
                 &mut unparsed_transactions,
             )?;
             if let Some(tx_event) = tx_event {
                 out_file
                     .write_all(format!("{}\n", serde_json::to_string(&tx_event)?).as_bytes())?;
+                written_events += 1;
             }
         }
 
@@ -241,23 +477,86 @@ async fn _main() -> anyhow::Result<()> {
         unparsed_transactions.len()
     );
 
+    tracing::info!(
+        "Total unresolved pointer outputs: {:?}",
+        pointer_resolver.total_unresolved()
+    );
+
+    let total_unparsed = unparsed_transactions.len();
     dump_unparsed_transactions_to_file(config.unparsed_transactions, unparsed_transactions)?;
 
-    payment_address_to_num.dump_to_file(config.payment_creds_mapping)?;
-    stake_address_to_num.dump_to_file(config.staking_creds_mapping)?;
-    policy_id_to_num.dump_to_file(config.policy_mapping)?;
-    asset_name_to_num.dump_to_file(config.asset_name_mapping)?;
+    let format = config.mapping_format;
+    payment_address_to_num.dump_to_file_in_format(config.payment_creds_mapping, format)?;
+    stake_address_to_num.dump_to_file_in_format(config.staking_creds_mapping, format)?;
+    policy_id_to_num.dump_to_file_in_format(config.policy_mapping, format)?;
+    asset_name_to_num.dump_to_file_in_format(config.asset_name_mapping, format)?;
+    if let (Some(mapper), Some(path)) = (byron_address_to_num, config.byron_address_mapping) {
+        mapper.dump_to_file_in_format(path, format)?;
+    }
+    if let Some(path) = config.pointers_mapping {
+        pointer_resolver.dump_to_file(path)?;
+    }
     dump_hashset_to_file(&banned_addresses, config.banned_addresses)?;
+    previous_outputs.flush()?;
 
     tracing::info!("Dumping finished, cleaning events");
 
-    clean_events(
-        config.events_output_path,
-        config.cleaned_events_output_path,
-        &banned_addresses,
-    )?;
+    // addresses banned by this run can be used by the events of the previous runs
+    let new_bans: HashSet<(u64, Option<u64>)> = banned_addresses
+        .difference(&previously_banned)
+        .cloned()
+        .collect();
+    let outdated_events = existing_events > 0
+        && !new_bans.is_empty()
+        && events_contain_addresses(
+            config.events_output_path.clone(),
+            existing_events,
+            &new_bans,
+        )?;
+    if outdated_events && !config.reclean_on_new_bans {
+        tracing::warn!(
+            "previous events use {:?} newly banned addresses, set reclean_on_new_bans to clean them",
+            new_bans.len()
+        );
+    }
 
-    tracing::info!("Cleaning finished");
+    let stats = if existing_events == 0 || (outdated_events && config.reclean_on_new_bans) {
+        clean_events(
+            config.events_output_path,
+            config.cleaned_events_output_path.clone(),
+            &banned_addresses,
+        )?
+    } else {
+        clean_events_tail(
+            config.events_output_path,
+            config.cleaned_events_output_path.clone(),
+            &banned_addresses,
+            existing_events,
+        )?
+    };
 
-    Ok(())
+    tracing::info!(
+        "Cleaning finished, cleaned events: {:?}, dropped: {:?}",
+        stats.total,
+        stats.dropped
+    );
+
+    let processed = ProcessedCheckpoint {
+        last_processed_tx,
+        events: existing_events + written_events,
+        cleaned_events: count_events(config.cleaned_events_output_path)?,
+        unparsed_transactions: total_unparsed,
+    };
+    Ok((previous_outputs, processed))
+}
+
+fn load_mapper<T: Hash + Eq + Serialize + DeserializeOwned + Clone>(
+    resume: bool,
+    path: PathBuf,
+) -> anyhow::Result<DataMapper<T>> {
+    if resume {
+        DataMapper::<T>::load_from_file(path)
+    } else {
+        Ok(DataMapper::<T>::new())
+    }
 }
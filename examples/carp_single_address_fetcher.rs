@@ -7,11 +7,11 @@ use cardano_multiplatform_lib::crypto::TransactionHash;
 use cardano_multiplatform_lib::PolicyID;
 use clap::Parser;
 
-use entity::sea_orm::Database;
 use entity::sea_orm::QueryFilter;
+use entity::sea_orm::{Database, DatabaseConnection};
 use entity::{
     prelude::*,
-    sea_orm::{ColumnTrait, Condition, EntityTrait, QueryOrder},
+    sea_orm::{ColumnTrait, EntityTrait},
 };
 use serde::Deserialize;
 
@@ -20,7 +20,8 @@ use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
 use tracing_subscriber::prelude::*;
 use utxo_selection_benchmark::generation_utils::{
-    carp_tx_to_events, dump_unparsed_transactions_to_file,
+    carp_output_to_tx_output, carp_tx_inputs, carp_tx_to_events, dump_previous_outputs_to_file,
+    dump_unparsed_transactions_to_file, PointerResolver, UnparsedTransaction,
 };
 use utxo_selection_benchmark::mapper::DataMapper;
 use utxo_selection_benchmark::tx_event::TxOutput;
@@ -51,6 +52,22 @@ pub struct Config {
     banned_addresses: PathBuf,
     events_output_path: PathBuf,
     input_transactions_path: PathBuf,
+    // map byron addresses instead of treating them as unknown ones
+    #[serde(default)]
+    byron_address_mapping: Option<PathBuf>,
+    // stake registrations dumped by the carp fetcher, used to resolve pointer addresses
+    #[serde(default)]
+    pointers_mapping: Option<PathBuf>,
+    // unspent outputs after the last transaction, used to retry the unparsed transactions
+    #[serde(default)]
+    previous_outputs: Option<PathBuf>,
+    // number of transactions requested from carp in a single query
+    #[serde(default = "default_batch_size")]
+    batch_size: usize,
+}
+
+fn default_batch_size() -> usize {
+    256
 }
 
 #[derive(Parser, Debug)]
@@ -144,35 +161,82 @@ async fn _main() -> anyhow::Result<()> {
     let mut payment_address_to_num = DataMapper::<StakeCredential>::new();
     let mut policy_id_to_num = DataMapper::<PolicyID>::new();
     let mut asset_name_to_num = DataMapper::<String>::new();
+    let mut byron_address_to_num = config
+        .byron_address_mapping
+        .as_ref()
+        .map(|_| DataMapper::<String>::new());
     let mut banned_addresses = HashSet::<(u64, Option<u64>)>::new();
+    let mut pointer_resolver = match config.pointers_mapping.clone() {
+        Some(path) => PointerResolver::load_from_file(path)?,
+        None => PointerResolver::default(),
+    };
 
-    let mut unparsed_transactions = Vec::<TransactionModel>::new();
+    let mut unparsed_transactions = Vec::<UnparsedTransaction>::new();
 
-    for tx_hash in transactions_hashes {
-        let current_query: Vec<TransactionModel> = Transaction::find()
-            .filter(Condition::all().add(TransactionColumn::Hash.eq(tx_hash.clone())))
-            .order_by_asc(TransactionColumn::Id)
+    // outputs of these transactions are registered while parsing, the rest is taken from carp
+    let listed: HashSet<String> = transactions_hashes.iter().map(hex::encode).collect();
+
+    for chunk in transactions_hashes.chunks(config.batch_size.max(1)) {
+        let batch: HashMap<Vec<u8>, TransactionModel> = Transaction::find()
+            .filter(TransactionColumn::Hash.is_in(chunk.to_vec()))
             .all(&conn)
-            .await?;
-        let tx = current_query.first().ok_or_else(|| {
-            anyhow!(
-                "tx is missing: tx_hash: {:?}",
-                TransactionHash::from_bytes(tx_hash).unwrap().to_bech32("")
-            )
-        })?;
-
-        let tx_event = carp_tx_to_events(
-            tx,
+            .await?
+            .into_iter()
+            .map(|tx| (tx.hash.clone(), tx))
+            .collect();
+
+        let mut external_inputs = HashMap::<String, HashSet<u64>>::new();
+        for tx in batch.values() {
+            for (hash, index) in carp_tx_inputs(tx) {
+                let known = previous_outputs
+                    .get(&hash)
+                    .map(|outputs| outputs.contains_key(&index))
+                    .unwrap_or(false);
+                if !known && !listed.contains(&hash) {
+                    external_inputs.entry(hash).or_default().insert(index);
+                }
+            }
+        }
+        resolve_outputs(
+            &conn,
+            external_inputs,
             &mut previous_outputs,
-            &mut stake_address_to_num,
             &mut payment_address_to_num,
+            &mut stake_address_to_num,
             &mut policy_id_to_num,
             &mut asset_name_to_num,
-            &mut banned_addresses,
-            &mut unparsed_transactions,
-        )?;
-        if let Some(tx_event) = tx_event {
-            out_file.write_all(format!("{}\n", serde_json::to_string(&tx_event)?).as_bytes())?;
+            byron_address_to_num.as_mut(),
+            &mut pointer_resolver,
+        )
+        .await?;
+
+        // the input file order is kept, so the outputs are registered before they are spent
+        for tx_hash in chunk {
+            let tx = batch.get(tx_hash).ok_or_else(|| {
+                anyhow!(
+                    "tx is missing: tx_hash: {:?}",
+                    TransactionHash::from_bytes(tx_hash.clone())
+                        .unwrap()
+                        .to_bech32("")
+                )
+            })?;
+
+            let tx_event = carp_tx_to_events(
+                tx,
+                &mut previous_outputs,
+                &mut stake_address_to_num,
+                &mut payment_address_to_num,
+                &mut policy_id_to_num,
+                &mut asset_name_to_num,
+                byron_address_to_num.as_mut(),
+                &mut pointer_resolver,
+                &mut banned_addresses,
+                &mut unparsed_transactions,
+            )?;
+            if let Some(tx_event) = tx_event {
+                out_file
+                    .write_all(format!("{}\n", serde_json::to_string(&tx_event)?).as_bytes())?;
+            }
         }
     }
 
@@ -190,9 +254,86 @@ async fn _main() -> anyhow::Result<()> {
     stake_address_to_num.dump_to_file(config.staking_creds_mapping)?;
     policy_id_to_num.dump_to_file(config.policy_mapping)?;
     asset_name_to_num.dump_to_file(config.asset_name_mapping)?;
+    if let (Some(mapper), Some(path)) = (byron_address_to_num, config.byron_address_mapping) {
+        mapper.dump_to_file(path)?;
+    }
     dump_hashset_to_file(&banned_addresses, config.banned_addresses)?;
+    if let Some(path) = config.previous_outputs {
+        dump_previous_outputs_to_file(&previous_outputs, path)?;
+    }
 
     tracing::info!("Dumping finished");
 
     Ok(())
 }
+
+// fetches the spent outputs created by transactions outside of the input list
+#[allow(clippy::too_many_arguments)]
+async fn resolve_outputs(
+    conn: &DatabaseConnection,
+    inputs: HashMap<String, HashSet<u64>>,
+    previous_outputs: &mut HashMap<String, HashMap<u64, TxOutput>>,
+    payment_address_to_num: &mut DataMapper<StakeCredential>,
+    stake_address_to_num: &mut DataMapper<StakeCredential>,
+    policy_id_to_num: &mut DataMapper<PolicyID>,
+    asset_name_to_num: &mut DataMapper<String>,
+    mut byron_address_to_num: Option<&mut DataMapper<String>>,
+    pointer_resolver: &mut PointerResolver,
+) -> anyhow::Result<()> {
+    if inputs.is_empty() {
+        return Ok(());
+    }
+    let hashes = inputs
+        .keys()
+        .map(hex::decode)
+        .collect::<Result<Vec<_>, _>>()?;
+    let funding_transactions: HashMap<i64, String> = Transaction::find()
+        .filter(TransactionColumn::Hash.is_in(hashes))
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|tx| (tx.id, hex::encode(tx.hash)))
+        .collect();
+    let outputs: Vec<TransactionOutputModel> = TransactionOutput::find()
+        .filter(
+            TransactionOutputColumn::TxId
+                .is_in(funding_transactions.keys().cloned().collect::<Vec<_>>()),
+        )
+        .all(conn)
+        .await?;
+
+    for output in outputs {
+        let tx_hash = match funding_transactions.get(&output.tx_id) {
+            Some(tx_hash) => tx_hash,
+            None => continue,
+        };
+        let index = output.output_index as u64;
+        let spent = inputs
+            .get(tx_hash)
+            .map(|indices| indices.contains(&index))
+            .unwrap_or(false);
+        if !spent {
+            continue;
+        }
+        match carp_output_to_tx_output(
+            &output.payload,
+            payment_address_to_num,
+            stake_address_to_num,
+            policy_id_to_num,
+            asset_name_to_num,
+            byron_address_to_num.as_deref_mut(),
+            pointer_resolver,
+        ) {
+            Ok(parsed) => {
+                previous_outputs
+                    .entry(tx_hash.clone())
+                    .or_default()
+                    .insert(index, parsed);
+            }
+            Err(err) => {
+                tracing::warn!("can't parse output {}#{}: {:?}", tx_hash, index, err);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use clap::Parser;
+use std::path::PathBuf;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use utxo_selection_benchmark::mapper::{
+    convert_mapping_file, detect_mapping_format, MappingFormat,
+};
+
+#[derive(Parser, Debug)]
+#[clap(version)]
+pub struct Cli {
+    /// mapping file in text or binary format
+    #[clap(long, value_parser)]
+    input: PathBuf,
+    /// path to the converted mapping
+    #[clap(long, value_parser)]
+    output: PathBuf,
+    /// text or binary
+    #[clap(long, value_parser)]
+    format: MappingFormat,
+}
+
+fn main() {
+    let result = _main();
+    result.unwrap();
+}
+
+fn _main() -> anyhow::Result<()> {
+    let fmt_layer = tracing_subscriber::fmt::layer().with_test_writer();
+
+    tracing_subscriber::registry().with(fmt_layer).init();
+
+    let Cli {
+        input,
+        output,
+        format,
+    } = Cli::parse();
+
+    tracing::info!(
+        "converting {:?} ({:?}) to {:?} ({:?})",
+        input,
+        detect_mapping_format(input.clone())?,
+        output,
+        format
+    );
+    convert_mapping_file(input, output, format)?;
+    tracing::info!("Conversion finished");
+
+    Ok(())
+}
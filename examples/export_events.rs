@@ -0,0 +1,68 @@
+use anyhow::{anyhow, Context};
+use clap::Parser;
+use serde::Deserialize;
+use std::fs::File;
+use std::path::PathBuf;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use utxo_selection_benchmark::parquet_export::export_events;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    events_path: PathBuf,
+    // events.parquet, inputs.parquet, outputs.parquet and assets.parquet are written there
+    output_dir: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+#[clap(version)]
+pub struct Cli {
+    /// path to config file
+    #[clap(long, value_parser)]
+    config_path: PathBuf,
+}
+
+fn main() {
+    let result = _main();
+    result.unwrap();
+}
+
+fn _main() -> anyhow::Result<()> {
+    // Start logging setup block
+    let fmt_layer = tracing_subscriber::fmt::layer().with_test_writer();
+
+    tracing_subscriber::registry().with(fmt_layer).init();
+
+    let Cli { config_path } = Cli::parse();
+
+    tracing::info!("Config file {:?}", config_path);
+    let file = File::open(&config_path).with_context(|| {
+        format!(
+            "Cannot read config file {path}",
+            path = config_path.display()
+        )
+    })?;
+    let config: Config = serde_yaml::from_reader(file).with_context(|| {
+        format!(
+            "Cannot read config file {path}",
+            path = config_path.display()
+        )
+    })?;
+
+    if !config.events_path.is_file() {
+        return Err(anyhow!("can't open events file: {:?}", config.events_path));
+    }
+
+    let summary = export_events(config.events_path, config.output_dir)?;
+
+    tracing::info!(
+        "Export finished, events: {:?}, inputs: {:?}, outputs: {:?}, assets: {:?}",
+        summary.events,
+        summary.inputs,
+        summary.outputs,
+        summary.assets
+    );
+
+    Ok(())
+}
@@ -5,13 +5,17 @@ use cardano_multiplatform_lib::crypto::{Ed25519KeyHash, ScriptHash};
 use clap::Parser;
 use pallas_addresses::{ShelleyDelegationPart, ShelleyPaymentPart};
 use serde::Deserialize;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use utxo_selection_benchmark::generation_utils::clean_events;
+use utxo_selection_benchmark::generation_utils::{
+    carp_tx_inputs, clean_events, read_previous_outputs_from_file,
+    read_unparsed_transactions_from_file, unparsed_output_addresses, CleaningStats,
+    PointerResolver,
+};
 use utxo_selection_benchmark::mapper::DataMapper;
 
 use utxo_selection_benchmark::utils::{dump_hashset_to_file, read_hashset_from_file};
@@ -22,7 +26,18 @@ pub struct Config {
     events_path: PathBuf,
     cleaned_events_output_path: PathBuf,
 
-    unparsed_transaction_addresses: PathBuf,
+    // bech32 addresses to ban in addition to the ones of the unparsed transactions
+    #[serde(default)]
+    unparsed_transaction_addresses: Option<PathBuf>,
+    // unparsed transactions dumped by the fetcher
+    #[serde(default)]
+    unparsed_transactions: Option<PathBuf>,
+    // unspent outputs dumped by the fetcher, used to find the input addresses
+    #[serde(default)]
+    previous_outputs: Option<PathBuf>,
+    // why the addresses are banned and how the events changed
+    #[serde(default)]
+    report_path: Option<PathBuf>,
 
     payment_creds_mapping: PathBuf,
     payment_creds_mapping_output: PathBuf,
@@ -30,6 +45,10 @@ pub struct Config {
     staking_creds_mapping_output: PathBuf,
     banned_addresses: PathBuf,
     banned_addresses_output: PathBuf,
+
+    // stake registrations dumped by the carp fetcher, used to resolve pointer addresses
+    #[serde(default)]
+    pointers_mapping: Option<PathBuf>,
 }
 
 #[derive(Parser, Debug)]
@@ -68,16 +87,11 @@ async fn _main() -> anyhow::Result<()> {
         )
     })?;
 
-    let unparsed_addresses_file = if config.unparsed_transaction_addresses.exists()
-        && config.unparsed_transaction_addresses.is_file()
-    {
-        File::open(config.unparsed_transaction_addresses.clone())?
-    } else {
+    if config.unparsed_transactions.is_none() && config.unparsed_transaction_addresses.is_none() {
         return Err(anyhow!(
-            "can't open input file: {:?}",
-            config.unparsed_transaction_addresses
+            "either unparsed_transactions or unparsed_transaction_addresses must be set"
         ));
-    };
+    }
 
     tracing::info!("loading mappings");
 
@@ -93,61 +107,104 @@ async fn _main() -> anyhow::Result<()> {
         read_hashset_from_file(config.banned_addresses)?;
     tracing::info!("banned addresses loaded");
 
+    let pointer_resolver = match config.pointers_mapping {
+        Some(path) => PointerResolver::load_from_file(path)?,
+        None => PointerResolver::default(),
+    };
+    tracing::info!("pointers loaded");
+
     tracing::info!("successfully loaded mappings");
 
-    let unparsed_addresses_file_lines = BufReader::new(unparsed_addresses_file).lines();
-    for line in unparsed_addresses_file_lines {
-        let address = line?;
+    // reasons of the bans made here, the reasons of the fetcher bans are unknown
+    let mut ban_reasons = HashMap::<(u64, Option<u64>), Vec<String>>::new();
+    // addresses that can't be banned: byron, stake, unresolved pointers or undecodable ones
+    let mut skipped = Vec::<(String, String)>::new();
 
-        match pallas_addresses::Address::from_bech32(address.as_str()) {
-            Ok(address) => {
-                let (payment, staking) = match address {
-                    pallas_addresses::Address::Byron(_) => {
-                        /* ignore */
-                        continue;
-                    }
-                    pallas_addresses::Address::Shelley(shelley) => {
-                        let payment_cred = match shelley.payment() {
-                            ShelleyPaymentPart::Key(key) => StakeCredential::from_keyhash(
-                                &Ed25519KeyHash::from_bytes(key.to_vec()).unwrap(),
-                            ),
-                            ShelleyPaymentPart::Script(script) => StakeCredential::from_scripthash(
-                                &ScriptHash::from_bytes(script.to_vec()).unwrap(),
-                            ),
-                        };
-                        let staking_cred: Option<StakeCredential> = match shelley.delegation() {
-                            ShelleyDelegationPart::Null => None,
-                            ShelleyDelegationPart::Key(key) => Some(StakeCredential::from_keyhash(
-                                &Ed25519KeyHash::from_bytes(key.to_vec()).unwrap(),
+    let mut addresses = Vec::<(pallas_addresses::Address, String)>::new();
+    if let Some(path) = config.unparsed_transaction_addresses.clone() {
+        let file = File::open(path.clone())
+            .with_context(|| format!("can't open input file: {:?}", path))?;
+        for line in BufReader::new(file).lines() {
+            let address = line?;
+            match pallas_addresses::Address::from_bech32(address.as_str()) {
+                Ok(parsed) => addresses.push((parsed, "listed in the input file".to_string())),
+                Err(err) => {
+                    tracing::error!("can't parse address: {:?}, addr={:?}", err, address);
+                    skipped.push((address, format!("can't parse address: {:?}", err)));
+                }
+            }
+        }
+    }
+
+    if let Some(path) = config.unparsed_transactions.clone() {
+        let previous_outputs = match config.previous_outputs.clone() {
+            Some(path) => read_previous_outputs_from_file(path)?,
+            None => HashMap::new(),
+        };
+        for unparsed in read_unparsed_transactions_from_file(path)? {
+            let tx_hash = hex::encode(&unparsed.tx.hash);
+            let reason = format!("unparsed tx {} ({:?})", tx_hash, unparsed.reason);
+
+            // unparsed txs don't spend their inputs, the known ones are still in the previous outputs
+            for (input_hash, input_index) in carp_tx_inputs(&unparsed.tx) {
+                let address = previous_outputs
+                    .get(&input_hash)
+                    .and_then(|outputs| outputs.get(&input_index))
+                    .and_then(|output| output.address);
+                match address {
+                    Some(address) => ban_reasons
+                        .entry(address)
+                        .or_default()
+                        .push(format!("{}: input", reason)),
+                    None => skipped.push((
+                        format!("{}@{}", input_hash, input_index),
+                        format!("{}: unknown input", reason),
+                    )),
+                }
+            }
+
+            match unparsed_output_addresses(&unparsed.tx) {
+                Ok(outputs) => {
+                    for raw in outputs {
+                        match pallas_addresses::Address::from_bytes(&raw) {
+                            Ok(parsed) => addresses.push((parsed, format!("{}: output", reason))),
+                            Err(err) => skipped.push((
+                                hex::encode(raw),
+                                format!("{}: can't decode output address: {:?}", reason, err),
                             )),
-                            ShelleyDelegationPart::Script(script) => {
-                                Some(StakeCredential::from_scripthash(
-                                    &ScriptHash::from_bytes(script.to_vec()).unwrap(),
-                                ))
-                            }
-                            ShelleyDelegationPart::Pointer(_) => {
-                                todo!("not supported")
-                            }
-                        };
-                        (payment_cred, staking_cred)
-                    }
-                    pallas_addresses::Address::Stake(_stake) => {
-                        /* ignore */
-                        continue;
+                        }
                     }
-                };
+                }
+                Err(err) => skipped.push((tx_hash, format!("{}: {:?}", reason, err))),
+            }
+        }
+    }
+
+    for (address, reason) in addresses {
+        let printable = address.to_hex();
+        match address_to_credentials(address, &pointer_resolver) {
+            Ok((payment, staking)) => {
                 let payment_mapping = payment_address_to_num.add_if_not_presented(payment);
                 let staking_mapping =
                     staking.map(|staking| stake_address_to_num.add_if_not_presented(staking));
-                banned_addresses.insert((payment_mapping, staking_mapping));
-            }
-
-            Err(err) => {
-                tracing::error!("can't parse address: {:?}, addr={:?}", err, address);
+                ban_reasons
+                    .entry((payment_mapping, staking_mapping))
+                    .or_default()
+                    .push(reason);
             }
+            Err(err) => skipped.push((printable, format!("{}: {}", reason, err))),
         }
     }
 
+    let fetcher_bans = banned_addresses.clone();
+    banned_addresses.extend(ban_reasons.keys().cloned());
+    tracing::info!(
+        "Banned addresses: {:?} new, {:?} total, skipped: {:?}",
+        banned_addresses.len() - fetcher_bans.len(),
+        banned_addresses.len(),
+        skipped.len()
+    );
+
     tracing::info!("Parsing finished, dumping files");
 
     payment_address_to_num.dump_to_file(config.payment_creds_mapping_output)?;
@@ -156,7 +213,7 @@ async fn _main() -> anyhow::Result<()> {
 
     tracing::info!("Dumping finished, cleaning events");
 
-    clean_events(
+    let stats = clean_events(
         config.events_path,
         config.cleaned_events_output_path,
         &banned_addresses,
@@ -164,5 +221,111 @@ async fn _main() -> anyhow::Result<()> {
 
     tracing::info!("Cleaning finished");
 
+    if let Some(path) = config.report_path {
+        write_report(
+            path,
+            &banned_addresses,
+            &fetcher_bans,
+            &ban_reasons,
+            &skipped,
+            &stats,
+        )?;
+        tracing::info!("Report written");
+    }
+
+    Ok(())
+}
+
+// payment and staking credentials of the shelley address, error describes why it can't be banned
+fn address_to_credentials(
+    address: pallas_addresses::Address,
+    pointer_resolver: &PointerResolver,
+) -> Result<(StakeCredential, Option<StakeCredential>), String> {
+    let shelley = match address {
+        pallas_addresses::Address::Shelley(shelley) => shelley,
+        pallas_addresses::Address::Byron(_) => return Err("byron address".to_string()),
+        pallas_addresses::Address::Stake(_) => return Err("stake address".to_string()),
+    };
+    let payment_cred = match shelley.payment() {
+        ShelleyPaymentPart::Key(key) => {
+            StakeCredential::from_keyhash(&Ed25519KeyHash::from_bytes(key.to_vec()).unwrap())
+        }
+        ShelleyPaymentPart::Script(script) => {
+            StakeCredential::from_scripthash(&ScriptHash::from_bytes(script.to_vec()).unwrap())
+        }
+    };
+    let staking_cred: Option<StakeCredential> = match shelley.delegation() {
+        ShelleyDelegationPart::Null => None,
+        ShelleyDelegationPart::Key(key) => Some(StakeCredential::from_keyhash(
+            &Ed25519KeyHash::from_bytes(key.to_vec()).unwrap(),
+        )),
+        ShelleyDelegationPart::Script(script) => Some(StakeCredential::from_scripthash(
+            &ScriptHash::from_bytes(script.to_vec()).unwrap(),
+        )),
+        ShelleyDelegationPart::Pointer(pointer) => {
+            match pointer_resolver.get((pointer.slot(), pointer.tx_idx(), pointer.cert_idx())) {
+                Some(staking) => Some(staking),
+                None => {
+                    // such outputs are not attributed to any wallet while fetching
+                    tracing::warn!("can't resolve pointer: {:?}", pointer);
+                    return Err(format!("can't resolve pointer: {:?}", pointer));
+                }
+            }
+        }
+    };
+    Ok((payment_cred, staking_cred))
+}
+
+fn write_report(
+    path: PathBuf,
+    banned_addresses: &HashSet<(u64, Option<u64>)>,
+    fetcher_bans: &HashSet<(u64, Option<u64>)>,
+    ban_reasons: &HashMap<(u64, Option<u64>), Vec<String>>,
+    skipped: &[(String, String)],
+    stats: &CleaningStats,
+) -> anyhow::Result<()> {
+    let mut report = File::create(path)?;
+    writeln!(report, "banned addresses: {}", banned_addresses.len())?;
+    let mut addresses: Vec<&(u64, Option<u64>)> = banned_addresses.iter().collect();
+    addresses.sort();
+    for address in addresses {
+        let mut reasons = ban_reasons.get(address).cloned().unwrap_or_default();
+        if fetcher_bans.contains(address) {
+            reasons.insert(0, "banned while fetching".to_string());
+        }
+        writeln!(
+            report,
+            "{:?}: affected events: {}, {}",
+            address,
+            stats
+                .affected_by_address
+                .get(address)
+                .cloned()
+                .unwrap_or_default(),
+            reasons.join("; ")
+        )?;
+    }
+
+    writeln!(report)?;
+    writeln!(report, "not banned addresses: {}", skipped.len())?;
+    for (address, reason) in skipped {
+        writeln!(report, "{}: {}", address, reason)?;
+    }
+
+    writeln!(report)?;
+    writeln!(report, "events: {}", stats.total)?;
+    writeln!(report, "unchanged: {}", stats.unchanged)?;
+    writeln!(
+        report,
+        "converted to partial: {}",
+        stats.converted_to_partial
+    )?;
+    writeln!(report, "with hidden outputs: {}", stats.outputs_hidden)?;
+    writeln!(
+        report,
+        "partial with removed outputs: {}",
+        stats.partial_outputs_removed
+    )?;
+    writeln!(report, "dropped: {}", stats.dropped)?;
     Ok(())
 }
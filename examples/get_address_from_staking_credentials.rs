@@ -1,4 +1,5 @@
 use clap::Parser;
+use utxo_selection_benchmark::network::NetworkProfile;
 
 #[derive(Parser, Debug)]
 #[clap(version)]
@@ -8,12 +9,17 @@ pub struct Cli {
 
     #[clap(long, value_parser)]
     staking_key: String,
+
+    /// mainnet, preprod or preview
+    #[clap(long, value_parser, default_value = "mainnet")]
+    network: NetworkProfile,
 }
 
 fn main() {
     let Cli {
         payment_key,
         staking_key,
+        network,
     } = Cli::parse();
 
     let payment_key = hex::decode(payment_key).unwrap();
@@ -24,9 +30,18 @@ fn main() {
     let staking_key =
         cardano_multiplatform_lib::address::StakeCredential::from_bytes(staking_key).unwrap();
 
-    let addr = cardano_multiplatform_lib::address::BaseAddress::new(1, &payment_key, &staking_key);
-
-    println!("{}", addr.to_address().to_bech32(None).unwrap());
+    let addr = cardano_multiplatform_lib::address::BaseAddress::new(
+        network.network_id(),
+        &payment_key,
+        &staking_key,
+    );
+
+    println!(
+        "{}",
+        addr.to_address()
+            .to_bech32(Some(network.address_prefix()))
+            .unwrap()
+    );
 }
 
 // pk 8200581c9566a8f301fb8a046e44557bb38dfb9080a1213f17f200dcd3808169
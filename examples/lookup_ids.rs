@@ -0,0 +1,144 @@
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use dcspark_core::Address;
+use serde::Deserialize;
+use std::fs::File;
+use std::path::PathBuf;
+use utxo_selection_benchmark::bench_utils::address_mapper::{
+    readable_asset_name, AddressIds, CardanoAddressMapper, CardanoDataMapper,
+};
+use utxo_selection_benchmark::network::NetworkProfile;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    payment_key_path: PathBuf,
+    staking_key_path: PathBuf,
+    policy_id_path: PathBuf,
+    asset_name_path: PathBuf,
+    #[serde(default)]
+    byron_address_path: Option<PathBuf>,
+    #[serde(default)]
+    network: NetworkProfile,
+}
+
+#[derive(Parser, Debug)]
+#[clap(version)]
+pub struct Cli {
+    /// path to config file
+    #[clap(long, value_parser)]
+    config_path: PathBuf,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// ids of a bech32 base, enterprise or stake address (or of a byron address)
+    Address {
+        #[clap(value_parser)]
+        address: String,
+    },
+    /// ids of a hex encoded policy id and asset name
+    Asset {
+        #[clap(value_parser)]
+        policy_id: String,
+        #[clap(value_parser)]
+        asset_name: String,
+    },
+    /// address of the payment key id and optional staking key id
+    PaymentKey {
+        #[clap(value_parser)]
+        id: u64,
+        #[clap(long, value_parser)]
+        staking_key: Option<u64>,
+    },
+    /// stake address of the staking key id
+    StakingKey {
+        #[clap(value_parser)]
+        id: u64,
+    },
+    /// policy id and asset name of the ids
+    AssetIds {
+        #[clap(value_parser)]
+        policy_id: u64,
+        #[clap(value_parser)]
+        asset_name: u64,
+    },
+}
+
+fn main() {
+    let result = _main();
+    result.unwrap();
+}
+
+fn _main() -> anyhow::Result<()> {
+    let Cli {
+        config_path,
+        command,
+    } = Cli::parse();
+
+    let file = File::open(&config_path).with_context(|| {
+        format!(
+            "Cannot read config file {path}",
+            path = config_path.display()
+        )
+    })?;
+    let config: Config = serde_yaml::from_reader(file).with_context(|| {
+        format!(
+            "Cannot read config file {path}",
+            path = config_path.display()
+        )
+    })?;
+
+    let mut mapper = CardanoAddressMapper::new(
+        config.payment_key_path,
+        config.staking_key_path,
+        config.policy_id_path,
+        config.asset_name_path,
+        config.byron_address_path,
+        None,
+        config.network,
+        Address::new(""),
+    )?;
+
+    match command {
+        Command::Address { address } => match mapper.lookup_address(&address)? {
+            AddressIds::Address((payment_key, Some(staking_key))) => {
+                println!("payment key: {payment_key}, staking key: {staking_key}")
+            }
+            AddressIds::Address((payment_key, None)) => {
+                println!("payment key: {payment_key}, no staking key")
+            }
+            AddressIds::StakingKey(staking_key) => println!("staking key: {staking_key}"),
+        },
+        Command::Asset {
+            policy_id,
+            asset_name,
+        } => {
+            let (policy_id, asset_name) = mapper.lookup_asset(&policy_id, &asset_name)?;
+            println!("policy id: {policy_id}, asset name: {asset_name}");
+        }
+        Command::PaymentKey { id, staking_key } => {
+            println!("{}", mapper.address_by_ids((id, staking_key))?);
+        }
+        Command::StakingKey { id } => {
+            println!("{}", mapper.stake_address_by_id(id)?);
+        }
+        Command::AssetIds {
+            policy_id,
+            asset_name,
+        } => {
+            let fingerprint = mapper.map_policy_id_and_asset_indices(policy_id, asset_name)?;
+            let (policy_id, asset_name) = mapper.asset_by_ids(policy_id, asset_name)?;
+            println!(
+                "policy id: {policy_id}, asset name: {asset_name} ({}), fingerprint: {}",
+                readable_asset_name(&asset_name).unwrap_or_default(),
+                fingerprint.as_ref()
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use anyhow::{anyhow, Context};
+use cardano_multiplatform_lib::address::StakeCredential;
+use cardano_multiplatform_lib::PolicyID;
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use utxo_selection_benchmark::mapper::DataMapper;
+use utxo_selection_benchmark::remapping::DatasetRemapping;
+use utxo_selection_benchmark::tx_event::TxEvent;
+use utxo_selection_benchmark::utils::{dump_hashset_to_file, read_hashset_from_file};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MappingPaths {
+    payment_creds_mapping: PathBuf,
+    staking_creds_mapping: PathBuf,
+    policy_mapping: PathBuf,
+    asset_name_mapping: PathBuf,
+    #[serde(default)]
+    byron_address_mapping: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // ids of the base dataset are kept in the merged mappings
+    base: MappingPaths,
+    // the dataset which is rewritten into the merged ids
+    other: MappingPaths,
+    merged: MappingPaths,
+
+    events_path: PathBuf,
+    remapped_events_path: PathBuf,
+    #[serde(default)]
+    banned_addresses: Option<PathBuf>,
+    #[serde(default)]
+    remapped_banned_addresses: Option<PathBuf>,
+    // staking keys of the other dataset, printed with the merged ids
+    #[serde(default)]
+    keys_of_interest: Vec<u64>,
+}
+
+#[derive(Parser, Debug)]
+#[clap(version)]
+pub struct Cli {
+    /// path to config file
+    #[clap(long, value_parser)]
+    config_path: PathBuf,
+}
+
+fn main() {
+    let result = _main();
+    result.unwrap();
+}
+
+fn _main() -> anyhow::Result<()> {
+    // Start logging setup block
+    let fmt_layer = tracing_subscriber::fmt::layer().with_test_writer();
+
+    tracing_subscriber::registry().with(fmt_layer).init();
+
+    let Cli { config_path } = Cli::parse();
+
+    tracing::info!("Config file {:?}", config_path);
+    let file = File::open(&config_path).with_context(|| {
+        format!(
+            "Cannot read config file {path}",
+            path = config_path.display()
+        )
+    })?;
+    let config: Config = serde_yaml::from_reader(file).with_context(|| {
+        format!(
+            "Cannot read config file {path}",
+            path = config_path.display()
+        )
+    })?;
+
+    tracing::info!("loading mappings");
+
+    let mut payment_address_to_num =
+        DataMapper::<StakeCredential>::load_from_file(config.base.payment_creds_mapping)?;
+    let mut stake_address_to_num =
+        DataMapper::<StakeCredential>::load_from_file(config.base.staking_creds_mapping)?;
+    let mut policy_id_to_num = DataMapper::<PolicyID>::load_from_file(config.base.policy_mapping)?;
+    let mut asset_name_to_num =
+        DataMapper::<String>::load_from_file(config.base.asset_name_mapping)?;
+    let mut byron_address_to_num = match config.base.byron_address_mapping {
+        Some(path) => DataMapper::<String>::load_from_file(path)?,
+        None => DataMapper::<String>::new(),
+    };
+
+    let remapping = DatasetRemapping {
+        payment: payment_address_to_num.merge(&DataMapper::load_from_file(
+            config.other.payment_creds_mapping,
+        )?),
+        staking: stake_address_to_num.merge(&DataMapper::load_from_file(
+            config.other.staking_creds_mapping,
+        )?),
+        policy: policy_id_to_num.merge(&DataMapper::load_from_file(config.other.policy_mapping)?),
+        asset_name: asset_name_to_num.merge(&DataMapper::load_from_file(
+            config.other.asset_name_mapping,
+        )?),
+        byron: match config.other.byron_address_mapping {
+            Some(path) => Some(byron_address_to_num.merge(&DataMapper::load_from_file(path)?)),
+            None => None,
+        },
+    };
+
+    tracing::info!(
+        "Merged mappings: payment keys: {:?}, staking keys: {:?}, policies: {:?}, asset names: {:?}",
+        payment_address_to_num.len(),
+        stake_address_to_num.len(),
+        policy_id_to_num.len(),
+        asset_name_to_num.len()
+    );
+
+    payment_address_to_num.dump_to_file(config.merged.payment_creds_mapping)?;
+    stake_address_to_num.dump_to_file(config.merged.staking_creds_mapping)?;
+    policy_id_to_num.dump_to_file(config.merged.policy_mapping)?;
+    asset_name_to_num.dump_to_file(config.merged.asset_name_mapping)?;
+    match config.merged.byron_address_mapping {
+        Some(path) => byron_address_to_num.dump_to_file(path)?,
+        None if !byron_address_to_num.is_empty() => {
+            return Err(anyhow!(
+                "merged byron_address_mapping is required to keep byron addresses"
+            ))
+        }
+        None => {}
+    }
+
+    tracing::info!("Mappings dumped, remapping events");
+
+    let mut out_file = File::create(config.remapped_events_path)?;
+    for (num, line) in BufReader::new(File::open(config.events_path)?)
+        .lines()
+        .enumerate()
+    {
+        let event: TxEvent = serde_json::from_str(line?.as_str())?;
+        let event = remapping
+            .remap_event(event)
+            .with_context(|| format!("event at line {}", num + 1))?;
+        out_file.write_all(format!("{}\n", serde_json::to_string(&event)?).as_bytes())?;
+        if num % 100000 == 0 {
+            tracing::info!("Processed {:?} entries", num + 1);
+        }
+    }
+    drop(out_file);
+
+    match (config.banned_addresses, config.remapped_banned_addresses) {
+        (Some(input), Some(output)) => {
+            let banned_addresses: HashSet<(u64, Option<u64>)> = read_hashset_from_file(input)?;
+            dump_hashset_to_file(&remapping.remap_addresses(&banned_addresses)?, output)?;
+        }
+        (None, None) => {}
+        _ => {
+            return Err(anyhow!(
+                "banned_addresses and remapped_banned_addresses must be set together"
+            ))
+        }
+    }
+
+    if !config.keys_of_interest.is_empty() {
+        let keys_of_interest = config
+            .keys_of_interest
+            .iter()
+            .map(|key| remapping.remap_staking_key(*key))
+            .collect::<anyhow::Result<Vec<u64>>>()?;
+        println!("keys_of_interest: {:?}", keys_of_interest);
+    }
+
+    tracing::info!("Remapping finished");
+
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use anyhow::anyhow;
+use clap::Parser;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use utxo_selection_benchmark::plots::{html_report, LineChart, Series, StatsTable};
+
+const LOVELACE_IN_ADA: f64 = 1_000_000.0;
+// file names of `balance_points_path` and `utxos_balance_path` in the bench configs
+const BALANCE_FILE: &str = "ada_balance.csv";
+const UTXO_COUNT_FILE: &str = "utxo_count.csv";
+
+#[derive(Parser, Debug)]
+#[clap(version)]
+pub struct Cli {
+    /// benchmark result directory as name=path, can be repeated to compare algorithms
+    #[clap(long, value_parser)]
+    result: Vec<String>,
+    /// keys to plot, all keys of the first result by default
+    #[clap(long, value_parser)]
+    key: Vec<u64>,
+    /// path to the html report
+    #[clap(long, value_parser)]
+    output: PathBuf,
+}
+
+struct BenchResult {
+    name: String,
+    balance: StatsTable,
+    utxo_count: StatsTable,
+}
+
+fn main() {
+    let result = _main();
+    result.unwrap();
+}
+
+fn _main() -> anyhow::Result<()> {
+    let fmt_layer = tracing_subscriber::fmt::layer().with_test_writer();
+
+    tracing_subscriber::registry().with(fmt_layer).init();
+
+    let Cli {
+        result,
+        key,
+        output,
+    } = Cli::parse();
+
+    let mut results = vec![];
+    for result in result.iter() {
+        let (name, dir) = result
+            .split_once('=')
+            .ok_or_else(|| anyhow!("result should be name=path: {}", result))?;
+        let dir = PathBuf::from(dir);
+        tracing::info!("loading {} from {:?}", name, dir);
+        results.push(BenchResult {
+            name: name.to_string(),
+            balance: StatsTable::load(dir.join(BALANCE_FILE))?,
+            utxo_count: StatsTable::load(dir.join(UTXO_COUNT_FILE))?,
+        });
+    }
+    let first = results
+        .first()
+        .ok_or_else(|| anyhow!("at least one result is required"))?;
+
+    let keys: BTreeSet<u64> = if key.is_empty() {
+        first.balance.points.keys().cloned().collect()
+    } else {
+        key.into_iter().collect()
+    };
+
+    let mut sections = vec![];
+    for key in keys.into_iter() {
+        // the actual history is the same for every algorithm
+        let actual_balance = first.balance.series(key, "ada_actual", LOVELACE_IN_ADA)?;
+        let actual_fee = first.balance.series(key, "fee_actual", LOVELACE_IN_ADA)?;
+
+        let mut utxos = vec![];
+        let mut balances = vec![Series::new("actual", actual_balance.clone())];
+        let mut fees = vec![Series::new("actual", actual_fee)];
+        let mut differences = vec![];
+        for result in results.iter() {
+            let computed = result
+                .balance
+                .series(key, "ada_computed", LOVELACE_IN_ADA)?;
+            utxos.push(Series::new(
+                result.name.clone(),
+                result.utxo_count.series(key, "utxo_count", 1.0)?,
+            ));
+            differences.push(Series::difference(
+                format!("{} - actual", result.name),
+                &computed,
+                &actual_balance,
+            ));
+            balances.push(Series::new(result.name.clone(), computed));
+            fees.push(Series::new(
+                result.name.clone(),
+                result
+                    .balance
+                    .series(key, "fee_computed", LOVELACE_IN_ADA)?,
+            ));
+        }
+
+        let chart = |title: &str, y_label: &str, series: Vec<Series>| LineChart {
+            title: title.to_string(),
+            x_label: "# of transactions".to_string(),
+            y_label: y_label.to_string(),
+            series,
+        };
+        sections.push((
+            format!("staking key {}", key),
+            vec![
+                chart("UTxO count", "# of utxos", utxos),
+                chart("ADA balance: computed and actual", "ada", balances),
+                chart("ADA balance: computed - actual", "ada", differences),
+                chart("Cumulative fees", "ada", fees),
+            ],
+        ));
+    }
+
+    std::fs::write(&output, html_report("UTxO selection benchmark", &sections))?;
+    tracing::info!("Report written to {:?}", output);
+
+    Ok(())
+}
@@ -1,13 +1,10 @@
 use anyhow::anyhow;
 use clap::Parser;
-use entity::prelude::TransactionModel;
 
-use std::fs::File;
-use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
-use std::str::FromStr;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
+use utxo_selection_benchmark::generation_utils::read_unparsed_transactions_from_file;
 
 #[derive(Parser, Debug)]
 #[clap(version)]
@@ -30,21 +27,18 @@ async fn _main() -> anyhow::Result<()> {
 
     let Cli { file_path } = Cli::parse();
 
-    let unparsed_txs_file = if file_path.exists() && file_path.is_file() {
-        File::open(file_path)?
-    } else {
+    if !file_path.is_file() {
         return Err(anyhow!("can't open input file: {:?}", file_path));
-    };
-
-    let mut unparsed_addresses_file_lines = BufReader::new(unparsed_txs_file).lines();
-    let count = u64::from_str(unparsed_addresses_file_lines.next().unwrap()?.as_str())?;
-    let mut seen = 0;
-    for line in unparsed_addresses_file_lines {
-        let tx: TransactionModel = serde_json::from_str(line?.as_str())?;
-        println!("hash: {}", hex::encode(tx.hash.clone()));
-        seen += 1;
     }
 
-    assert_eq!(seen, count);
+    for unparsed in read_unparsed_transactions_from_file(file_path)? {
+        println!(
+            "hash: {} reason: {:?} error: {}",
+            hex::encode(unparsed.tx.hash.clone()),
+            unparsed.reason,
+            unparsed.error
+        );
+    }
+
     Ok(())
 }
@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Context};
+use cardano_multiplatform_lib::address::StakeCredential;
+use cardano_multiplatform_lib::PolicyID;
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use utxo_selection_benchmark::generation_utils::{
+    carp_tx_to_events, clean_events, dump_previous_outputs_to_file,
+    dump_unparsed_transactions_to_file, read_previous_outputs_from_file,
+    read_unparsed_transactions_from_file, PointerResolver, UnparsedTransaction,
+};
+use utxo_selection_benchmark::mapper::DataMapper;
+use utxo_selection_benchmark::utils::{dump_hashset_to_file, read_hashset_from_file};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    unparsed_transactions: PathBuf,
+    // transactions that failed again
+    remaining_unparsed_transactions: PathBuf,
+    previous_outputs: PathBuf,
+
+    payment_creds_mapping: PathBuf,
+    staking_creds_mapping: PathBuf,
+    policy_mapping: PathBuf,
+    asset_name_mapping: PathBuf,
+    banned_addresses: PathBuf,
+    #[serde(default)]
+    byron_address_mapping: Option<PathBuf>,
+    #[serde(default)]
+    pointers_mapping: Option<PathBuf>,
+
+    // new events are appended to the end of the file
+    events_output_path: PathBuf,
+    #[serde(default)]
+    cleaned_events_output_path: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+#[clap(version)]
+pub struct Cli {
+    /// path to config file
+    #[clap(long, value_parser)]
+    config_path: PathBuf,
+}
+
+#[tokio::main]
+async fn main() {
+    let result = _main().await;
+    result.unwrap();
+}
+
+async fn _main() -> anyhow::Result<()> {
+    // Start logging setup block
+    let fmt_layer = tracing_subscriber::fmt::layer().with_test_writer();
+
+    tracing_subscriber::registry().with(fmt_layer).init();
+
+    let Cli { config_path } = Cli::parse();
+
+    tracing::info!("Config file {:?}", config_path);
+    let file = File::open(&config_path).with_context(|| {
+        format!(
+            "Cannot read config file {path}",
+            path = config_path.display()
+        )
+    })?;
+    let config: Config = serde_yaml::from_reader(file).with_context(|| {
+        format!(
+            "Cannot read config file {path}",
+            path = config_path.display()
+        )
+    })?;
+
+    if !config.events_output_path.is_file() {
+        return Err(anyhow!(
+            "can't open events file: {:?}",
+            config.events_output_path
+        ));
+    }
+
+    tracing::info!("loading mappings");
+
+    let mut stake_address_to_num =
+        DataMapper::<StakeCredential>::load_from_file(config.staking_creds_mapping.clone())?;
+    let mut payment_address_to_num =
+        DataMapper::<StakeCredential>::load_from_file(config.payment_creds_mapping.clone())?;
+    let mut policy_id_to_num =
+        DataMapper::<PolicyID>::load_from_file(config.policy_mapping.clone())?;
+    let mut asset_name_to_num =
+        DataMapper::<String>::load_from_file(config.asset_name_mapping.clone())?;
+    let mut byron_address_to_num = match config.byron_address_mapping.clone() {
+        Some(path) => Some(DataMapper::<String>::load_from_file(path)?),
+        None => None,
+    };
+    // block slots are unknown here, so only the registrations of the fetch are used
+    let mut pointer_resolver = match config.pointers_mapping.clone() {
+        Some(path) => PointerResolver::load_from_file(path)?,
+        None => PointerResolver::default(),
+    };
+    let mut banned_addresses: HashSet<(u64, Option<u64>)> =
+        read_hashset_from_file(config.banned_addresses.clone())?;
+    let mut previous_outputs = read_previous_outputs_from_file(config.previous_outputs.clone())?;
+
+    let mut unparsed = read_unparsed_transactions_from_file(config.unparsed_transactions)?;
+    // outputs of the earlier transactions are registered before the later ones spend them
+    unparsed.sort_by_key(|unparsed| unparsed.tx.id);
+
+    tracing::info!(
+        "successfully loaded mappings, retrying {:?} transactions",
+        unparsed.len()
+    );
+
+    let mut out_file = OpenOptions::new()
+        .append(true)
+        .open(config.events_output_path.clone())?;
+
+    let mut remaining = Vec::<UnparsedTransaction>::new();
+    let mut total_events = 0;
+    for unparsed in unparsed.into_iter() {
+        let tx_event = carp_tx_to_events(
+            &unparsed.tx,
+            &mut previous_outputs,
+            &mut stake_address_to_num,
+            &mut payment_address_to_num,
+            &mut policy_id_to_num,
+            &mut asset_name_to_num,
+            byron_address_to_num.as_mut(),
+            &mut pointer_resolver,
+            &mut banned_addresses,
+            &mut remaining,
+        )?;
+        if let Some(tx_event) = tx_event {
+            out_file.write_all(format!("{}\n", serde_json::to_string(&tx_event)?).as_bytes())?;
+            total_events += 1;
+        }
+    }
+
+    drop(out_file);
+
+    tracing::info!(
+        "Retry finished, new events: {:?}, still unparsed: {:?}",
+        total_events,
+        remaining.len()
+    );
+
+    dump_unparsed_transactions_to_file(config.remaining_unparsed_transactions, remaining)?;
+
+    payment_address_to_num.dump_to_file(config.payment_creds_mapping)?;
+    stake_address_to_num.dump_to_file(config.staking_creds_mapping)?;
+    policy_id_to_num.dump_to_file(config.policy_mapping)?;
+    asset_name_to_num.dump_to_file(config.asset_name_mapping)?;
+    if let (Some(mapper), Some(path)) = (byron_address_to_num, config.byron_address_mapping) {
+        mapper.dump_to_file(path)?;
+    }
+    dump_hashset_to_file(&banned_addresses, config.banned_addresses)?;
+    dump_previous_outputs_to_file(&previous_outputs, config.previous_outputs)?;
+
+    tracing::info!("Dumping finished");
+
+    if let Some(cleaned_events_output_path) = config.cleaned_events_output_path {
+        clean_events(
+            config.events_output_path,
+            cleaned_events_output_path,
+            &banned_addresses,
+        )?;
+        tracing::info!("Cleaning finished");
+    }
+
+    Ok(())
+}
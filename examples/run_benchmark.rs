@@ -4,15 +4,13 @@ use anyhow::{anyhow, Context};
 
 use clap::Parser;
 
-use cardano_multiplatform_lib::builders::tx_builder::TransactionBuilderConfigBuilder;
 use cardano_multiplatform_lib::builders::witness_builder::NativeScriptWitnessInfo;
-use cardano_multiplatform_lib::ledger::alonzo::fees::LinearFee;
 use cardano_multiplatform_lib::ledger::common::value::BigNum;
 use cardano_multiplatform_lib::metadata::{
     AuxiliaryData, GeneralTransactionMetadata, TransactionMetadatum,
 };
-use cardano_multiplatform_lib::plutus::{ExUnitPrices, PlutusData};
-use cardano_multiplatform_lib::{RequiredSigners, UnitInterval};
+use cardano_multiplatform_lib::plutus::PlutusData;
+use cardano_multiplatform_lib::RequiredSigners;
 use dcspark_core::multisig_plan::MultisigPlan;
 use dcspark_core::network_id::NetworkInfo;
 use dcspark_core::tx::{CardanoPaymentCredentials, UTxOBuilder, UTxODetails};
@@ -28,7 +26,17 @@ use utxo_selection_benchmark::bench::{run_algorithm_benchmark, PathsConfig};
 use utxo_selection_benchmark::bench_utils::address_mapper::{
     CardanoAddressMapper, CardanoDataMapper, StringAddressMapper,
 };
+use utxo_selection_benchmark::bench_utils::batching::BatchingConfig;
+use utxo_selection_benchmark::bench_utils::min_ada::MinAdaValidation;
+use utxo_selection_benchmark::bench_utils::network_parameters::{
+    transaction_builder_config, CardanoNetworkParameters,
+};
 use utxo_selection_benchmark::bench_utils::selection_eligibility::SelectionEligibility;
+use utxo_selection_benchmark::bench_utils::transaction_limits::TransactionLimitsConfig;
+use utxo_selection_benchmark::bench_utils::tx_reconstruction::{
+    TxReconstruction, TxReconstructionConfig,
+};
+use utxo_selection_benchmark::network::NetworkProfile;
 
 use serde::Deserialize;
 
@@ -91,24 +99,6 @@ pub enum FeeEstimatorConfig {
     },
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct CardanoNetworkParameters {
-    coefficient: BigNum,
-    constant: BigNum,
-    pool_deposit: BigNum,
-    key_deposit: BigNum,
-    max_value_size: u32,
-    max_tx_size: u32,
-    coins_per_utxo_byte: BigNum,
-    ex_unit_mem_price_numerator: BigNum,
-    ex_unit_mem_price_denominator: BigNum,
-    ex_unit_step_price_numerator: BigNum,
-    ex_unit_step_price_denominator: BigNum,
-    collateral_percentage: u32,
-    max_collateral_inputs: u32,
-}
-
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
@@ -119,11 +109,20 @@ pub enum DataMapperConfig {
         staking_key_path: PathBuf,
         policy_id_path: PathBuf,
         asset_name_path: PathBuf,
-        network: u8,
+        #[serde(default)]
+        byron_address_path: Option<PathBuf>,
+        network: NetworkProfile,
         default_address: Address,
     },
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MinAdaValidationConfig {
+    #[serde(default)]
+    reject_selection: bool,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
@@ -136,6 +135,19 @@ pub struct Config {
 
     keys_of_interest: Vec<u64>,
     allow_balance_change: bool,
+
+    #[serde(default)]
+    batching: Option<BatchingConfig>,
+
+    #[serde(default)]
+    transaction_limits: Option<TransactionLimitsConfig>,
+
+    #[serde(default)]
+    min_ada_validation: Option<MinAdaValidationConfig>,
+
+    // rebuild the selections with the cml transaction builder, requires the cml estimator and mapper
+    #[serde(default)]
+    transaction_reconstruction: Option<TxReconstructionConfig>,
 }
 
 #[tokio::main]
@@ -216,8 +228,13 @@ pub fn parse_estimator_creator<
             plan_path,
             coins_per_utxo_byte,
         } => {
+            if main_config.transaction_reconstruction.is_some() {
+                return Err(anyhow!(
+                    "transaction reconstruction requires the cml estimator"
+                ));
+            }
             let plan = MultisigPlan::load(plan_path)?;
-            parse_mapper(main_config, algo, change_algo, || {
+            parse_mapper(main_config, algo, change_algo, None, || {
                 Ok(ThermostatFeeEstimator::new(
                     network.clone(),
                     &plan,
@@ -251,50 +268,31 @@ pub fn parse_estimator_creator<
                 }
             };
 
-            parse_mapper(main_config, algo, change_algo, || {
-                let linear_fee = LinearFee::new(&parameters.coefficient, &parameters.constant);
+            let builder_config = transaction_builder_config(&parameters)?;
+            // for the unwrap method we still set the metadata 87 to mark who is the
+            // source of the
+            let auxiliary_data = magic.as_deref().map(magic_auxiliary_data).transpose()?;
+            let tx_reconstruction = main_config
+                .transaction_reconstruction
+                .clone()
+                .map(|config| {
+                    TxReconstruction::new(
+                        config,
+                        builder_config.clone(),
+                        credentials.clone(),
+                        auxiliary_data.clone(),
+                        parameters.max_tx_size as usize,
+                    )
+                })
+                .transpose()?;
 
+            parse_mapper(main_config, algo, change_algo, tx_reconstruction, || {
                 let mut builder =
                     cardano_multiplatform_lib::builders::tx_builder::TransactionBuilder::new(
-                        &TransactionBuilderConfigBuilder::new()
-                            .fee_algo(&linear_fee)
-                            .pool_deposit(&parameters.pool_deposit)
-                            .key_deposit(&parameters.key_deposit)
-                            .max_value_size(parameters.max_value_size)
-                            .max_tx_size(parameters.max_tx_size)
-                            .coins_per_utxo_byte(&parameters.coins_per_utxo_byte)
-                            .ex_unit_prices(&ExUnitPrices::new(
-                                &UnitInterval::new(
-                                    &parameters.ex_unit_mem_price_numerator,
-                                    &parameters.ex_unit_mem_price_denominator,
-                                ),
-                                &UnitInterval::new(
-                                    &parameters.ex_unit_step_price_numerator,
-                                    &parameters.ex_unit_step_price_denominator,
-                                ),
-                            ))
-                            .collateral_percentage(parameters.collateral_percentage)
-                            .max_collateral_inputs(parameters.max_collateral_inputs)
-                            .build()
-                            .map_err(|err| anyhow!("can't build tx builder: {}", err))?,
+                        &builder_config,
                     );
-
-                if let Some(magic) = &magic {
-                    // for the unwrap method we still set the metadata 87 to mark who is the
-                    // source of the
-                    let auxiliary_data = {
-                        let mut auxiliary_data = AuxiliaryData::new();
-                        let mut metadata = GeneralTransactionMetadata::new();
-                        metadata.insert(
-                            &BigNum::from_str("87").expect("87 should read as a bignum"),
-                            &TransactionMetadatum::new_text(magic.clone()).map_err(|error| {
-                                anyhow::anyhow!("Failed to encode the magic metadata: {}", error)
-                            })?,
-                        );
-                        auxiliary_data.set_metadata(&metadata);
-                        auxiliary_data
-                    };
-                    builder.set_auxiliary_data(&auxiliary_data);
+                if let Some(auxiliary_data) = &auxiliary_data {
+                    builder.set_auxiliary_data(auxiliary_data);
                 }
 
                 CmlFeeEstimator::new(
@@ -308,6 +306,18 @@ pub fn parse_estimator_creator<
     }
 }
 
+fn magic_auxiliary_data(magic: &str) -> anyhow::Result<AuxiliaryData> {
+    let mut auxiliary_data = AuxiliaryData::new();
+    let mut metadata = GeneralTransactionMetadata::new();
+    metadata.insert(
+        &BigNum::from_str("87").expect("87 should read as a bignum"),
+        &TransactionMetadatum::new_text(magic.to_string())
+            .map_err(|error| anyhow::anyhow!("Failed to encode the magic metadata: {}", error))?,
+    );
+    auxiliary_data.set_metadata(&metadata);
+    Ok(auxiliary_data)
+}
+
 pub fn parse_mapper<
     Estimator: TransactionFeeEstimator<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder>,
     Algo: InputSelectionAlgorithm<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder> + UTxOStoreSupport,
@@ -317,6 +327,7 @@ pub fn parse_mapper<
     main_config: Config,
     algo: Algo,
     change_algo: ChangeAlgo,
+    tx_reconstruction: Option<TxReconstruction>,
     estimator_creator: EstimatorCreator,
 ) -> anyhow::Result<()>
 where
@@ -324,12 +335,18 @@ where
 {
     match main_config.mapper.clone() {
         DataMapperConfig::StringMapper => {
+            if tx_reconstruction.is_some() {
+                return Err(anyhow!(
+                    "transaction reconstruction requires the cml mapper"
+                ));
+            }
             run_bench::<Estimator, Algo, ChangeAlgo, EstimatorCreator, StringAddressMapper>(
                 main_config,
                 algo,
                 change_algo,
                 estimator_creator,
                 StringAddressMapper::default(),
+                None,
             )
         }
         DataMapperConfig::CmlMapper {
@@ -337,22 +354,29 @@ where
             staking_key_path,
             policy_id_path,
             asset_name_path,
+            byron_address_path,
             network,
             default_address,
-        } => run_bench::<Estimator, Algo, ChangeAlgo, EstimatorCreator, CardanoAddressMapper>(
-            main_config,
-            algo,
-            change_algo,
-            estimator_creator,
-            CardanoAddressMapper::new(
+        } => {
+            let mapper = CardanoAddressMapper::new(
                 payment_key_path,
                 staking_key_path,
                 policy_id_path,
                 asset_name_path,
+                byron_address_path,
+                Some(main_config.paths.events_path()),
                 network,
                 default_address,
-            )?,
-        ),
+            )?;
+            run_bench::<Estimator, Algo, ChangeAlgo, EstimatorCreator, CardanoAddressMapper>(
+                main_config,
+                algo,
+                change_algo,
+                estimator_creator,
+                mapper,
+                tx_reconstruction,
+            )
+        }
     }
 }
 
@@ -368,10 +392,40 @@ pub fn run_bench<
     change_algo: ChangeAlgo,
     estimator_creator: EstimatorCreator,
     data_mapper: DataMapper,
+    tx_reconstruction: Option<TxReconstruction>,
 ) -> anyhow::Result<()>
 where
     EstimatorCreator: Fn() -> anyhow::Result<Estimator>,
 {
+    let coins_per_utxo_byte = match &main_config.fee_estimator {
+        FeeEstimatorConfig::Thermostat {
+            coins_per_utxo_byte,
+            ..
+        } => *coins_per_utxo_byte,
+        FeeEstimatorConfig::CmlEstimator { parameters, .. } => parameters.coins_per_utxo_byte,
+    };
+    // cml needs the real addresses to build the outputs
+    let use_cml = matches!(
+        main_config.fee_estimator,
+        FeeEstimatorConfig::CmlEstimator { .. }
+    ) && matches!(main_config.mapper, DataMapperConfig::CmlMapper { .. });
+    let min_ada_validation = main_config
+        .min_ada_validation
+        .map(|validation| MinAdaValidation {
+            coins_per_utxo_byte: u64::from(coins_per_utxo_byte),
+            reject_selection: validation.reject_selection,
+            use_cml,
+        });
+
+    let network_parameters = match &main_config.fee_estimator {
+        FeeEstimatorConfig::Thermostat { .. } => None,
+        FeeEstimatorConfig::CmlEstimator { parameters, .. } => Some(parameters),
+    };
+    let transaction_limits = main_config
+        .transaction_limits
+        .map(|limits| limits.resolve(network_parameters))
+        .transpose()?;
+
     let mut selection = SelectionEligibility::default();
     if !main_config.keys_of_interest.is_empty() {
         selection.set_staking_keys_of_interest(main_config.keys_of_interest);
@@ -385,5 +439,9 @@ where
         selection,
         main_config.paths,
         main_config.allow_balance_change,
+        main_config.batching,
+        transaction_limits,
+        min_ada_validation,
+        tx_reconstruction,
     )
 }
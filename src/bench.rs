@@ -1,27 +1,44 @@
 use std::cell::RefCell;
+use std::cmp::{max, min};
+use std::fmt;
 use std::path::PathBuf;
 
 use crate::tx_event::{TxEvent, TxOutput};
 
 use dcspark_core::tx::{UTxOBuilder, UTxODetails};
-use dcspark_core::{Balance, Regulated, TokenId};
+use dcspark_core::{Address, Balance, Regulated, TokenId, UTxOStore, Value};
 
 use crate::bench_utils::address_mapper::CardanoDataMapper;
 use crate::bench_utils::balance_accumulator::BalanceAccumulator;
 use crate::bench_utils::balance_verification::verify_io_balance;
+use crate::bench_utils::batching::{BatchedEvent, BatchingConfig, BatchingStats, EventBatcher};
 use crate::bench_utils::change_extraction::extract_changes;
+use crate::bench_utils::fee_accuracy::{FeeAccuracyAccumulator, FeeRecord};
+use crate::bench_utils::min_ada::{MinAdaStats, MinAdaValidation};
 use crate::bench_utils::output_utils::{builders_to_utxo_details, tx_outputs_to_utxo_builders};
-use crate::bench_utils::selection_eligibility::SelectionEligibility;
+use crate::bench_utils::selection_eligibility::{
+    FailureReason, FailureRecord, SelectionEligibility,
+};
+use crate::bench_utils::selection_snapshot::{SelectionSnapshotConfig, SelectionSnapshotWriter};
+use crate::bench_utils::selection_timing::SelectionTiming;
+use crate::bench_utils::transaction_limits::{LimitViolation, TransactionLimits};
+use crate::bench_utils::tx_reconstruction::TxReconstruction;
 use crate::bench_utils::utxo_accumulator::UTxOStoreAccumulator;
+use crate::parquet_export::ParquetRecordWriter;
+use arrow::datatypes::{DataType, Field};
 use serde::Deserialize;
 
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
 use std::rc::Rc;
 use std::str::FromStr;
+use std::time::Instant;
 
-use crate::bench_utils::stats_accumulator::{BalanceStats, StatsAccumulator};
-use crate::utils::balance_to_i64;
+use crate::bench_utils::stats_accumulator::{
+    BalanceStats, StatsAccumulator, StatsRecord, StatsSampling, TokenStats, UtxoCountStats,
+};
+use crate::bench_utils::token_spread::TokenSpreadAccumulator;
+use crate::utils::{balance_to_i64, checked_balance_to_i64, checked_value_to_i64, value_to_u64};
 use utxo_selection::{
     InputOutputSetup, InputSelectionAlgorithm, TransactionFeeEstimator, UTxOStoreSupport,
 };
@@ -45,6 +62,41 @@ pub struct PathsConfig {
 
     #[serde(default)]
     balance_points_path: Option<PathBuf>,
+
+    // per native token balances and utxo counts of the keys of interest
+    #[serde(default)]
+    token_points_path: Option<PathBuf>,
+
+    // which points of utxos_balance_path, balance_points_path and token_points_path are written
+    #[serde(default)]
+    stats_sampling: StatsSampling,
+
+    // fragmentation of native tokens compared to the history
+    #[serde(default)]
+    token_report_path: Option<PathBuf>,
+
+    #[serde(default)]
+    output_failures: Option<PathBuf>,
+
+    #[serde(default)]
+    batching_report_path: Option<PathBuf>,
+
+    #[serde(default)]
+    fee_accuracy_report_path: Option<PathBuf>,
+
+    // stats, failures, fee estimations and final balances are also written there as parquet
+    #[serde(default)]
+    parquet_dir: Option<PathBuf>,
+
+    // inputs of the selections for the microbenchmarks in benches/
+    #[serde(default)]
+    selection_snapshots: Option<SelectionSnapshotConfig>,
+}
+
+impl PathsConfig {
+    pub fn events_path(&self) -> PathBuf {
+        self.events_path.clone()
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -62,6 +114,10 @@ pub fn run_algorithm_benchmark<
     selection_eligibility_criteria: SelectionEligibility,
     paths: PathsConfig,
     allow_balance_change: bool,
+    batching: Option<BatchingConfig>,
+    transaction_limits: Option<TransactionLimits>,
+    min_ada_validation: Option<MinAdaValidation>,
+    mut tx_reconstruction: Option<TxReconstruction>,
 ) -> anyhow::Result<()>
 where
     EstimatorCreator: Fn() -> anyhow::Result<Estimator>,
@@ -75,12 +131,57 @@ where
 
     let input_events = BufReader::new(File::open(paths.events_path.clone())?);
 
-    let mut balance_points_acc = StatsAccumulator::<BalanceStats>::default();
-    let mut utxo_count_acc = StatsAccumulator::<u64>::default();
+    if let Some(dir) = &paths.parquet_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut balance_points_acc = stats_accumulator::<BalanceStats>(
+        paths.balance_points_path.clone(),
+        "ada_balance",
+        &paths,
+    )?;
+    let mut utxo_count_acc = stats_accumulator::<UtxoCountStats>(
+        paths.utxos_balance_path.clone(),
+        "utxo_count",
+        &paths,
+    )?;
+    let mut token_points_acc =
+        stats_accumulator::<TokenStats>(paths.token_points_path.clone(), "token_balance", &paths)?;
+    let mut selection_snapshots = paths
+        .selection_snapshots
+        .clone()
+        .map(SelectionSnapshotWriter::create)
+        .transpose()?;
+    let mut selection_timing = SelectionTiming::default();
+    let mut batching_stats = BatchingStats::default();
+    let mut oversized_transactions: u64 = 0;
+    let mut split_transactions: u64 = 0;
+    let mut min_ada_stats = MinAdaStats::default();
+    let mut fee_accuracy_acc = FeeAccuracyAccumulator::default();
     let mut read: u64 = 0;
 
-    for (tx_number, event_str) in input_events.lines().enumerate() {
-        read += 1;
+    let input_events = input_events.lines().enumerate().map(
+        |(tx_number, event_str)| -> anyhow::Result<(u64, TxEvent)> {
+            Ok((tx_number as u64, serde_json::from_str(&event_str?)?))
+        },
+    );
+    let input_events: Box<dyn Iterator<Item = anyhow::Result<BatchedEvent>>> = match batching {
+        None => Box::new(input_events.map(|event| {
+            event.map(|(tx_number, event)| BatchedEvent {
+                tx_number,
+                event,
+                merged_transactions: 1,
+            })
+        })),
+        Some(config) => Box::new(EventBatcher::new(input_events, config)),
+    };
+
+    for batched_event in input_events {
+        let BatchedEvent {
+            tx_number,
+            event,
+            merged_transactions,
+        } = batched_event?;
+        read = max(read, tx_number + 1);
 
         for stake_key in selection_eligibility_criteria
             .clone()
@@ -91,17 +192,29 @@ where
         {
             collect_stats(
                 stake_key,
-                tx_number as u64,
-                &paths,
+                tx_number,
+                false,
                 &actual_balance_acc,
                 &computed_balance_acc,
                 &utxo_accumulator,
-                &mut balance_points_acc,
-                &mut utxo_count_acc,
-            );
+                &data_mapper,
+                balance_points_acc.as_mut(),
+                utxo_count_acc.as_mut(),
+                token_points_acc.as_mut(),
+            )?;
+        }
+
+        let touched_keys = event.staking_keys();
+        if let Some(acc) = balance_points_acc.as_mut() {
+            acc.touch(touched_keys.clone());
+        }
+        if let Some(acc) = utxo_count_acc.as_mut() {
+            acc.touch(touched_keys.clone());
+        }
+        if let Some(acc) = token_points_acc.as_mut() {
+            acc.touch(touched_keys);
         }
 
-        let event: TxEvent = serde_json::from_str(&event_str?)?;
         match event {
             TxEvent::Full {
                 from: inputs,
@@ -110,17 +223,43 @@ where
             } => {
                 verify_io_balance(&inputs, &outputs, &fee).unwrap(); // if balance is not correct -> data is corrupted
 
+                // merged events are not historical transactions, so there is nothing to compare with
+                if paths.fee_accuracy_report_path.is_some() && merged_transactions == 1 {
+                    match estimate_historical_fee(
+                        &estimator_creator,
+                        tx_number,
+                        &inputs,
+                        &outputs,
+                        &mut data_mapper,
+                    ) {
+                        Ok(estimated) => fee_accuracy_acc.add_estimation(
+                            tx_number,
+                            checked_value_to_i64(&estimated)?,
+                            checked_value_to_i64(&fee)?,
+                        ),
+                        Err(err) => {
+                            tracing::warn!(
+                                "can't estimate historical fee: {}, tx_number: {}",
+                                err,
+                                tx_number
+                            );
+                            fee_accuracy_acc.add_failed_estimation();
+                        }
+                    }
+                }
+
                 actual_balance_acc.reduce_balance_from(&inputs, &mut data_mapper)?;
                 actual_balance_acc.add_balance_from(&outputs, &mut data_mapper)?;
 
                 let should_perform_selection = selection_eligibility_criteria
                     .clone()
                     .borrow_mut()
-                    .should_perform_selection(&inputs);
+                    .should_perform_selection(&inputs, tx_number);
 
                 let (pk, sk) = match should_perform_selection {
                     None => {
                         remove_inputs_from_consideration(
+                            tx_number,
                             inputs,
                             &mut utxo_accumulator,
                             &mut actual_balance_acc,
@@ -128,7 +267,7 @@ where
                             selection_eligibility_criteria.clone(),
                         );
                         add_balances_from_partial_outputs(
-                            tx_number as u64,
+                            tx_number,
                             outputs,
                             &mut utxo_accumulator,
                             &mut computed_balance_acc,
@@ -146,43 +285,70 @@ where
                 // now we have inputs related to only one staking key. we're not insolvent and not discarded
 
                 let parsed_outputs = extract_changes(&outputs, (pk, sk));
+                let (inputs_actual, changes_actual) = (inputs.len(), parsed_outputs.changes.len());
                 let non_change_outputs =
                     tx_outputs_to_utxo_builders(parsed_outputs.fixed_outputs, &mut data_mapper)?;
 
-                let mut estimate = estimator_creator()?;
-
-                for output in non_change_outputs.iter() {
-                    estimate.add_output(output.clone())?;
-                }
-
                 let available_inputs = utxo_accumulator.get_available_inputs(sk);
                 let initial_available_inputs_count = available_inputs.len();
 
                 let change_address = data_mapper.map_address(Some((pk, Some(sk))))?;
-                algorithm.set_available_utxos(available_inputs)?;
-                let first_stage_select_result = algorithm.select_inputs(
-                    &mut estimate,
-                    InputOutputSetup::<UTxODetails, UTxOBuilder>::from_fixed_inputs_and_outputs(
-                        vec![],
-                        non_change_outputs.clone(),
-                        Some(change_address.clone()),
-                    ),
-                );
+                if let Some(snapshots) = selection_snapshots.as_mut() {
+                    snapshots.add(
+                        tx_number,
+                        sk,
+                        &change_address,
+                        &available_inputs,
+                        &non_change_outputs,
+                    )?;
+                }
+                let started = Instant::now();
+                let selection = select_inputs_within_limits(
+                    &mut algorithm,
+                    &mut balance_change_algo,
+                    &estimator_creator,
+                    available_inputs,
+                    non_change_outputs,
+                    &change_address,
+                    allow_balance_change,
+                    transaction_limits.as_ref(),
+                    &mut data_mapper,
+                )?;
+                selection_timing.add(started.elapsed());
 
-                let mut first_stage_select_result = match first_stage_select_result {
-                    Ok(r) => r,
+                let selection = match (selection, &min_ada_validation) {
+                    (Ok(selection), Some(validation)) => validate_min_ada(
+                        selection,
+                        validation,
+                        &mut min_ada_stats,
+                        &mut data_mapper,
+                    )?,
+                    (selection, _) => selection,
+                };
+
+                let selection = match selection {
+                    Ok(selection) => selection,
                     Err(err) => {
                         tracing::error!(
-                            "initial selection didn't converge: {}, tx_number: {}, sk: {}",
+                            "selection failed: {}, tx_number: {}, sk: {}",
                             err,
                             tx_number,
                             sk
                         );
+                        if let SelectionError::Limits(_) = err {
+                            oversized_transactions += 1;
+                        }
                         selection_eligibility_criteria
                             .clone()
                             .borrow_mut()
-                            .mark_key_as_insolvent(sk);
+                            .mark_key_as_insolvent(
+                                sk,
+                                err.reason(),
+                                tx_number,
+                                Some(err.to_string()),
+                            );
                         remove_inputs_from_consideration(
+                            tx_number,
                             inputs,
                             &mut utxo_accumulator,
                             &mut actual_balance_acc,
@@ -190,7 +356,7 @@ where
                             selection_eligibility_criteria.clone(),
                         );
                         add_balances_from_partial_outputs(
-                            tx_number as u64,
+                            tx_number,
                             outputs,
                             &mut utxo_accumulator,
                             &mut computed_balance_acc,
@@ -200,152 +366,70 @@ where
                     }
                 };
 
-                let mut available_inputs = algorithm.get_available_utxos()?;
-
-                let initial_fixed_outputs = first_stage_select_result.fixed_outputs.clone();
-
-                let mut selected_changes = first_stage_select_result.changes.clone();
-                let mut selected_inputs = first_stage_select_result.chosen_inputs.clone();
-                let mut fee_computed = first_stage_select_result.fee.clone();
-
                 assert_eq!(
-                    selected_inputs.len() + available_inputs.len(),
+                    selection.available_inputs.len() + selection.chosen_inputs.len(),
                     initial_available_inputs_count
                 );
 
-                if !first_stage_select_result.are_utxos_balanced() && allow_balance_change {
-                    balance_change_algo.set_available_utxos(available_inputs.clone())?;
-
-                    // now all selected inputs are chosen ones
-                    let mut fixed_inputs = first_stage_select_result.fixed_inputs;
-                    fixed_inputs.append(&mut first_stage_select_result.chosen_inputs);
-
-                    // outputs as well
-                    let mut fixed_outputs = first_stage_select_result.fixed_outputs;
-                    fixed_outputs.append(&mut first_stage_select_result.changes);
-
-                    let second_stage_select_result = balance_change_algo.select_inputs(
-                        &mut estimate,
-                        InputOutputSetup::from_fixed_inputs_and_outputs(
-                            fixed_inputs,
-                            fixed_outputs,
-                            Some(change_address.clone()),
-                        ),
-                    );
+                if selection.transactions > 1 {
+                    split_transactions += 1;
+                }
 
-                    let mut second_stage_select_result = match second_stage_select_result {
-                        Ok(r) if r.are_utxos_balanced() => r,
-                        _ => {
-                            if let Err(err) = second_stage_select_result {
-                                tracing::error!("balance change selection didn't converge: {}, tx_number: {}, sk: {}", err, tx_number, sk);
-                            } else {
-                                tracing::error!("balance change selection didn't converge: utxos are not balanced, tx_number: {}, sk: {}", tx_number, sk);
-                            }
-                            selection_eligibility_criteria
-                                .clone()
-                                .borrow_mut()
-                                .mark_key_as_insolvent(sk);
-                            remove_inputs_from_consideration(
-                                inputs,
-                                &mut utxo_accumulator,
-                                &mut actual_balance_acc,
-                                &mut computed_balance_acc,
-                                selection_eligibility_criteria.clone(),
-                            );
-                            add_balances_from_partial_outputs(
-                                tx_number as u64,
-                                outputs,
-                                &mut utxo_accumulator,
-                                &mut computed_balance_acc,
-                                &mut data_mapper,
-                            )?;
-                            continue;
-                        }
-                    };
-
-                    // changes from first stage + changes from balance + original fixed outputs = all outputs
-                    available_inputs = balance_change_algo.get_available_utxos()?;
-
-                    selected_changes.append(&mut second_stage_select_result.changes);
-                    selected_inputs.append(&mut second_stage_select_result.chosen_inputs);
-
-                    fee_computed = second_stage_select_result.fee;
-                } else if !first_stage_select_result.are_utxos_balanced() {
-                    tracing::error!("initial selection didn't converge and balance change is switched off, tx_number: {}, sk: {}", tx_number, sk);
-                    tracing::error!(
-                        "input balance: {:?}, output balance: {:?}, fee: {:?}",
-                        first_stage_select_result.input_balance,
-                        first_stage_select_result.output_balance,
-                        first_stage_select_result.fee
-                    );
-                    tracing::error!("selected inputs:");
-                    for output in first_stage_select_result.chosen_inputs.iter() {
-                        tracing::error!("selected: {:?}", output);
-                    }
-                    tracing::error!("fixed outputs:");
-                    for output in first_stage_select_result.fixed_outputs.iter() {
-                        tracing::error!("output: {:?}", output);
-                    }
-                    tracing::error!("change outputs:");
-                    for output in first_stage_select_result.changes.iter() {
-                        tracing::error!("change: {:?}", output);
+                let selected_outputs = selection.outputs();
+
+                if let Some(reconstruction) = tx_reconstruction.as_mut() {
+                    if selection.transactions > 1 {
+                        reconstruction.skip_split_selection();
+                    } else {
+                        let estimated_size = transaction_limits
+                            .as_ref()
+                            .map(|limits| {
+                                limits.estimate_tx_size(
+                                    &selection.chosen_inputs,
+                                    &selected_outputs,
+                                    &mut data_mapper,
+                                )
+                            })
+                            .transpose()?;
+                        reconstruction.reconstruct(
+                            tx_number,
+                            &selection.chosen_inputs,
+                            &selected_outputs,
+                            &selection.fee,
+                            estimated_size,
+                        )?;
                     }
-                    selection_eligibility_criteria
-                        .clone()
-                        .borrow_mut()
-                        .mark_key_as_insolvent(sk);
-                    remove_inputs_from_consideration(
-                        inputs,
-                        &mut utxo_accumulator,
-                        &mut actual_balance_acc,
-                        &mut computed_balance_acc,
-                        selection_eligibility_criteria.clone(),
-                    );
-                    add_balances_from_partial_outputs(
-                        tx_number as u64,
-                        outputs,
-                        &mut utxo_accumulator,
-                        &mut computed_balance_acc,
-                        &mut data_mapper,
-                    )?;
-                    continue;
                 }
 
-                assert_eq!(
-                    available_inputs.len() + selected_inputs.len(),
-                    initial_available_inputs_count
-                );
-
-                utxo_accumulator.set_available_inputs(sk, available_inputs);
+                utxo_accumulator.set_available_inputs(sk, selection.available_inputs);
                 utxo_accumulator.add_from_outputs(
-                    builders_to_utxo_details(
-                        tx_number as u64,
-                        initial_fixed_outputs
-                            .iter()
-                            .cloned()
-                            .chain(selected_changes.iter().cloned())
-                            .collect(),
-                    )?,
+                    builders_to_utxo_details(tx_number, selected_outputs.clone())?,
                     &mut data_mapper,
                 )?;
 
                 computed_balance_acc
-                    .reduce_balance_from_utxos(&selected_inputs, &mut data_mapper)?;
-                computed_balance_acc.add_balance_from_builders(
-                    &initial_fixed_outputs
-                        .iter()
-                        .cloned()
-                        .chain(selected_changes.iter().cloned())
-                        .collect::<Vec<_>>(),
-                    &mut data_mapper,
-                )?;
-
-                computed_balance_acc.add_fee_spending(sk, &fee_computed);
+                    .reduce_balance_from_utxos(&selection.chosen_inputs, &mut data_mapper)?;
+                computed_balance_acc
+                    .add_balance_from_builders(&selected_outputs, &mut data_mapper)?;
+
+                computed_balance_acc.add_fee_spending(sk, &selection.fee);
+
+                if merged_transactions > 1 {
+                    batching_stats.add_batch(
+                        merged_transactions,
+                        &fee,
+                        &selection.fee,
+                        inputs_actual,
+                        selection.chosen_inputs.len(),
+                        changes_actual,
+                        selection.changes.len(),
+                    );
+                }
             }
             TxEvent::Partial { to } => {
                 actual_balance_acc.add_balance_from(&to, &mut data_mapper)?;
                 add_balances_from_partial_outputs(
-                    tx_number as u64,
+                    tx_number,
                     to,
                     &mut utxo_accumulator,
                     &mut computed_balance_acc,
@@ -368,13 +452,15 @@ where
         collect_stats(
             stake_key,
             read,
-            &paths,
+            true,
             &actual_balance_acc,
             &computed_balance_acc,
             &utxo_accumulator,
-            &mut balance_points_acc,
-            &mut utxo_count_acc,
-        );
+            &data_mapper,
+            balance_points_acc.as_mut(),
+            utxo_count_acc.as_mut(),
+            token_points_acc.as_mut(),
+        )?;
     }
 
     tracing::info!(
@@ -388,6 +474,14 @@ where
             .borrow()
             .total_insolvent_addresses()
     );
+    let timing = selection_timing.summary();
+    tracing::info!(
+        "Total selection time: {:?}, mean: {:?}",
+        timing.total,
+        timing.mean
+    );
+    tracing::info!("Total oversized transactions: {:?}", oversized_transactions);
+    tracing::info!("Total split transactions: {:?}", split_transactions);
     tracing::info!(
         "Total banned addresses: {:?}",
         selection_eligibility_criteria
@@ -402,78 +496,761 @@ where
     selection_eligibility_criteria
         .borrow_mut()
         .print_insolvent(paths.output_insolvent)?;
+    if let Some(path) = paths.output_failures {
+        selection_eligibility_criteria
+            .borrow_mut()
+            .print_failures(path)?;
+    }
+
+    let token_spread = paths
+        .token_report_path
+        .as_ref()
+        .map(|_| {
+            token_spread(
+                selection_eligibility_criteria
+                    .as_ref()
+                    .borrow()
+                    .get_whitelisted_non_banned(),
+                &actual_balance_acc,
+                &computed_balance_acc,
+                &data_mapper,
+            )
+        })
+        .transpose()?;
+
+    if let Some(path) = parquet_path(&paths, "balances") {
+        write_balances_parquet(
+            path,
+            &actual_balance_acc,
+            &computed_balance_acc,
+            &data_mapper,
+        )?;
+    }
+    if let Some(path) = parquet_path(&paths, "failures") {
+        write_failures_parquet(
+            path,
+            selection_eligibility_criteria.as_ref().borrow().failures(),
+        )?;
+    }
+    if let (Some(path), Some(_)) = (
+        parquet_path(&paths, "fee_accuracy"),
+        &paths.fee_accuracy_report_path,
+    ) {
+        write_fee_accuracy_parquet(path, fee_accuracy_acc.records())?;
+    }
+    if let (Some(path), Some(_)) = (parquet_path(&paths, "utxos"), &paths.utxos_path) {
+        write_utxos_parquet(path, &utxo_accumulator)?;
+    }
 
     print_balances(
         actual_balance_acc,
         computed_balance_acc,
+        &data_mapper,
         paths.output_balance,
-        paths.output_balance_short,
+        paths.output_balance_short.clone(),
     )?;
 
+    let mut output_balance_short = OpenOptions::new()
+        .append(true)
+        .open(paths.output_balance_short)?;
+    output_balance_short
+        .write_all(format!("oversized transactions: {oversized_transactions:?}\n").as_bytes())?;
+    output_balance_short
+        .write_all(format!("split transactions: {split_transactions:?}\n").as_bytes())?;
+    output_balance_short.write_all(selection_timing.to_report().as_bytes())?;
+    if min_ada_validation.is_some() {
+        output_balance_short.write_all(min_ada_stats.to_report().as_bytes())?;
+    }
+    if let Some(token_spread) = &token_spread {
+        output_balance_short.write_all(token_spread.to_report().as_bytes())?;
+    }
+    if let Some(reconstruction) = &tx_reconstruction {
+        let summary = reconstruction.summary();
+        tracing::info!(
+            "Total reconstructed transactions: {:?}, failed: {:?}",
+            summary.reconstructed,
+            summary.failed
+        );
+        output_balance_short.write_all(
+            format!(
+                "reconstructed transactions: {:?}, failed: {:?}, underpaid: {:?}\n",
+                summary.reconstructed, summary.failed, summary.underpaid
+            )
+            .as_bytes(),
+        )?;
+    }
+    for (reason, count) in selection_eligibility_criteria
+        .as_ref()
+        .borrow()
+        .failures_by_reason()
+    {
+        tracing::info!("Total failures {:?}: {:?}", reason, count);
+        output_balance_short.write_all(format!("failures {reason:?}: {count:?}\n").as_bytes())?;
+    }
+
     if let Some(path) = paths.utxos_path {
         utxo_accumulator.print_utxos(path)?;
     }
 
-    if let Some(path) = paths.balance_points_path {
-        balance_points_acc.dump_stats(
-            path,
-            "ada_computed,ada_actual,fee_computed,fee_actual".to_string(),
-        )?;
+    if let Some(acc) = balance_points_acc {
+        acc.finish()?;
+    }
+
+    if let Some(acc) = utxo_count_acc {
+        acc.finish()?;
     }
 
-    if let Some(path) = paths.utxos_balance_path {
-        utxo_count_acc.dump_stats(path, "utxo_count".to_string())?;
+    if let Some(acc) = token_points_acc {
+        acc.finish()?;
+    }
+
+    if let (Some(token_spread), Some(path)) = (token_spread, paths.token_report_path) {
+        token_spread.print_report(path)?;
+    }
+
+    if let Some(path) = paths.batching_report_path {
+        batching_stats.print_report(path)?;
+    }
+
+    if let Some(path) = paths.fee_accuracy_report_path {
+        fee_accuracy_acc.print_report(path)?;
+    }
+
+    if let Some(reconstruction) = tx_reconstruction {
+        reconstruction.print_report()?;
+    }
+
+    if let Some(snapshots) = selection_snapshots {
+        let written = snapshots.finish()?;
+        tracing::info!("Total selection snapshots: {:?}", written);
     }
 
     Ok(())
 }
 
+pub struct Selection {
+    chosen_inputs: Vec<UTxODetails>,
+    fixed_outputs: Vec<UTxOBuilder>,
+    changes: Vec<UTxOBuilder>,
+    fee: Value<Regulated>,
+    available_inputs: UTxOStore,
+    // number of transactions the payment was split into
+    transactions: usize,
+}
+
+impl Selection {
+    fn outputs(&self) -> Vec<UTxOBuilder> {
+        self.fixed_outputs
+            .iter()
+            .cloned()
+            .chain(self.changes.iter().cloned())
+            .collect()
+    }
+
+    fn merge(mut self, mut other: Selection) -> Selection {
+        self.chosen_inputs.append(&mut other.chosen_inputs);
+        self.fixed_outputs.append(&mut other.fixed_outputs);
+        self.changes.append(&mut other.changes);
+        self.fee += &other.fee;
+        self.available_inputs = other.available_inputs;
+        self.transactions += other.transactions;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum SelectionError {
+    InitialSelection(String),
+    BalanceChange(String),
+    BalanceChangeDisabled,
+    Limits(LimitViolation),
+    MinAda { violations: u64 },
+}
+
+impl SelectionError {
+    fn reason(&self) -> FailureReason {
+        match self {
+            SelectionError::InitialSelection(_) => FailureReason::InitialSelection,
+            SelectionError::BalanceChange(_) => FailureReason::BalanceChange,
+            SelectionError::BalanceChangeDisabled => FailureReason::BalanceChangeDisabled,
+            SelectionError::Limits(_) => FailureReason::TransactionLimits,
+            SelectionError::MinAda { .. } => FailureReason::MinAda,
+        }
+    }
+}
+
+impl fmt::Display for SelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectionError::InitialSelection(err) => {
+                write!(f, "initial selection didn't converge: {}", err)
+            }
+            SelectionError::BalanceChange(err) => {
+                write!(f, "balance change selection didn't converge: {}", err)
+            }
+            SelectionError::BalanceChangeDisabled => write!(
+                f,
+                "initial selection didn't converge and balance change is switched off"
+            ),
+            SelectionError::Limits(violation) => write!(f, "{}", violation),
+            SelectionError::MinAda { violations } => {
+                write!(f, "{} changes are below min ada", violations)
+            }
+        }
+    }
+}
+
+// a selection of the benchmark: the algorithm, then the change balancing if it is allowed
+pub fn select_inputs<
+    Estimator: TransactionFeeEstimator<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder>,
+    Algo: InputSelectionAlgorithm<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder> + UTxOStoreSupport,
+    ChangeBalanceAlgo: InputSelectionAlgorithm<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder> + UTxOStoreSupport,
+    EstimatorCreator,
+>(
+    algorithm: &mut Algo,
+    balance_change_algo: &mut ChangeBalanceAlgo,
+    estimator_creator: &EstimatorCreator,
+    available_inputs: UTxOStore,
+    fixed_outputs: Vec<UTxOBuilder>,
+    change_address: &Address,
+    allow_balance_change: bool,
+) -> anyhow::Result<Result<Selection, SelectionError>>
+where
+    EstimatorCreator: Fn() -> anyhow::Result<Estimator>,
+{
+    let mut estimate = estimator_creator()?;
+
+    for output in fixed_outputs.iter() {
+        estimate.add_output(output.clone())?;
+    }
+
+    let initial_available_inputs_count = available_inputs.len();
+
+    algorithm.set_available_utxos(available_inputs)?;
+    let first_stage_select_result = algorithm.select_inputs(
+        &mut estimate,
+        InputOutputSetup::<UTxODetails, UTxOBuilder>::from_fixed_inputs_and_outputs(
+            vec![],
+            fixed_outputs,
+            Some(change_address.clone()),
+        ),
+    );
+
+    let mut first_stage_select_result = match first_stage_select_result {
+        Ok(r) => r,
+        Err(err) => return Ok(Err(SelectionError::InitialSelection(err.to_string()))),
+    };
+
+    let mut available_inputs = algorithm.get_available_utxos()?;
+
+    let initial_fixed_outputs = first_stage_select_result.fixed_outputs.clone();
+
+    let mut selected_changes = first_stage_select_result.changes.clone();
+    let mut selected_inputs = first_stage_select_result.chosen_inputs.clone();
+    let mut fee_computed = first_stage_select_result.fee.clone();
+
+    assert_eq!(
+        selected_inputs.len() + available_inputs.len(),
+        initial_available_inputs_count
+    );
+
+    if !first_stage_select_result.are_utxos_balanced() && allow_balance_change {
+        balance_change_algo.set_available_utxos(available_inputs.clone())?;
+
+        // now all selected inputs are chosen ones
+        let mut fixed_inputs = first_stage_select_result.fixed_inputs;
+        fixed_inputs.append(&mut first_stage_select_result.chosen_inputs);
+
+        // outputs as well
+        let mut fixed_outputs = first_stage_select_result.fixed_outputs;
+        fixed_outputs.append(&mut first_stage_select_result.changes);
+
+        let second_stage_select_result = balance_change_algo.select_inputs(
+            &mut estimate,
+            InputOutputSetup::from_fixed_inputs_and_outputs(
+                fixed_inputs,
+                fixed_outputs,
+                Some(change_address.clone()),
+            ),
+        );
+
+        let mut second_stage_select_result = match second_stage_select_result {
+            Ok(r) if r.are_utxos_balanced() => r,
+            Ok(_) => {
+                return Ok(Err(SelectionError::BalanceChange(
+                    "utxos are not balanced".to_string(),
+                )))
+            }
+            Err(err) => return Ok(Err(SelectionError::BalanceChange(err.to_string()))),
+        };
+
+        // changes from first stage + changes from balance + original fixed outputs = all outputs
+        available_inputs = balance_change_algo.get_available_utxos()?;
+
+        selected_changes.append(&mut second_stage_select_result.changes);
+        selected_inputs.append(&mut second_stage_select_result.chosen_inputs);
+
+        fee_computed = second_stage_select_result.fee;
+    } else if !first_stage_select_result.are_utxos_balanced() {
+        tracing::error!(
+            "input balance: {:?}, output balance: {:?}, fee: {:?}",
+            first_stage_select_result.input_balance,
+            first_stage_select_result.output_balance,
+            first_stage_select_result.fee
+        );
+        tracing::error!("selected inputs:");
+        for output in first_stage_select_result.chosen_inputs.iter() {
+            tracing::error!("selected: {:?}", output);
+        }
+        tracing::error!("fixed outputs:");
+        for output in first_stage_select_result.fixed_outputs.iter() {
+            tracing::error!("output: {:?}", output);
+        }
+        tracing::error!("change outputs:");
+        for output in first_stage_select_result.changes.iter() {
+            tracing::error!("change: {:?}", output);
+        }
+        return Ok(Err(SelectionError::BalanceChangeDisabled));
+    }
+
+    Ok(Ok(Selection {
+        chosen_inputs: selected_inputs,
+        fixed_outputs: initial_fixed_outputs,
+        changes: selected_changes,
+        fee: fee_computed,
+        available_inputs,
+        transactions: 1,
+    }))
+}
+
 #[allow(clippy::too_many_arguments)]
-fn collect_stats(
+fn select_inputs_within_limits<
+    Estimator: TransactionFeeEstimator<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder>,
+    Algo: InputSelectionAlgorithm<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder> + UTxOStoreSupport,
+    ChangeBalanceAlgo: InputSelectionAlgorithm<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder> + UTxOStoreSupport,
+    EstimatorCreator,
+    DataMapper: CardanoDataMapper,
+>(
+    algorithm: &mut Algo,
+    balance_change_algo: &mut ChangeBalanceAlgo,
+    estimator_creator: &EstimatorCreator,
+    available_inputs: UTxOStore,
+    fixed_outputs: Vec<UTxOBuilder>,
+    change_address: &Address,
+    allow_balance_change: bool,
+    limits: Option<&TransactionLimits>,
+    data_mapper: &mut DataMapper,
+) -> anyhow::Result<Result<Selection, SelectionError>>
+where
+    EstimatorCreator: Fn() -> anyhow::Result<Estimator>,
+{
+    let limits = match limits {
+        None => {
+            return select_inputs(
+                algorithm,
+                balance_change_algo,
+                estimator_creator,
+                available_inputs,
+                fixed_outputs,
+                change_address,
+                allow_balance_change,
+            )
+        }
+        Some(limits) => limits,
+    };
+
+    let mut violation = None;
+
+    // first try to pay everything in one transaction, then split the fixed outputs into more parts
+    for parts in 1..=max(1, min(limits.max_splits, fixed_outputs.len())) {
+        let chunks: Vec<Vec<UTxOBuilder>> = if parts == 1 {
+            vec![fixed_outputs.clone()]
+        } else {
+            split_outputs(&fixed_outputs, parts)
+        };
+
+        let mut available_inputs = available_inputs.clone();
+        let mut result: Option<Selection> = None;
+        for chunk in chunks {
+            let selection = match select_inputs(
+                algorithm,
+                balance_change_algo,
+                estimator_creator,
+                available_inputs.clone(),
+                chunk,
+                change_address,
+                allow_balance_change,
+            )? {
+                Ok(selection) => selection,
+                Err(err) => return Ok(Err(violation.map(SelectionError::Limits).unwrap_or(err))),
+            };
+            if let Some(err) =
+                limits.check(&selection.chosen_inputs, &selection.outputs(), data_mapper)?
+            {
+                violation = Some(err);
+                result = None;
+                break;
+            }
+            available_inputs = selection.available_inputs.clone();
+            result = Some(match result {
+                None => selection,
+                Some(result) => result.merge(selection),
+            });
+        }
+
+        match (result, &violation) {
+            (Some(result), _) => return Ok(Ok(result)),
+            (None, Some(LimitViolation::TransactionTooLarge { .. })) => continue,
+            (None, _) => break,
+        }
+    }
+
+    Ok(Err(SelectionError::Limits(violation.expect(
+        "selection is either successful or violates the limits",
+    ))))
+}
+
+// exactly `parts` chunks, their lengths differ by one at most
+fn split_outputs(outputs: &[UTxOBuilder], parts: usize) -> Vec<Vec<UTxOBuilder>> {
+    let (size, rest) = (outputs.len() / parts, outputs.len() % parts);
+    let mut chunks = Vec::with_capacity(parts);
+    let mut start = 0;
+    for part in 0..parts {
+        let end = start + size + usize::from(part < rest);
+        chunks.push(outputs[start..end].to_vec());
+        start = end;
+    }
+    chunks
+}
+
+fn validate_min_ada<DataMapper: CardanoDataMapper>(
+    selection: Selection,
+    validation: &MinAdaValidation,
+    stats: &mut MinAdaStats,
+    data_mapper: &mut DataMapper,
+) -> anyhow::Result<Result<Selection, SelectionError>> {
+    let fixed_output_violations =
+        validation.count_violations(&selection.fixed_outputs, data_mapper)?;
+    let change_violations = validation.count_violations(&selection.changes, data_mapper)?;
+
+    stats.checked_outputs += (selection.fixed_outputs.len() + selection.changes.len()) as u64;
+    stats.fixed_output_violations += fixed_output_violations;
+    stats.change_violations += change_violations;
+
+    // fixed outputs come from the history, their violations only check the estimation
+    if change_violations > 0 && validation.reject_selection {
+        stats.rejected_selections += 1;
+        return Ok(Err(SelectionError::MinAda {
+            violations: change_violations,
+        }));
+    }
+    Ok(Ok(selection))
+}
+
+fn estimate_historical_fee<
+    Estimator: TransactionFeeEstimator<InputUtxo = UTxODetails, OutputUtxo = UTxOBuilder>,
+    EstimatorCreator,
+    DataMapper: CardanoDataMapper,
+>(
+    estimator_creator: &EstimatorCreator,
+    tx_number: u64,
+    inputs: &[TxOutput],
+    outputs: &[TxOutput],
+    data_mapper: &mut DataMapper,
+) -> anyhow::Result<Value<Regulated>>
+where
+    EstimatorCreator: Fn() -> anyhow::Result<Estimator>,
+{
+    let mut estimate = estimator_creator()?;
+
+    let inputs = builders_to_utxo_details(
+        tx_number,
+        tx_outputs_to_utxo_builders(inputs.to_vec(), data_mapper)?,
+    )?;
+    for input in inputs.into_iter() {
+        estimate.add_input(input)?;
+    }
+    for output in tx_outputs_to_utxo_builders(outputs.to_vec(), data_mapper)?.into_iter() {
+        estimate.add_output(output)?;
+    }
+
+    estimate.min_required_fee()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn collect_stats<DataMapper: CardanoDataMapper>(
     stake_key: &u64,
     tx_number: u64,
-    paths: &PathsConfig,
+    is_final: bool,
     actual_balance_acc: &BalanceAccumulator,
     computed_balance_acc: &BalanceAccumulator,
     utxo_accumulator: &UTxOStoreAccumulator,
-    balance_points_acc: &mut StatsAccumulator<BalanceStats>,
-    utxo_count_acc: &mut StatsAccumulator<u64>,
-) {
-    if paths.balance_points_path.is_some() {
-        balance_points_acc.add_stats(
-            *stake_key,
-            tx_number,
-            BalanceStats {
-                ada_computed: balance_to_i64(
-                    computed_balance_acc.get_balance(*stake_key, TokenId::MAIN),
-                ),
-                ada_actual: balance_to_i64(
-                    actual_balance_acc.get_balance(*stake_key, TokenId::MAIN),
-                ),
-                fee_computed: i64::from_str(
-                    computed_balance_acc
-                        .get_fee(*stake_key)
-                        .to_string()
-                        .as_str(),
-                )
-                .unwrap(),
-                fee_actual: i64::from_str(
-                    actual_balance_acc.get_fee(*stake_key).to_string().as_str(),
-                )
+    data_mapper: &DataMapper,
+    balance_points_acc: Option<&mut StatsAccumulator<BalanceStats>>,
+    utxo_count_acc: Option<&mut StatsAccumulator<UtxoCountStats>>,
+    token_points_acc: Option<&mut StatsAccumulator<TokenStats>>,
+) -> anyhow::Result<()> {
+    if let Some(balance_points_acc) = balance_points_acc {
+        let stats = BalanceStats {
+            ada_computed: balance_to_i64(
+                computed_balance_acc.get_balance(*stake_key, TokenId::MAIN),
+            ),
+            ada_actual: balance_to_i64(actual_balance_acc.get_balance(*stake_key, TokenId::MAIN)),
+            fee_computed: i64::from_str(
+                computed_balance_acc
+                    .get_fee(*stake_key)
+                    .to_string()
+                    .as_str(),
+            )
+            .unwrap(),
+            fee_actual: i64::from_str(actual_balance_acc.get_fee(*stake_key).to_string().as_str())
                 .unwrap(),
-            },
-        );
+        };
+        if is_final {
+            balance_points_acc.add_final_stats(*stake_key, tx_number, stats)?;
+        } else {
+            balance_points_acc.add_stats(*stake_key, tx_number, stats)?;
+        }
     }
-    if paths.utxos_balance_path.is_some() {
-        utxo_count_acc.add_stats(
-            *stake_key,
-            tx_number,
-            utxo_accumulator.get_available_inputs(*stake_key).len() as u64,
-        );
+    if let Some(utxo_count_acc) = utxo_count_acc {
+        let stats = UtxoCountStats {
+            utxo_count: utxo_accumulator.get_available_inputs(*stake_key).len() as u64,
+        };
+        if is_final {
+            utxo_count_acc.add_final_stats(*stake_key, tx_number, stats)?;
+        } else {
+            utxo_count_acc.add_stats(*stake_key, tx_number, stats)?;
+        }
+    }
+    if let Some(token_points_acc) = token_points_acc
+        .filter(|token_points_acc| is_final || token_points_acc.samples(*stake_key, tx_number))
+    {
+        for token in token_ids(*stake_key, actual_balance_acc, computed_balance_acc) {
+            let stats = token_stats(
+                *stake_key,
+                &token,
+                actual_balance_acc,
+                computed_balance_acc,
+                data_mapper,
+            )?;
+            if is_final {
+                token_points_acc.add_final_stats(*stake_key, tx_number, stats)?;
+            } else {
+                token_points_acc.add_stats(*stake_key, tx_number, stats)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// native tokens held by the key either in the history or in the computed run
+fn token_ids(
+    stake_key: u64,
+    actual_balance_acc: &BalanceAccumulator,
+    computed_balance_acc: &BalanceAccumulator,
+) -> Vec<TokenId> {
+    let mut tokens = actual_balance_acc.get_tokens(stake_key);
+    for token in computed_balance_acc.get_tokens(stake_key) {
+        if !tokens.contains(&token) {
+            tokens.push(token);
+        }
     }
+    tokens.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    tokens
+}
+
+fn parquet_path(paths: &PathsConfig, name: &str) -> Option<PathBuf> {
+    paths
+        .parquet_dir
+        .as_ref()
+        .map(|dir| dir.join(format!("{name}.parquet")))
+}
+
+fn stats_accumulator<T: StatsRecord>(
+    path: Option<PathBuf>,
+    parquet_name: &str,
+    paths: &PathsConfig,
+) -> anyhow::Result<Option<StatsAccumulator<T>>> {
+    let path = match path {
+        None => return Ok(None),
+        Some(path) => path,
+    };
+    let acc = StatsAccumulator::<T>::create(path, paths.stats_sampling.clone())?;
+    Ok(Some(match parquet_path(paths, parquet_name) {
+        None => acc,
+        Some(parquet) => acc.with_parquet(parquet)?,
+    }))
+}
+
+// final balances of every converged key, a row per token
+fn write_balances_parquet<DataMapper: CardanoDataMapper>(
+    path: PathBuf,
+    actual_balance_acc: &BalanceAccumulator,
+    computed_balance_acc: &BalanceAccumulator,
+    data_mapper: &DataMapper,
+) -> anyhow::Result<()> {
+    let mut writer = ParquetRecordWriter::create(
+        path,
+        vec![
+            Field::new("stake_key", DataType::UInt64, false),
+            Field::new("token", DataType::Utf8, false),
+            Field::new("balance_computed", DataType::Int64, false),
+            Field::new("balance_actual", DataType::Int64, false),
+            Field::new("utxos_computed", DataType::UInt64, false),
+            Field::new("utxos_actual", DataType::UInt64, false),
+            Field::new("fee_computed", DataType::Int64, false),
+            Field::new("fee_actual", DataType::Int64, false),
+        ],
+    )?;
+    for stake_key in computed_balance_acc.stake_keys() {
+        let tokens = std::iter::once(TokenId::MAIN).chain(token_ids(
+            stake_key,
+            actual_balance_acc,
+            computed_balance_acc,
+        ));
+        for token in tokens {
+            let stats = token_stats(
+                stake_key,
+                &token,
+                actual_balance_acc,
+                computed_balance_acc,
+                data_mapper,
+            )?;
+            let mut row = serde_json::Map::new();
+            row.insert("stake_key".to_string(), stake_key.into());
+            if let serde_json::Value::Object(fields) = serde_json::to_value(stats)? {
+                row.extend(fields);
+            }
+            row.insert(
+                "fee_computed".to_string(),
+                checked_value_to_i64(&computed_balance_acc.get_fee(stake_key))?.into(),
+            );
+            row.insert(
+                "fee_actual".to_string(),
+                checked_value_to_i64(&actual_balance_acc.get_fee(stake_key))?.into(),
+            );
+            writer.write_row(row)?;
+        }
+    }
+    writer.finish()
+}
+
+fn write_failures_parquet(path: PathBuf, failures: &[FailureRecord]) -> anyhow::Result<()> {
+    let mut writer = ParquetRecordWriter::create(
+        path,
+        vec![
+            Field::new("staking_key", DataType::UInt64, false),
+            Field::new("reason", DataType::Utf8, false),
+            Field::new("tx_number", DataType::UInt64, true),
+            Field::new("error", DataType::Utf8, true),
+        ],
+    )?;
+    for failure in failures {
+        writer.write(failure)?;
+    }
+    writer.finish()
+}
+
+fn write_fee_accuracy_parquet(path: PathBuf, records: &[FeeRecord]) -> anyhow::Result<()> {
+    let mut writer = ParquetRecordWriter::create(
+        path,
+        vec![
+            Field::new("tx_number", DataType::UInt64, false),
+            Field::new("estimated", DataType::Int64, false),
+            Field::new("actual", DataType::Int64, false),
+        ],
+    )?;
+    for record in records {
+        writer.write(record)?;
+    }
+    writer.finish()
+}
+
+// final utxos of every converged key, as in utxos_path
+fn write_utxos_parquet(
+    path: PathBuf,
+    utxo_accumulator: &UTxOStoreAccumulator,
+) -> anyhow::Result<()> {
+    let mut writer = ParquetRecordWriter::create(
+        path,
+        vec![
+            Field::new("stake_key", DataType::UInt64, false),
+            Field::new("transaction_id", DataType::Utf8, false),
+            Field::new("output_index", DataType::UInt64, false),
+            Field::new("value", DataType::UInt64, false),
+            Field::new("assets", DataType::UInt64, false),
+        ],
+    )?;
+    let mut utxos: Vec<(u64, &UTxOStore)> = utxo_accumulator.converged_utxos().collect();
+    utxos.sort_by_key(|(stake_key, _)| *stake_key);
+    for (stake_key, utxos) in utxos {
+        for utxo in utxos.iter_ordered_by_wmain() {
+            let mut row = serde_json::Map::new();
+            row.insert("stake_key".to_string(), stake_key.into());
+            row.insert(
+                "transaction_id".to_string(),
+                utxo.pointer.transaction_id.as_ref().to_string().into(),
+            );
+            row.insert(
+                "output_index".to_string(),
+                u64::from(utxo.pointer.output_index).into(),
+            );
+            row.insert("value".to_string(), value_to_u64(&utxo.value)?.into());
+            row.insert("assets".to_string(), (utxo.assets.len() as u64).into());
+            writer.write_row(row)?;
+        }
+    }
+    writer.finish()
+}
+
+fn token_stats<DataMapper: CardanoDataMapper>(
+    stake_key: u64,
+    token: &TokenId,
+    actual_balance_acc: &BalanceAccumulator,
+    computed_balance_acc: &BalanceAccumulator,
+    data_mapper: &DataMapper,
+) -> anyhow::Result<TokenStats> {
+    Ok(TokenStats {
+        token: if *token == TokenId::MAIN {
+            "ada".to_string()
+        } else {
+            data_mapper.describe_token(token)
+        },
+        balance_computed: checked_balance_to_i64(
+            computed_balance_acc.get_balance(stake_key, token.clone()),
+        )?,
+        balance_actual: checked_balance_to_i64(
+            actual_balance_acc.get_balance(stake_key, token.clone()),
+        )?,
+        utxos_computed: computed_balance_acc.get_utxo_count(stake_key, token),
+        utxos_actual: actual_balance_acc.get_utxo_count(stake_key, token),
+    })
+}
+
+fn token_spread<DataMapper: CardanoDataMapper>(
+    stake_keys: Vec<u64>,
+    actual_balance_acc: &BalanceAccumulator,
+    computed_balance_acc: &BalanceAccumulator,
+    data_mapper: &DataMapper,
+) -> anyhow::Result<TokenSpreadAccumulator> {
+    let mut token_spread = TokenSpreadAccumulator::default();
+    for stake_key in stake_keys {
+        for token in token_ids(stake_key, actual_balance_acc, computed_balance_acc) {
+            let stats = token_stats(
+                stake_key,
+                &token,
+                actual_balance_acc,
+                computed_balance_acc,
+                data_mapper,
+            )?;
+            token_spread.add(stake_key, stats);
+        }
+    }
+    Ok(token_spread)
 }
 
 fn remove_inputs_from_consideration(
+    tx_number: u64,
     inputs: Vec<TxOutput>,
     utxo_accumulator: &mut UTxOStoreAccumulator,
     actual_balance_acc: &mut BalanceAccumulator,
@@ -488,7 +1265,12 @@ fn remove_inputs_from_consideration(
             selection_eligibility_criteria
                 .clone()
                 .borrow_mut()
-                .mark_key_as_insolvent(sk);
+                .mark_key_as_insolvent(
+                    sk,
+                    FailureReason::InputOfFailedTransaction,
+                    tx_number,
+                    None,
+                );
         }
     }
 }
@@ -507,9 +1289,10 @@ fn add_balances_from_partial_outputs<DataMapper: CardanoDataMapper>(
     Ok(())
 }
 
-fn print_balances(
+fn print_balances<DataMapper: CardanoDataMapper>(
     actual_balance_acc: BalanceAccumulator,
     computed_balance_acc: BalanceAccumulator,
+    data_mapper: &DataMapper,
     output_balance: PathBuf,
     output_balance_short: PathBuf,
 ) -> anyhow::Result<()> {
@@ -545,8 +1328,11 @@ fn print_balances(
                 None => {
                     not_found_token_actual += 1;
                     output_balance.write_all(
-                        format!("no token actual data: address: {key:?}, token: {token:?}\n")
-                            .as_bytes(),
+                        format!(
+                            "no token actual data: address: {key:?}, token: {}\n",
+                            data_mapper.describe_token(token)
+                        )
+                        .as_bytes(),
                     )?;
                     continue;
                 }
@@ -575,8 +1361,8 @@ fn print_balances(
             };
             output_balance.write_all(
                 format!(
-                    "diff: address: {:?}, token: {:?}, diff: {:?}, actual: {:?}, computed: {:?}, fee actual: {:?}, fee computed: {:?}\n",
-                    key, token, print_value, actual_token_balance, computed_token_balance, actual_fee.get(key), computed_fee.get(key),
+                    "diff: address: {:?}, token: {}, diff: {:?}, actual: {:?}, computed: {:?}, fee actual: {:?}, fee computed: {:?}\n",
+                    key, data_mapper.describe_token(token), print_value, actual_token_balance, computed_token_balance, actual_fee.get(key), computed_fee.get(key),
                 )
                 .as_bytes(),
             )?;
@@ -606,3 +1392,35 @@ fn print_balances(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bench::split_outputs;
+    use dcspark_core::tx::UTxOBuilder;
+    use dcspark_core::{Address, Value};
+
+    #[test]
+    fn splits_into_balanced_parts() {
+        let outputs: Vec<UTxOBuilder> = (0..7u64)
+            .map(|value| UTxOBuilder::new(Address::new("0:1"), Value::from(value), vec![]))
+            .collect();
+        // chunks of ceil(len / parts) outputs split 7 outputs into 4 parts when 5 are requested
+        for parts in 1..=7 {
+            let chunks = split_outputs(&outputs, parts);
+            assert_eq!(chunks.len(), parts);
+            let lengths: Vec<usize> = chunks.iter().map(|chunk| chunk.len()).collect();
+            assert!(lengths.iter().max().unwrap() - lengths.iter().min().unwrap() <= 1);
+            assert_eq!(chunks.concat().len(), outputs.len());
+        }
+        let values: Vec<String> = split_outputs(&outputs, 3)
+            .iter()
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(|output| output.value.to_string())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(values, vec!["012", "34", "56"]);
+    }
+}
@@ -1,9 +1,13 @@
 use crate::mapper::DataMapper;
+use crate::network::NetworkProfile;
+use crate::tx_event::{byron_address_pair, byron_index, TxEvent};
 use anyhow::anyhow;
 use cardano_multiplatform_lib::address::StakeCredential;
 use cardano_multiplatform_lib::PolicyID;
 use dcspark_core::{Address, AssetName, PolicyId, TokenId};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
 use std::str::FromStr;
 
@@ -36,6 +40,20 @@ pub trait CardanoDataMapper {
         let asset_name = self.map_asset_name(asset)?;
         self.map_policy_id_and_asset(policy_id, asset_name)
     }
+
+    // token as it is shown in the reports
+    fn describe_token(&self, token: &TokenId) -> String {
+        token.as_ref().to_string()
+    }
+}
+
+// utf-8 asset name if it is printable, asset names are hex encoded in the mapping files
+pub fn readable_asset_name(asset_name: &str) -> Option<String> {
+    let name = String::from_utf8(hex::decode(asset_name).ok()?).ok()?;
+    if name.is_empty() || name.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    Some(name)
 }
 
 #[derive(Default)]
@@ -125,43 +143,179 @@ impl CardanoDataMapper for StringAddressMapper {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressIds {
+    // payment and staking key ids as in the events
+    Address((u64, Option<u64>)),
+    StakingKey(u64),
+}
+
 pub struct CardanoAddressMapper {
     payment_key_mapper: DataMapper<StakeCredential>,
     staking_key_mapper: DataMapper<StakeCredential>,
     policy_id_mapper: DataMapper<PolicyID>,
     asset_name_mapper: DataMapper<String>,
+    byron_address_mapper: Option<DataMapper<String>>,
     network: u8,
+    address_prefix: String,
+    stake_prefix: String,
     default_address: Address,
-    mapped_tokens: HashMap<String, (PolicyId, AssetName)>,
+    // fingerprint -> (policy id, asset name id) of every token of the events and of every produced
+    // fingerprint, the mapping files keep policies and asset names apart
+    tokens: HashMap<TokenId, (u64, u64)>,
 }
 
 impl CardanoAddressMapper {
+    // the tokens of the events are indexed once, so `map_token_id` doesn't depend on the call order
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         payment_key_path: PathBuf,
         staking_key_path: PathBuf,
         policy_id_path: PathBuf,
         asset_name_path: PathBuf,
-        network: u8,
+        byron_address_path: Option<PathBuf>,
+        events_path: Option<PathBuf>,
+        network: NetworkProfile,
         default_address: Address,
     ) -> anyhow::Result<CardanoAddressMapper> {
         let payment_key_mapper = DataMapper::load_from_file(payment_key_path)?;
         let staking_key_mapper = DataMapper::load_from_file(staking_key_path)?;
         let policy_id_mapper = DataMapper::load_from_file(policy_id_path)?;
         let asset_name_mapper = DataMapper::load_from_file(asset_name_path)?;
-        Ok(Self {
+        let byron_address_mapper = byron_address_path
+            .map(DataMapper::load_from_file)
+            .transpose()?;
+        let mut mapper = Self {
             payment_key_mapper,
             staking_key_mapper,
             policy_id_mapper,
             asset_name_mapper,
-            network,
+            byron_address_mapper,
+            network: network.network_id(),
+            address_prefix: network.address_prefix(),
+            stake_prefix: network.stake_prefix(),
             default_address,
-            mapped_tokens: Default::default(),
-        })
+            tokens: Default::default(),
+        };
+        if let Some(events_path) = events_path {
+            mapper.index_tokens(events_path)?;
+        }
+        Ok(mapper)
     }
-}
 
-impl CardanoDataMapper for CardanoAddressMapper {
-    fn map_address(&mut self, address: Option<(u64, Option<u64>)>) -> anyhow::Result<Address> {
+    fn index_tokens(&mut self, events_path: PathBuf) -> anyhow::Result<()> {
+        let mut asset_ids = HashSet::<(u64, u64)>::new();
+        for line in BufReader::new(File::open(events_path)?).lines() {
+            let event: TxEvent = serde_json::from_str(line?.as_str())?;
+            let outputs = match &event {
+                TxEvent::Partial { to } => vec![to],
+                TxEvent::Full { to, from, .. } => vec![to, from],
+            };
+            for output in outputs.into_iter().flatten() {
+                asset_ids.extend(output.assets.iter().map(|asset| asset.asset_id));
+            }
+        }
+        for (policy_id, asset_name) in asset_ids.into_iter() {
+            self.map_policy_id_and_asset_indices(policy_id, asset_name)?;
+        }
+        Ok(())
+    }
+
+    // ids of a bech32 base, enterprise or stake address, or of a base58 byron address
+    pub fn lookup_address(&self, address: &str) -> anyhow::Result<AddressIds> {
+        if let Some(index) = self
+            .byron_address_mapper
+            .as_ref()
+            .and_then(|mapper| mapper.get(&address.to_string()))
+        {
+            return Ok(AddressIds::Address(byron_address_pair(index)));
+        }
+        let inner = cardano_multiplatform_lib::address::Address::from_bech32(address)
+            .map_err(|err| anyhow!("can't parse address: {}, err: {}", address, err))?;
+        if let Some(reward) =
+            cardano_multiplatform_lib::address::RewardAddress::from_address(&inner)
+        {
+            let sk = self
+                .staking_key_mapper
+                .get(&reward.payment_cred())
+                .ok_or_else(|| anyhow!("staking key is not found: {}", address))?;
+            return Ok(AddressIds::StakingKey(sk));
+        }
+        let pk = inner
+            .payment_cred()
+            .ok_or_else(|| anyhow!("address has no payment key: {}", address))?;
+        let pk = self
+            .payment_key_mapper
+            .get(&pk)
+            .ok_or_else(|| anyhow!("payment key is not found: {}", address))?;
+        let sk = match inner.staking_cred() {
+            None => None,
+            Some(sk) => Some(
+                self.staking_key_mapper
+                    .get(&sk)
+                    .ok_or_else(|| anyhow!("staking key is not found: {}", address))?,
+            ),
+        };
+        Ok(AddressIds::Address((pk, sk)))
+    }
+
+    // policy id is hex encoded, asset name is hex encoded as in the mapping files
+    pub fn lookup_asset(&self, policy_id: &str, asset_name: &str) -> anyhow::Result<(u64, u64)> {
+        let policy = PolicyID::from_hex(policy_id)
+            .map_err(|err| anyhow!("can't decode policy id: {}, err: {}", policy_id, err))?;
+        let policy = self
+            .policy_id_mapper
+            .get(&policy)
+            .ok_or_else(|| anyhow!("policy id is not found: {}", policy_id))?;
+        let asset_name = self
+            .asset_name_mapper
+            .get(&asset_name.to_string())
+            .ok_or_else(|| anyhow!("asset name is not found: {}", asset_name))?;
+        Ok((policy, asset_name))
+    }
+
+    pub fn address_by_ids(&self, address: (u64, Option<u64>)) -> anyhow::Result<String> {
+        Ok(self.address_or_default(Some(address))?.to_string())
+    }
+
+    pub fn stake_address_by_id(&self, staking_key: u64) -> anyhow::Result<String> {
+        let sk = self
+            .staking_key_mapper
+            .get_by_index(staking_key)
+            .ok_or_else(|| anyhow!("can't find sk: {}", staking_key))?;
+        cardano_multiplatform_lib::address::RewardAddress::new(self.network, sk)
+            .to_address()
+            .to_bech32(Some(self.stake_prefix.clone()))
+            .map_err(|err| anyhow!("can't convert address: {}", err))
+    }
+
+    // hex encoded policy id and asset name
+    pub fn asset_by_ids(
+        &self,
+        policy_id: u64,
+        asset_name: u64,
+    ) -> anyhow::Result<(String, String)> {
+        let policy = self
+            .policy_id_mapper
+            .get_by_index(policy_id)
+            .ok_or_else(|| anyhow!("can't find policy id: {}", policy_id))?;
+        let asset_name = self
+            .asset_name_mapper
+            .get_by_index(asset_name)
+            .ok_or_else(|| anyhow!("can't find asset name: {}", asset_name))?;
+        Ok((policy.to_string(), asset_name.clone()))
+    }
+
+    fn address_or_default(&self, address: Option<(u64, Option<u64>)>) -> anyhow::Result<Address> {
+        if let (Some(byron_mapper), Some(index)) = (
+            self.byron_address_mapper.as_ref(),
+            address.and_then(byron_index),
+        ) {
+            let byron = byron_mapper
+                .get_by_index(index)
+                .ok_or_else(|| anyhow!("can't find byron address: {}", index))?;
+            return Ok(Address::new(byron.clone()));
+        }
         match address {
             None => Ok(self.default_address.clone()),
             Some((pk, Some(sk))) => {
@@ -177,7 +331,7 @@ impl CardanoDataMapper for CardanoAddressMapper {
                 Ok(Address::new(
                     cardano_multiplatform_lib::address::BaseAddress::new(self.network, pk, sk)
                         .to_address()
-                        .to_bech32(None)
+                        .to_bech32(Some(self.address_prefix.clone()))
                         .map_err(|err| anyhow!("can't convert address: {}", err))?,
                 ))
             }
@@ -190,12 +344,18 @@ impl CardanoDataMapper for CardanoAddressMapper {
                 Ok(Address::new(
                     cardano_multiplatform_lib::address::EnterpriseAddress::new(self.network, pk)
                         .to_address()
-                        .to_bech32(None)
+                        .to_bech32(Some(self.address_prefix.clone()))
                         .map_err(|err| anyhow!("can't convert address: {}", err))?,
                 ))
             }
         }
     }
+}
+
+impl CardanoDataMapper for CardanoAddressMapper {
+    fn map_address(&mut self, address: Option<(u64, Option<u64>)>) -> anyhow::Result<Address> {
+        self.address_or_default(address)
+    }
 
     fn map_address_to_indices(
         &mut self,
@@ -204,6 +364,11 @@ impl CardanoDataMapper for CardanoAddressMapper {
         if address == self.default_address {
             return Ok(None);
         }
+        if let Some(byron_mapper) = self.byron_address_mapper.as_ref() {
+            if let Some(index) = byron_mapper.get(&address.to_string()) {
+                return Ok(Some(byron_address_pair(index)));
+            }
+        }
         let inner = cardano_multiplatform_lib::address::Address::from_bech32(address.as_ref())
             .map_err(|err| anyhow!("can't convert address: {}, err: {}", address, err))?;
         let pk = match inner.payment_cred() {
@@ -258,10 +423,14 @@ impl CardanoDataMapper for CardanoAddressMapper {
     }
 
     fn map_token_id(&mut self, token: TokenId) -> anyhow::Result<(PolicyId, AssetName)> {
-        match self.mapped_tokens.get(token.as_ref()).cloned() {
-            None => Err(anyhow!("can't map token: {}", token.as_ref())),
-            Some(result) => Ok(result),
-        }
+        let (policy_id, asset_name) = *self
+            .tokens
+            .get(&token)
+            .ok_or_else(|| anyhow!("can't map token: {}", token.as_ref()))?;
+        Ok((
+            self.map_policy_id(policy_id)?,
+            self.map_asset_name(asset_name)?,
+        ))
     }
 
     fn map_policy_id_and_asset(
@@ -270,16 +439,58 @@ impl CardanoDataMapper for CardanoAddressMapper {
         asset: AssetName,
     ) -> anyhow::Result<TokenId> {
         let fingerprint = dcspark_core::fingerprint(&policy, &asset)?;
-        self.mapped_tokens
-            .insert(fingerprint.to_string(), (policy, asset));
+        // tokens unknown to the mapping files still have a fingerprint, they just can't be resolved
+        if let (Ok(policy_id), Ok(asset_name)) = (
+            self.map_policy_id_to_index(policy),
+            self.map_asset_name_to_index(asset),
+        ) {
+            self.tokens
+                .insert(fingerprint.clone(), (policy_id, asset_name));
+        }
         Ok(fingerprint)
     }
+
+    fn map_policy_id_and_asset_indices(
+        &mut self,
+        policy: u64,
+        asset: u64,
+    ) -> anyhow::Result<TokenId> {
+        let policy_id = self.map_policy_id(policy)?;
+        let asset_name = self.map_asset_name(asset)?;
+        let fingerprint = dcspark_core::fingerprint(&policy_id, &asset_name)?;
+        self.tokens.insert(fingerprint.clone(), (policy, asset));
+        Ok(fingerprint)
+    }
+
+    fn describe_token(&self, token: &TokenId) -> String {
+        let asset = self
+            .tokens
+            .get(token)
+            .and_then(|(policy_id, asset_name)| self.asset_by_ids(*policy_id, *asset_name).ok());
+        match asset {
+            None => token.as_ref().to_string(),
+            Some((policy_id, asset_name)) => format!(
+                "{} ({}.{})",
+                token.as_ref(),
+                policy_id,
+                readable_asset_name(&asset_name).unwrap_or(asset_name)
+            ),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::bench_utils::address_mapper::{CardanoDataMapper, StringAddressMapper};
-    use dcspark_core::{Address, AssetName, PolicyId, TokenId};
+    use crate::bench_utils::address_mapper::{
+        readable_asset_name, AddressIds, CardanoAddressMapper, CardanoDataMapper,
+        StringAddressMapper,
+    };
+    use crate::mapper::DataMapper;
+    use crate::network::NetworkProfile;
+    use crate::tx_event::{TxAsset, TxEvent, TxOutput};
+    use cardano_multiplatform_lib::address::StakeCredential;
+    use cardano_multiplatform_lib::PolicyID;
+    use dcspark_core::{Address, AssetName, PolicyId, Regulated, TokenId, Value};
 
     #[test]
     fn check_string_mapper() {
@@ -334,4 +545,127 @@ mod tests {
             TokenId::new("44:1")
         );
     }
+
+    #[test]
+    fn check_readable_asset_name() {
+        assert_eq!(
+            readable_asset_name(&hex::encode("token")),
+            Some("token".to_string())
+        );
+        assert_eq!(readable_asset_name(""), None);
+        assert_eq!(readable_asset_name("00ff"), None);
+        assert_eq!(readable_asset_name("0001"), None);
+    }
+
+    #[test]
+    fn check_cardano_mapper_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let credential =
+            |key: &str| StakeCredential::from_bytes(hex::decode(key).unwrap()).unwrap();
+        let policy = "b863bc7369f46136ac1048adb2fa7dae3af944c3bbb2be2f216a8d4f";
+
+        let mut payment = DataMapper::<StakeCredential>::new();
+        payment.add_if_not_presented(credential(
+            "8200581c9566a8f301fb8a046e44557bb38dfb9080a1213f17f200dcd3808169",
+        ));
+        let mut staking = DataMapper::<StakeCredential>::new();
+        staking.add_if_not_presented(credential(
+            "8200581c1111a8f301fb8a046e44557bb38dfb9080a1213f17f200dcd3808169",
+        ));
+        staking.add_if_not_presented(credential(
+            "8200581c49f14106ef746c2d3597381d1d5d1c65c91e933acd1baef3fc915f0b",
+        ));
+        let mut policies = DataMapper::<PolicyID>::new();
+        policies.add_if_not_presented(PolicyID::from_hex(policy).unwrap());
+        let mut asset_names = DataMapper::<String>::new();
+        asset_names.add_if_not_presented(hex::encode("token"));
+        payment.dump_to_file(dir.join("payment")).unwrap();
+        staking.dump_to_file(dir.join("staking")).unwrap();
+        policies.dump_to_file(dir.join("policy")).unwrap();
+        asset_names.dump_to_file(dir.join("asset_name")).unwrap();
+
+        // the tokens of the events are resolved without producing their fingerprint first
+        let event = TxEvent::Partial {
+            to: vec![TxOutput {
+                address: Some((0, Some(1))),
+                value: Value::<Regulated>::from(2_000_000),
+                assets: vec![TxAsset {
+                    asset_id: (0, 0),
+                    value: Value::<Regulated>::from(1),
+                }],
+            }],
+        };
+        std::fs::write(
+            dir.join("events"),
+            format!("{}\n", serde_json::to_string(&event).unwrap()),
+        )
+        .unwrap();
+        let mut mapper = CardanoAddressMapper::new(
+            dir.join("payment"),
+            dir.join("staking"),
+            dir.join("policy"),
+            dir.join("asset_name"),
+            None,
+            Some(dir.join("events")),
+            NetworkProfile::Mainnet,
+            Address::new("default"),
+        )
+        .unwrap();
+
+        let address = "addr1qx2kd28nq8ac5prwg32hhvudlwggpgfp8utlyqxu6wqgz62f79qsdmm5dsknt9ecr5w468r9ey0fxwkdrwh08ly3tu9sy0f4qd";
+        assert_eq!(
+            mapper.lookup_address(address).unwrap(),
+            AddressIds::Address((0, Some(1)))
+        );
+        assert_eq!(mapper.address_by_ids((0, Some(1))).unwrap(), address);
+
+        let stake_address = mapper.stake_address_by_id(1).unwrap();
+        assert!(stake_address.starts_with("stake1"));
+        assert_eq!(
+            mapper.lookup_address(&stake_address).unwrap(),
+            AddressIds::StakingKey(1)
+        );
+
+        let enterprise = mapper.address_by_ids((0, None)).unwrap();
+        assert_eq!(
+            mapper.lookup_address(&enterprise).unwrap(),
+            AddressIds::Address((0, None))
+        );
+
+        assert_eq!(
+            mapper.lookup_asset(policy, &hex::encode("token")).unwrap(),
+            (0, 0)
+        );
+        assert_eq!(
+            mapper.asset_by_ids(0, 0).unwrap(),
+            (policy.to_string(), hex::encode("token"))
+        );
+        assert!(mapper.lookup_asset(policy, "00").is_err());
+        assert!(mapper.stake_address_by_id(2).is_err());
+
+        let token = dcspark_core::fingerprint(
+            &PolicyId::new(policy),
+            &AssetName::new(hex::encode("token")),
+        )
+        .unwrap();
+        assert_eq!(
+            mapper.map_token_id(token.clone()).unwrap(),
+            (PolicyId::new(policy), AssetName::new(hex::encode("token")))
+        );
+        assert_eq!(
+            mapper.describe_token(&token),
+            format!("{} ({}.token)", token.as_ref(), policy)
+        );
+        assert_eq!(
+            mapper.describe_token(&TokenId::MAIN),
+            TokenId::MAIN.as_ref()
+        );
+
+        // unknown policies and asset names still get a fingerprint
+        let unknown = mapper
+            .map_policy_id_and_asset(PolicyId::new("00".repeat(28)), AssetName::new("00"))
+            .unwrap();
+        assert!(mapper.map_token_id(unknown).is_err());
+    }
 }
@@ -5,12 +5,14 @@ use crate::tx_event::TxOutput;
 use dcspark_core::tx::{UTxOBuilder, UTxODetails};
 use dcspark_core::{Balance, Regulated, TokenId, Value};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 pub struct BalanceAccumulator {
     balance_by_stake_key: HashMap<u64, HashMap<TokenId, Balance<Regulated>>>,
     fee_by_stake_key: HashMap<u64, Value<Regulated>>,
+    // number of utxos holding the token, every utxo holds ada
+    utxos_by_stake_key: HashMap<u64, HashMap<TokenId, u64>>,
     criteria: Rc<RefCell<SelectionEligibility>>,
 }
 
@@ -19,6 +21,7 @@ impl BalanceAccumulator {
         Self {
             balance_by_stake_key: Default::default(),
             fee_by_stake_key: Default::default(),
+            utxos_by_stake_key: Default::default(),
             criteria: selection_eligibility,
         }
     }
@@ -38,6 +41,30 @@ impl BalanceAccumulator {
             .unwrap_or_default()
     }
 
+    pub fn get_utxo_count(&self, stake_key: u64, token: &TokenId) -> u64 {
+        self.utxos_by_stake_key
+            .get(&stake_key)
+            .and_then(|map| map.get(token))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // native tokens the key has ever held, sorted
+    pub fn get_tokens(&self, stake_key: u64) -> Vec<TokenId> {
+        let mut tokens: Vec<TokenId> = self
+            .balance_by_stake_key
+            .get(&stake_key)
+            .map(|map| {
+                map.keys()
+                    .filter(|token| **token != TokenId::MAIN)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        tokens.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        tokens
+    }
+
     pub fn reduce_balance_from<DataMapper: CardanoDataMapper>(
         &mut self,
         from: &[TxOutput],
@@ -52,13 +79,17 @@ impl BalanceAccumulator {
             if criteria.is_whitelisted(sk) && !criteria.is_banned(sk) {
                 let entry = self.balance_by_stake_key.entry(sk).or_default();
                 *entry.entry(TokenId::MAIN).or_default() -= &builder.value;
+                let mut tokens = vec![TokenId::MAIN];
                 for asset in builder.assets.iter() {
                     let fingerprint = mapper
                         .map_policy_id_and_asset_indices(asset.asset_id.0, asset.asset_id.1)?;
-                    *entry.entry(fingerprint).or_default() -= &asset.value;
+                    *entry.entry(fingerprint.clone()).or_default() -= &asset.value;
+                    tokens.push(fingerprint);
                 }
+                count_utxo(self.utxos_by_stake_key.entry(sk).or_default(), tokens, true);
             } else {
                 self.balance_by_stake_key.remove(&sk);
+                self.utxos_by_stake_key.remove(&sk);
             }
         }
 
@@ -79,13 +110,21 @@ impl BalanceAccumulator {
             if criteria.is_whitelisted(sk) && !criteria.is_banned(sk) {
                 let entry = self.balance_by_stake_key.entry(sk).or_default();
                 *entry.entry(TokenId::MAIN).or_default() += &builder.value;
+                let mut tokens = vec![TokenId::MAIN];
                 for asset in builder.assets.iter() {
                     let fingerprint = mapper
                         .map_policy_id_and_asset_indices(asset.asset_id.0, asset.asset_id.1)?;
-                    *entry.entry(fingerprint).or_default() += &asset.value;
+                    *entry.entry(fingerprint.clone()).or_default() += &asset.value;
+                    tokens.push(fingerprint);
                 }
+                count_utxo(
+                    self.utxos_by_stake_key.entry(sk).or_default(),
+                    tokens,
+                    false,
+                );
             } else {
                 self.balance_by_stake_key.remove(&sk);
+                self.utxos_by_stake_key.remove(&sk);
             }
         }
 
@@ -109,8 +148,12 @@ impl BalanceAccumulator {
                 for asset in builder.assets.iter() {
                     *entry.entry(asset.fingerprint.clone()).or_default() -= &asset.quantity;
                 }
+                let tokens = std::iter::once(TokenId::MAIN)
+                    .chain(builder.assets.iter().map(|asset| asset.fingerprint.clone()));
+                count_utxo(self.utxos_by_stake_key.entry(sk).or_default(), tokens, true);
             } else {
                 self.balance_by_stake_key.remove(&sk);
+                self.utxos_by_stake_key.remove(&sk);
             }
         }
 
@@ -134,8 +177,16 @@ impl BalanceAccumulator {
                 for asset in builder.assets.iter() {
                     *entry.entry(asset.fingerprint.clone()).or_default() += &asset.quantity;
                 }
+                let tokens = std::iter::once(TokenId::MAIN)
+                    .chain(builder.assets.iter().map(|asset| asset.fingerprint.clone()));
+                count_utxo(
+                    self.utxos_by_stake_key.entry(sk).or_default(),
+                    tokens,
+                    false,
+                );
             } else {
                 self.balance_by_stake_key.remove(&sk);
+                self.utxos_by_stake_key.remove(&sk);
             }
         }
 
@@ -169,6 +220,13 @@ impl BalanceAccumulator {
     pub fn remove_stake_key(&mut self, staking_key: u64) {
         self.balance_by_stake_key.remove(&staking_key);
         self.fee_by_stake_key.remove(&staking_key);
+        self.utxos_by_stake_key.remove(&staking_key);
+    }
+
+    pub fn stake_keys(&self) -> Vec<u64> {
+        let mut keys: Vec<u64> = self.balance_by_stake_key.keys().cloned().collect();
+        keys.sort_unstable();
+        keys
     }
 
     pub fn len(&self) -> usize {
@@ -189,3 +247,20 @@ impl BalanceAccumulator {
         (self.balance_by_stake_key, self.fee_by_stake_key)
     }
 }
+
+fn count_utxo(
+    counts: &mut HashMap<TokenId, u64>,
+    tokens: impl IntoIterator<Item = TokenId>,
+    spent: bool,
+) {
+    // the same token can appear in several assets of an output, it's still a single utxo
+    let tokens: HashSet<TokenId> = tokens.into_iter().collect();
+    for token in tokens {
+        let count = counts.entry(token).or_default();
+        *count = if spent {
+            count.saturating_sub(1)
+        } else {
+            *count + 1
+        };
+    }
+}
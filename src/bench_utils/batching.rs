@@ -0,0 +1,432 @@
+use crate::tx_event::{TxEvent, TxOutput};
+use crate::utils::checked_value_to_i64;
+use dcspark_core::{Regulated, Value};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchingConfig {
+    // the batch is closed once a new event is further than `window_lines` from its first event.
+    // events have no block numbers, so windows in blocks are not supported
+    pub window_lines: u64,
+    // maximum number of historical transactions merged into one selection
+    pub max_batch_size: usize,
+}
+
+pub struct BatchedEvent {
+    pub tx_number: u64,
+    pub event: TxEvent,
+    pub merged_transactions: usize,
+}
+
+struct PendingBatch {
+    first_line: u64,
+    inputs: Vec<TxOutput>,
+    outputs: Vec<TxOutput>,
+    fee: Value<Regulated>,
+    merged_transactions: usize,
+}
+
+impl PendingBatch {
+    fn into_batched_event(self) -> BatchedEvent {
+        BatchedEvent {
+            tx_number: self.first_line,
+            event: TxEvent::Full {
+                from: self.inputs,
+                to: self.outputs,
+                fee: self.fee,
+            },
+            merged_transactions: self.merged_transactions,
+        }
+    }
+}
+
+/* merges consecutive full events spending from a single staking key:
+ * - events not touching the staking key of a pending batch are passed through
+ * - events sending funds to the staking key of a pending batch flush that batch first
+ * - events spending from a key paid by a pending batch flush that batch first
+ * - inputs spending the outputs of the batch (e.g. its change) are dropped with those outputs
+ * - batch is emitted with the line number of its first event
+ * - events are emitted in line order, passed through events wait for the older pending batches
+ */
+pub struct EventBatcher<I: Iterator<Item = anyhow::Result<(u64, TxEvent)>>> {
+    events: I,
+    config: BatchingConfig,
+    pending: HashMap<u64, PendingBatch>,
+    ready: BTreeMap<u64, BatchedEvent>,
+    finished: bool,
+}
+
+impl<I: Iterator<Item = anyhow::Result<(u64, TxEvent)>>> EventBatcher<I> {
+    pub fn new(events: I, config: BatchingConfig) -> Self {
+        Self {
+            events,
+            config,
+            pending: HashMap::default(),
+            ready: BTreeMap::default(),
+            finished: false,
+        }
+    }
+
+    fn flush_key(&mut self, staking_key: u64) {
+        if let Some(batch) = self.pending.remove(&staking_key) {
+            self.ready
+                .insert(batch.first_line, batch.into_batched_event());
+        }
+    }
+
+    fn flush_expired(&mut self, line: u64) {
+        let mut expired: Vec<(u64, u64)> = self
+            .pending
+            .iter()
+            .filter(|(_, batch)| line - batch.first_line >= self.config.window_lines)
+            .map(|(key, batch)| (batch.first_line, *key))
+            .collect();
+        expired.sort();
+        for (_, key) in expired {
+            self.flush_key(key);
+        }
+    }
+
+    fn flush_all(&mut self) {
+        let mut keys: Vec<(u64, u64)> = self
+            .pending
+            .iter()
+            .map(|(key, batch)| (batch.first_line, *key))
+            .collect();
+        keys.sort();
+        for (_, key) in keys {
+            self.flush_key(key);
+        }
+    }
+
+    fn push(&mut self, line: u64, event: TxEvent) {
+        self.flush_expired(line);
+
+        let touched_keys = event.staking_keys();
+        let (batch_key, spent_keys): (Option<u64>, HashSet<u64>) = match &event {
+            TxEvent::Full { from, .. } => (
+                single_input_staking_key(from),
+                from.iter()
+                    .filter_map(|input| input.address.and_then(|(_, staking)| staking))
+                    .collect(),
+            ),
+            TxEvent::Partial { .. } => (None, HashSet::new()),
+        };
+
+        // batches paying to the keys the event spends from must be emitted before it,
+        // the change of the batch being extended is spent by the event itself
+        let mut flushed: Vec<(u64, u64)> = self
+            .pending
+            .iter()
+            .filter(|(key, _)| Some(**key) != batch_key)
+            .filter(|(key, batch)| {
+                touched_keys.contains(key)
+                    || batch.outputs.iter().any(|output| match output.address {
+                        Some((_, Some(sk))) => spent_keys.contains(&sk),
+                        _ => false,
+                    })
+            })
+            .map(|(key, batch)| (batch.first_line, *key))
+            .collect();
+        flushed.sort();
+        for (_, key) in flushed {
+            self.flush_key(key);
+        }
+
+        let (batch_key, from, to, fee) = match (batch_key, event) {
+            (Some(batch_key), TxEvent::Full { from, to, fee }) => (batch_key, from, to, fee),
+            (_, event) => {
+                self.ready.insert(
+                    line,
+                    BatchedEvent {
+                        tx_number: line,
+                        event,
+                        merged_transactions: 1,
+                    },
+                );
+                return;
+            }
+        };
+
+        if self
+            .pending
+            .get(&batch_key)
+            .map(|batch| batch.merged_transactions >= self.config.max_batch_size)
+            .unwrap_or(false)
+        {
+            self.flush_key(batch_key);
+        }
+
+        let batch = self.pending.entry(batch_key).or_insert(PendingBatch {
+            first_line: line,
+            inputs: vec![],
+            outputs: vec![],
+            fee: Value::zero(),
+            merged_transactions: 0,
+        });
+        // outputs are matched by value, spending any of the equal ones gives the same batch
+        for input in from {
+            match batch.outputs.iter().position(|output| *output == input) {
+                Some(position) => {
+                    batch.outputs.remove(position);
+                }
+                None => batch.inputs.push(input),
+            }
+        }
+        batch.outputs.extend(to);
+        batch.fee += &fee;
+        batch.merged_transactions += 1;
+    }
+}
+
+impl<I: Iterator<Item = anyhow::Result<(u64, TxEvent)>>> Iterator for EventBatcher<I> {
+    type Item = anyhow::Result<BatchedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let oldest_pending = self.pending.values().map(|batch| batch.first_line).min();
+            match (self.ready.keys().next(), oldest_pending) {
+                (Some(line), Some(pending_line)) if *line < pending_line => break,
+                (Some(_), None) => break,
+                _ if self.finished => break,
+                _ => {}
+            }
+            match self.events.next() {
+                Some(Ok((line, event))) => self.push(line, event),
+                Some(Err(err)) => return Some(Err(err)),
+                None => {
+                    self.flush_all();
+                    self.finished = true;
+                }
+            }
+        }
+        self.ready.pop_first().map(|(_, event)| Ok(event))
+    }
+}
+
+fn single_input_staking_key(inputs: &[TxOutput]) -> Option<u64> {
+    let mut keys = HashSet::<u64>::new();
+    for input in inputs.iter() {
+        match input.address {
+            Some((_, Some(sk))) => {
+                keys.insert(sk);
+            }
+            _ => return None,
+        }
+    }
+    if keys.len() == 1 {
+        keys.into_iter().next()
+    } else {
+        None
+    }
+}
+
+#[derive(Default)]
+pub struct BatchingStats {
+    batches: u64,
+    merged_transactions: u64,
+    fee_actual: Value<Regulated>,
+    fee_computed: Value<Regulated>,
+    inputs_actual: u64,
+    inputs_computed: u64,
+    changes_actual: u64,
+    changes_computed: u64,
+}
+
+impl BatchingStats {
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_batch(
+        &mut self,
+        merged_transactions: usize,
+        fee_actual: &Value<Regulated>,
+        fee_computed: &Value<Regulated>,
+        inputs_actual: usize,
+        inputs_computed: usize,
+        changes_actual: usize,
+        changes_computed: usize,
+    ) {
+        self.batches += 1;
+        self.merged_transactions += merged_transactions as u64;
+        self.fee_actual += fee_actual;
+        self.fee_computed += fee_computed;
+        self.inputs_actual += inputs_actual as u64;
+        self.inputs_computed += inputs_computed as u64;
+        self.changes_actual += changes_actual as u64;
+        self.changes_computed += changes_computed as u64;
+    }
+
+    pub fn print_report(&self, path: PathBuf) -> anyhow::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(format!("batches: {}\n", self.batches).as_bytes())?;
+        file.write_all(format!("merged transactions: {}\n", self.merged_transactions).as_bytes())?;
+        file.write_all(format!("fee actual: {}\n", self.fee_actual).as_bytes())?;
+        file.write_all(format!("fee computed: {}\n", self.fee_computed).as_bytes())?;
+        file.write_all(
+            format!(
+                "fee savings: {}\n",
+                checked_value_to_i64(&self.fee_actual)? - checked_value_to_i64(&self.fee_computed)?
+            )
+            .as_bytes(),
+        )?;
+        file.write_all(format!("inputs actual: {}\n", self.inputs_actual).as_bytes())?;
+        file.write_all(format!("inputs computed: {}\n", self.inputs_computed).as_bytes())?;
+        file.write_all(format!("changes actual: {}\n", self.changes_actual).as_bytes())?;
+        file.write_all(format!("changes computed: {}\n", self.changes_computed).as_bytes())?;
+        file.write_all(
+            format!(
+                "utxo set delta actual: {}\n",
+                self.changes_actual as i64 - self.inputs_actual as i64
+            )
+            .as_bytes(),
+        )?;
+        file.write_all(
+            format!(
+                "utxo set delta computed: {}\n",
+                self.changes_computed as i64 - self.inputs_computed as i64
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_utils::batching::{BatchingConfig, EventBatcher};
+    use crate::tx_event::{TxEvent, TxOutput};
+    use dcspark_core::{Regulated, Value};
+
+    fn output(pk: u64, sk: u64, value: u64) -> TxOutput {
+        TxOutput {
+            address: Some((pk, Some(sk))),
+            value: Value::from(value),
+            assets: vec![],
+        }
+    }
+
+    fn full(from: Vec<TxOutput>, to: Vec<TxOutput>, fee: u64) -> TxEvent {
+        TxEvent::Full {
+            from,
+            to,
+            fee: Value::<Regulated>::from(fee),
+        }
+    }
+
+    fn run(events: Vec<TxEvent>, window_lines: u64, max_batch_size: usize) -> Vec<(u64, usize)> {
+        EventBatcher::new(
+            events
+                .into_iter()
+                .enumerate()
+                .map(|(line, event)| Ok((line as u64, event))),
+            BatchingConfig {
+                window_lines,
+                max_batch_size,
+            },
+        )
+        .map(|event| {
+            let event = event.unwrap();
+            (event.tx_number, event.merged_transactions)
+        })
+        .collect()
+    }
+
+    #[test]
+    fn merges_consecutive_spendings() {
+        let events = vec![
+            full(
+                vec![output(0, 1, 100)],
+                vec![output(5, 5, 50), output(0, 1, 49)],
+                1,
+            ),
+            full(
+                vec![output(0, 1, 49)],
+                vec![output(6, 6, 40), output(0, 1, 8)],
+                1,
+            ),
+            full(vec![output(0, 1, 8)], vec![output(7, 7, 7)], 1),
+        ];
+        assert_eq!(run(events.clone(), 10, 10), vec![(0, 3)]);
+
+        // the change spent inside the batch is neither an input nor an output of the merged transaction
+        let mut batched = EventBatcher::new(
+            events
+                .into_iter()
+                .enumerate()
+                .map(|(line, event)| Ok((line as u64, event))),
+            BatchingConfig {
+                window_lines: 10,
+                max_batch_size: 10,
+            },
+        )
+        .map(|event| event.unwrap().event);
+        match batched.next() {
+            Some(TxEvent::Full { from, to, fee }) => {
+                assert_eq!(from, vec![output(0, 1, 100)]);
+                assert_eq!(
+                    to,
+                    vec![output(5, 5, 50), output(6, 6, 40), output(7, 7, 7)]
+                );
+                assert_eq!(fee, Value::<Regulated>::from(3));
+            }
+            event => panic!("expected a full event, got {:?}", event),
+        }
+        assert!(batched.next().is_none());
+    }
+
+    #[test]
+    fn respects_window_and_size() {
+        let events = vec![
+            full(vec![output(0, 1, 10)], vec![output(5, 5, 9)], 1),
+            full(vec![output(0, 1, 10)], vec![output(5, 5, 9)], 1),
+            full(vec![output(0, 1, 10)], vec![output(5, 5, 9)], 1),
+        ];
+        assert_eq!(run(events.clone(), 2, 10), vec![(0, 2), (2, 1)]);
+        assert_eq!(run(events, 10, 1), vec![(0, 1), (1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn incoming_payment_flushes_batch() {
+        let events = vec![
+            full(vec![output(0, 1, 10)], vec![output(5, 5, 9)], 1),
+            TxEvent::Partial {
+                to: vec![output(0, 1, 10)],
+            },
+            full(vec![output(3, 3, 10)], vec![output(5, 5, 9)], 1),
+            full(vec![output(0, 1, 10)], vec![output(5, 5, 9)], 1),
+        ];
+        assert_eq!(run(events, 10, 10), vec![(0, 1), (1, 1), (2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn spending_batch_outputs_flushes_batch() {
+        let events = vec![
+            // batch of key 1 pays key 2
+            full(
+                vec![output(0, 1, 100)],
+                vec![output(2, 2, 50), output(0, 1, 49)],
+                1,
+            ),
+            // key 2 spends the output of the pending batch
+            full(vec![output(2, 2, 50)], vec![output(7, 7, 49)], 1),
+            full(vec![output(0, 1, 49)], vec![output(7, 7, 48)], 1),
+        ];
+        assert_eq!(run(events, 10, 10), vec![(0, 1), (1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn passed_through_events_keep_line_order() {
+        let events = vec![
+            full(vec![output(0, 1, 10)], vec![output(5, 5, 9)], 1),
+            TxEvent::Partial {
+                to: vec![output(9, 9, 10)],
+            },
+            full(vec![output(0, 1, 10)], vec![output(5, 5, 9)], 1),
+        ];
+        assert_eq!(run(events, 10, 10), vec![(0, 2), (1, 1)]);
+    }
+}
@@ -0,0 +1,192 @@
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+const WORST_OFFENDERS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeeRecord {
+    pub tx_number: u64,
+    pub estimated: i64,
+    pub actual: i64,
+}
+
+impl FeeRecord {
+    pub fn error(&self) -> i64 {
+        self.estimated - self.actual
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeAccuracySummary {
+    pub transactions: usize,
+    pub failed_estimations: u64,
+    pub underestimated: usize,
+    pub overestimated: usize,
+    pub exact: usize,
+    // mean of estimated - actual
+    pub bias: f64,
+    pub mean_absolute_error: f64,
+    pub mean_relative_error: f64,
+    // percentiles of the absolute error
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub max: i64,
+}
+
+#[derive(Default)]
+pub struct FeeAccuracyAccumulator {
+    records: Vec<FeeRecord>,
+    failed_estimations: u64,
+}
+
+impl FeeAccuracyAccumulator {
+    pub fn add_estimation(&mut self, tx_number: u64, estimated: i64, actual: i64) {
+        self.records.push(FeeRecord {
+            tx_number,
+            estimated,
+            actual,
+        });
+    }
+
+    pub fn add_failed_estimation(&mut self) {
+        self.failed_estimations += 1;
+    }
+
+    pub fn summary(&self) -> FeeAccuracySummary {
+        let mut absolute_errors: Vec<i64> = self
+            .records
+            .iter()
+            .map(|record| record.error().abs())
+            .collect();
+        absolute_errors.sort_unstable();
+
+        let percentile = |p: usize| -> i64 {
+            if absolute_errors.is_empty() {
+                return 0;
+            }
+            absolute_errors[(absolute_errors.len() - 1) * p / 100]
+        };
+
+        let count = self.records.len().max(1) as f64;
+        FeeAccuracySummary {
+            transactions: self.records.len(),
+            failed_estimations: self.failed_estimations,
+            underestimated: self.records.iter().filter(|r| r.error() < 0).count(),
+            overestimated: self.records.iter().filter(|r| r.error() > 0).count(),
+            exact: self.records.iter().filter(|r| r.error() == 0).count(),
+            bias: self.records.iter().map(|r| r.error() as f64).sum::<f64>() / count,
+            mean_absolute_error: absolute_errors.iter().map(|e| *e as f64).sum::<f64>() / count,
+            mean_relative_error: self
+                .records
+                .iter()
+                .map(|r| r.error().abs() as f64 / r.actual.max(1) as f64)
+                .sum::<f64>()
+                / count,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: absolute_errors.last().cloned().unwrap_or_default(),
+        }
+    }
+
+    pub fn records(&self) -> &[FeeRecord] {
+        &self.records
+    }
+
+    pub fn worst_offenders(&self, count: usize) -> Vec<FeeRecord> {
+        let mut records = self.records.clone();
+        records.sort_by_key(|record| std::cmp::Reverse(record.error().abs()));
+        records.truncate(count);
+        records
+    }
+
+    pub fn print_report(&self, path: PathBuf) -> anyhow::Result<()> {
+        let mut file = File::create(path)?;
+        let summary = self.summary();
+
+        file.write_all(format!("transactions: {}\n", summary.transactions).as_bytes())?;
+        file.write_all(format!("failed estimations: {}\n", summary.failed_estimations).as_bytes())?;
+        file.write_all(format!("underestimated: {}\n", summary.underestimated).as_bytes())?;
+        file.write_all(format!("overestimated: {}\n", summary.overestimated).as_bytes())?;
+        file.write_all(format!("exact: {}\n", summary.exact).as_bytes())?;
+        file.write_all(format!("bias: {:.2}\n", summary.bias).as_bytes())?;
+        file.write_all(
+            format!("mean absolute error: {:.2}\n", summary.mean_absolute_error).as_bytes(),
+        )?;
+        file.write_all(
+            format!(
+                "mean relative error: {:.4}%\n",
+                summary.mean_relative_error * 100.0
+            )
+            .as_bytes(),
+        )?;
+        file.write_all(
+            format!(
+                "absolute error p50: {}, p90: {}, p99: {}, max: {}\n",
+                summary.p50, summary.p90, summary.p99, summary.max
+            )
+            .as_bytes(),
+        )?;
+
+        file.write_all("worst offenders:\n".as_bytes())?;
+        for record in self.worst_offenders(WORST_OFFENDERS) {
+            file.write_all(
+                format!(
+                    "tx_number: {}, estimated: {}, actual: {}, error: {}\n",
+                    record.tx_number,
+                    record.estimated,
+                    record.actual,
+                    record.error()
+                )
+                .as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_utils::fee_accuracy::{FeeAccuracyAccumulator, FeeRecord};
+
+    #[test]
+    fn summary_works() {
+        let mut acc = FeeAccuracyAccumulator::default();
+        acc.add_estimation(0, 100, 100);
+        acc.add_estimation(1, 110, 100);
+        acc.add_estimation(2, 70, 100);
+        acc.add_estimation(3, 100, 100);
+        acc.add_failed_estimation();
+
+        let summary = acc.summary();
+        assert_eq!(summary.transactions, 4);
+        assert_eq!(summary.failed_estimations, 1);
+        assert_eq!(summary.underestimated, 1);
+        assert_eq!(summary.overestimated, 1);
+        assert_eq!(summary.exact, 2);
+        assert_eq!(summary.bias, -5.0);
+        assert_eq!(summary.mean_absolute_error, 10.0);
+        assert_eq!(summary.p50, 0);
+        assert_eq!(summary.max, 30);
+
+        assert_eq!(
+            acc.worst_offenders(1),
+            vec![FeeRecord {
+                tx_number: 2,
+                estimated: 70,
+                actual: 100
+            }]
+        );
+    }
+
+    #[test]
+    fn empty_summary() {
+        let summary = FeeAccuracyAccumulator::default().summary();
+        assert_eq!(summary.transactions, 0);
+        assert_eq!(summary.bias, 0.0);
+        assert_eq!(summary.max, 0);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::bench_utils::address_mapper::CardanoDataMapper;
+use crate::bench_utils::transaction_limits::output_size;
+use crate::bench_utils::tx_reconstruction::to_cml_output;
+use anyhow::anyhow;
+use cardano_multiplatform_lib::ledger::babbage::min_ada::min_ada_required;
+use cardano_multiplatform_lib::ledger::common::value::BigNum;
+use dcspark_core::tx::UTxOBuilder;
+use std::str::FromStr;
+
+// constant overhead of the utxo entry added by the ledger to the serialized output size
+const UTXO_ENTRY_OVERHEAD: u64 = 160;
+
+#[derive(Debug, Clone)]
+pub struct MinAdaValidation {
+    pub coins_per_utxo_byte: u64,
+    // treat selections producing changes below min ada as failed ones
+    pub reject_selection: bool,
+    // the outputs have the real addresses of the cml mapper, so cml computes the min ada
+    pub use_cml: bool,
+}
+
+impl MinAdaValidation {
+    pub fn min_ada_required<DataMapper: CardanoDataMapper>(
+        &self,
+        output: &UTxOBuilder,
+        mapper: &mut DataMapper,
+    ) -> anyhow::Result<u64> {
+        if self.use_cml {
+            let output = to_cml_output(&output.address, &output.value, &output.assets)?;
+            return min_ada_required(&output, &BigNum::from(self.coins_per_utxo_byte))
+                .map(u64::from)
+                .map_err(|err| anyhow!("can't compute min ada: {:?}", err));
+        }
+        let size = output_size(&output.address, &output.value, &output.assets, mapper)? as u64;
+        Ok((UTXO_ENTRY_OVERHEAD + size) * self.coins_per_utxo_byte)
+    }
+
+    pub fn count_violations<DataMapper: CardanoDataMapper>(
+        &self,
+        outputs: &[UTxOBuilder],
+        mapper: &mut DataMapper,
+    ) -> anyhow::Result<u64> {
+        let mut violations = 0;
+        for output in outputs.iter() {
+            let value = u64::from_str(output.value.to_string().as_str())?;
+            if value < self.min_ada_required(output, mapper)? {
+                violations += 1;
+            }
+        }
+        Ok(violations)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MinAdaStats {
+    pub checked_outputs: u64,
+    pub fixed_output_violations: u64,
+    pub change_violations: u64,
+    pub rejected_selections: u64,
+}
+
+impl MinAdaStats {
+    pub fn to_report(&self) -> String {
+        format!(
+            "min ada checked outputs: {}\nmin ada fixed output violations: {}\nmin ada change violations: {}\nmin ada rejected selections: {}\n",
+            self.checked_outputs,
+            self.fixed_output_violations,
+            self.change_violations,
+            self.rejected_selections,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_utils::address_mapper::StringAddressMapper;
+    use crate::bench_utils::min_ada::MinAdaValidation;
+    use dcspark_core::tx::{TransactionAsset, UTxOBuilder};
+    use dcspark_core::{Address, AssetName, PolicyId, TokenId, Value};
+
+    #[test]
+    fn ada_only_output() {
+        let mut mapper = StringAddressMapper::default();
+        let validation = MinAdaValidation {
+            coins_per_utxo_byte: 4310,
+            reject_selection: false,
+            use_cml: false,
+        };
+        let output = UTxOBuilder::new(Address::new("0:1"), Value::from(1_000_000), vec![]);
+        // 160 + (1 + 59 + 5) bytes
+        assert_eq!(
+            validation.min_ada_required(&output, &mut mapper).unwrap(),
+            225 * 4310
+        );
+        assert_eq!(
+            validation
+                .count_violations(&[output.clone()], &mut mapper)
+                .unwrap(),
+            0
+        );
+
+        let small = UTxOBuilder::new(Address::new("0:1"), Value::from(900_000), vec![]);
+        assert_eq!(
+            validation
+                .count_violations(&[output, small], &mut mapper)
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn assets_require_more_ada() {
+        let mut mapper = StringAddressMapper::default();
+        let validation = MinAdaValidation {
+            coins_per_utxo_byte: 4310,
+            reject_selection: false,
+            use_cml: false,
+        };
+        let ada_only = UTxOBuilder::new(Address::new("0:1"), Value::from(1_000_000), vec![]);
+        let with_assets = UTxOBuilder::new(
+            Address::new("0:1"),
+            Value::from(1_000_000),
+            vec![TransactionAsset {
+                policy_id: PolicyId::new(hex::encode([0u8; 28])),
+                asset_name: AssetName::new(hex::encode("token")),
+                fingerprint: TokenId::new("0:0"),
+                quantity: Value::from(100),
+            }],
+        );
+        assert!(
+            validation
+                .min_ada_required(&with_assets, &mut mapper)
+                .unwrap()
+                > validation.min_ada_required(&ada_only, &mut mapper).unwrap()
+        );
+        assert_eq!(
+            validation
+                .count_violations(&[with_assets], &mut mapper)
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn cml_min_ada() {
+        let mut mapper = StringAddressMapper::default();
+        let validation = MinAdaValidation {
+            coins_per_utxo_byte: 4310,
+            reject_selection: false,
+            use_cml: true,
+        };
+        let address = Address::new("addr1qx2kd28nq8ac5prwg32hhvudlwggpgfp8utlyqxu6wqgz62f79qsdmm5dsknt9ecr5w468r9ey0fxwkdrwh08ly3tu9sy0f4qd");
+        let output = UTxOBuilder::new(address.clone(), Value::from(1_000_000), vec![]);
+        let small = UTxOBuilder::new(address, Value::from(900_000), vec![]);
+        assert_eq!(
+            validation
+                .count_violations(&[output, small], &mut mapper)
+                .unwrap(),
+            1
+        );
+    }
+}
@@ -1,8 +1,17 @@
 pub mod address_mapper;
 pub mod balance_accumulator;
 pub mod balance_verification;
+pub mod batching;
 pub mod change_extraction;
+pub mod fee_accuracy;
+pub mod min_ada;
+pub mod network_parameters;
 pub mod output_utils;
 pub mod selection_eligibility;
+pub mod selection_snapshot;
+pub mod selection_timing;
 pub mod stats_accumulator;
+pub mod token_spread;
+pub mod transaction_limits;
+pub mod tx_reconstruction;
 pub mod utxo_accumulator;
@@ -0,0 +1,55 @@
+use anyhow::anyhow;
+use cardano_multiplatform_lib::builders::tx_builder::{
+    TransactionBuilderConfig, TransactionBuilderConfigBuilder,
+};
+use cardano_multiplatform_lib::ledger::alonzo::fees::LinearFee;
+use cardano_multiplatform_lib::ledger::common::value::BigNum;
+use cardano_multiplatform_lib::plutus::ExUnitPrices;
+use cardano_multiplatform_lib::UnitInterval;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CardanoNetworkParameters {
+    pub coefficient: BigNum,
+    pub constant: BigNum,
+    pub pool_deposit: BigNum,
+    pub key_deposit: BigNum,
+    pub max_value_size: u32,
+    pub max_tx_size: u32,
+    pub coins_per_utxo_byte: BigNum,
+    pub ex_unit_mem_price_numerator: BigNum,
+    pub ex_unit_mem_price_denominator: BigNum,
+    pub ex_unit_step_price_numerator: BigNum,
+    pub ex_unit_step_price_denominator: BigNum,
+    pub collateral_percentage: u32,
+    pub max_collateral_inputs: u32,
+}
+
+pub fn transaction_builder_config(
+    parameters: &CardanoNetworkParameters,
+) -> anyhow::Result<TransactionBuilderConfig> {
+    let linear_fee = LinearFee::new(&parameters.coefficient, &parameters.constant);
+
+    TransactionBuilderConfigBuilder::new()
+        .fee_algo(&linear_fee)
+        .pool_deposit(&parameters.pool_deposit)
+        .key_deposit(&parameters.key_deposit)
+        .max_value_size(parameters.max_value_size)
+        .max_tx_size(parameters.max_tx_size)
+        .coins_per_utxo_byte(&parameters.coins_per_utxo_byte)
+        .ex_unit_prices(&ExUnitPrices::new(
+            &UnitInterval::new(
+                &parameters.ex_unit_mem_price_numerator,
+                &parameters.ex_unit_mem_price_denominator,
+            ),
+            &UnitInterval::new(
+                &parameters.ex_unit_step_price_numerator,
+                &parameters.ex_unit_step_price_denominator,
+            ),
+        ))
+        .collateral_percentage(parameters.collateral_percentage)
+        .max_collateral_inputs(parameters.max_collateral_inputs)
+        .build()
+        .map_err(|err| anyhow!("can't build tx builder: {}", err))
+}
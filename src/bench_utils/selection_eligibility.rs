@@ -1,12 +1,41 @@
 use crate::tx_event::TxOutput;
-use std::collections::HashSet;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    // bans
+    Manual,
+    NoStakingKeyInInputs,
+    MultipleStakingKeysInInputs,
+    BannedKeyInInputs,
+    // insolvency
+    InitialSelection,
+    BalanceChange,
+    BalanceChangeDisabled,
+    TransactionLimits,
+    MinAda,
+    InputOfFailedTransaction,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailureRecord {
+    pub staking_key: u64,
+    pub reason: FailureReason,
+    pub tx_number: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 pub struct SelectionEligibility {
     insolvent_staking_keys: HashSet<u64>,
     banned_staking_keys: HashSet<u64>,
+    // first ban / insolvency of each key
+    failures: Vec<FailureRecord>,
 
     staking_keys_of_interest: HashSet<u64>,
     allow_all_stake_keys: bool,
@@ -17,6 +46,7 @@ impl Default for SelectionEligibility {
         Self {
             insolvent_staking_keys: Default::default(),
             banned_staking_keys: Default::default(),
+            failures: Default::default(),
             staking_keys_of_interest: Default::default(),
             allow_all_stake_keys: true,
         }
@@ -46,11 +76,15 @@ impl SelectionEligibility {
      * - with more than one staking key in inputs
      * - with no staking key in inputs
      */
-    pub fn should_perform_selection(&mut self, inputs: &[TxOutput]) -> Option<(Vec<u64>, u64)> {
+    pub fn should_perform_selection(
+        &mut self,
+        inputs: &[TxOutput],
+        tx_number: u64,
+    ) -> Option<(Vec<u64>, u64)> {
         let mut seen_keys = HashSet::<u64>::new();
         let mut payment_keys: Vec<u64> = vec![];
 
-        let mut should_ban = false;
+        let mut ban_reason = None;
 
         for input in inputs.iter() {
             match input.address {
@@ -59,30 +93,30 @@ impl SelectionEligibility {
                     payment_keys.push(pk);
                 }
                 _ => {
-                    should_ban = true; // no staking key in input
+                    ban_reason = Some(FailureReason::NoStakingKeyInInputs);
                     break;
                 }
             }
         }
 
-        if seen_keys.len() != 1 {
-            should_ban = true; // more than 1 key in input
+        if ban_reason.is_none() && seen_keys.len() > 1 {
+            ban_reason = Some(FailureReason::MultipleStakingKeysInInputs);
         }
 
         let mut selected_sk: Option<(Vec<u64>, u64)> = None;
 
         if let Some(sk) = seen_keys.iter().next().cloned() {
             if self.is_banned(sk) {
-                should_ban = true;
+                ban_reason = ban_reason.or(Some(FailureReason::BannedKeyInInputs));
             } else if self.is_whitelisted(sk) {
                 selected_sk = Some((payment_keys, sk));
             }
         } else {
-            should_ban = true;
+            ban_reason = ban_reason.or(Some(FailureReason::NoStakingKeyInInputs));
         }
 
-        if should_ban {
-            self.ban_keys_from_inputs(inputs);
+        if let Some(reason) = ban_reason {
+            self.ban_keys_from_inputs(inputs, reason, tx_number);
             None
         } else {
             selected_sk
@@ -96,21 +130,48 @@ impl SelectionEligibility {
         self.allow_all_stake_keys = false;
     }
 
-    pub fn mark_key_as_insolvent(&mut self, staking_key: u64) {
+    pub fn mark_key_as_insolvent(
+        &mut self,
+        staking_key: u64,
+        reason: FailureReason,
+        tx_number: u64,
+        error: Option<String>,
+    ) {
         if self.staking_keys_of_interest.contains(&staking_key) {
             tracing::error!("staking key of interest is insolvent: {}", staking_key);
         }
-        self.insolvent_staking_keys.insert(staking_key);
+        if self.insolvent_staking_keys.insert(staking_key) {
+            self.failures.push(FailureRecord {
+                staking_key,
+                reason,
+                tx_number: Some(tx_number),
+                error,
+            });
+        }
     }
 
     pub fn ban_key(&mut self, staking_key: u64) {
-        self.banned_staking_keys.insert(staking_key);
+        if self.banned_staking_keys.insert(staking_key) {
+            self.failures.push(FailureRecord {
+                staking_key,
+                reason: FailureReason::Manual,
+                tx_number: None,
+                error: None,
+            });
+        }
     }
 
-    fn ban_keys_from_inputs(&mut self, inputs: &[TxOutput]) {
+    fn ban_keys_from_inputs(&mut self, inputs: &[TxOutput], reason: FailureReason, tx_number: u64) {
         for input in inputs.iter() {
             if let Some((_, Some(sk))) = input.address {
-                self.banned_staking_keys.insert(sk);
+                if self.banned_staking_keys.insert(sk) {
+                    self.failures.push(FailureRecord {
+                        staking_key: sk,
+                        reason,
+                        tx_number: Some(tx_number),
+                        error: None,
+                    });
+                }
             }
         }
     }
@@ -138,11 +199,31 @@ impl SelectionEligibility {
     pub fn total_insolvent_addresses(&self) -> usize {
         self.insolvent_staking_keys.len()
     }
+
+    pub fn print_failures(&self, path: PathBuf) -> anyhow::Result<()> {
+        let mut file = File::create(path)?;
+        for failure in self.failures.iter() {
+            file.write_all(format!("{}\n", serde_json::to_string(failure)?).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn failures(&self) -> &[FailureRecord] {
+        &self.failures
+    }
+
+    pub fn failures_by_reason(&self) -> BTreeMap<FailureReason, usize> {
+        let mut result = BTreeMap::new();
+        for failure in self.failures.iter() {
+            *result.entry(failure.reason).or_default() += 1;
+        }
+        result
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::bench_utils::selection_eligibility::SelectionEligibility;
+    use crate::bench_utils::selection_eligibility::{FailureReason, SelectionEligibility};
     use crate::tx_event::TxOutput;
 
     #[test]
@@ -166,7 +247,7 @@ mod tests {
         assert!(!criteria.is_whitelisted(0));
         assert!(criteria.is_whitelisted(1));
         assert!(!criteria.is_banned(1));
-        criteria.mark_key_as_insolvent(1);
+        criteria.mark_key_as_insolvent(1, FailureReason::InitialSelection, 0, None);
         assert!(criteria.is_banned(1));
         assert!(criteria.is_whitelisted(1));
     }
@@ -197,14 +278,18 @@ mod tests {
                     assets: vec![],
                 })
                 .collect::<Vec<_>>(),
+            0,
         );
         assert!(result.is_none());
         assert!(keys.iter().map(|key| criteria.is_banned(*key)).all(|s| s));
-        let result = criteria.should_perform_selection(&[TxOutput {
-            address: Some((11, Some(0))),
-            value: Default::default(),
-            assets: vec![],
-        }]);
+        let result = criteria.should_perform_selection(
+            &[TxOutput {
+                address: Some((11, Some(0))),
+                value: Default::default(),
+                assets: vec![],
+            }],
+            1,
+        );
         assert!(result.is_none());
         assert!(keys.iter().map(|key| criteria.is_banned(*key)).all(|s| s));
     }
@@ -223,18 +308,52 @@ mod tests {
                     assets: vec![],
                 })
                 .collect::<Vec<_>>(),
+            0,
         );
         assert!(result.is_some());
         assert_eq!(result.unwrap(), (vec![2, 3], 0));
         assert!(!criteria.is_banned(0));
-        let result = criteria.should_perform_selection(&[TxOutput {
-            address: Some((0, Some(0))),
-            value: Default::default(),
-            assets: vec![],
-        }]);
+        let result = criteria.should_perform_selection(
+            &[TxOutput {
+                address: Some((0, Some(0))),
+                value: Default::default(),
+                assets: vec![],
+            }],
+            1,
+        );
 
         assert!(result.is_some());
         assert_eq!(result.unwrap(), (vec![0], 0));
         assert!(!criteria.is_banned(0));
     }
+
+    #[test]
+    fn failures_are_recorded_once() {
+        let mut criteria = SelectionEligibility::default();
+        let inputs = [0, 1]
+            .into_iter()
+            .map(|key| TxOutput {
+                address: Some((10, Some(key))),
+                value: Default::default(),
+                assets: vec![],
+            })
+            .collect::<Vec<_>>();
+        assert!(criteria.should_perform_selection(&inputs, 5).is_none());
+        assert!(criteria.should_perform_selection(&inputs[..1], 6).is_none());
+        criteria.mark_key_as_insolvent(
+            2,
+            FailureReason::BalanceChange,
+            7,
+            Some("not balanced".to_string()),
+        );
+        criteria.mark_key_as_insolvent(2, FailureReason::InitialSelection, 8, None);
+
+        let by_reason = criteria.failures_by_reason();
+        assert_eq!(by_reason.len(), 2);
+        assert_eq!(
+            by_reason.get(&FailureReason::MultipleStakingKeysInInputs),
+            Some(&2)
+        );
+        assert_eq!(by_reason.get(&FailureReason::BalanceChange), Some(&1));
+    }
 }
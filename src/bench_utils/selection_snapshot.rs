@@ -0,0 +1,270 @@
+use crate::bench_utils::output_utils::builders_to_utxo_details;
+use anyhow::anyhow;
+use dcspark_core::tx::{TransactionAsset, UTxOBuilder};
+use dcspark_core::{Address, AssetName, PolicyId, Regulated, TokenId, UTxOStore, Value};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SelectionSnapshotConfig {
+    path: PathBuf,
+    // every n-th selection is dumped
+    #[serde(default = "default_sample_rate")]
+    sample_rate: u64,
+    // smaller utxo stores are skipped
+    #[serde(default)]
+    min_inputs: usize,
+}
+
+fn default_sample_rate() -> u64 {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotAsset {
+    pub policy_id: String,
+    pub asset_name: String,
+    pub fingerprint: String,
+    pub quantity: Value<Regulated>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotOutput {
+    pub address: String,
+    pub value: Value<Regulated>,
+    pub assets: Vec<SnapshotAsset>,
+}
+
+impl From<&UTxOBuilder> for SnapshotOutput {
+    fn from(builder: &UTxOBuilder) -> Self {
+        SnapshotOutput {
+            address: builder.address.as_ref().to_string(),
+            value: builder.value.clone(),
+            assets: builder
+                .assets
+                .iter()
+                .map(|asset| SnapshotAsset {
+                    policy_id: asset.policy_id.as_ref().to_string(),
+                    asset_name: asset.asset_name.as_ref().to_string(),
+                    fingerprint: asset.fingerprint.as_ref().to_string(),
+                    quantity: asset.quantity.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&SnapshotOutput> for UTxOBuilder {
+    fn from(output: &SnapshotOutput) -> Self {
+        UTxOBuilder::new(
+            Address::new(output.address.clone()),
+            output.value.clone(),
+            output
+                .assets
+                .iter()
+                .map(|asset| TransactionAsset {
+                    policy_id: PolicyId::new(asset.policy_id.clone()),
+                    asset_name: AssetName::new(asset.asset_name.clone()),
+                    fingerprint: TokenId::new(asset.fingerprint.clone()),
+                    quantity: asset.quantity.clone(),
+                })
+                .collect(),
+        )
+    }
+}
+
+// inputs of a selection as they were passed to the algorithm, addresses and assets are mapped already
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelectionSnapshot {
+    pub tx_number: u64,
+    pub stake_key: u64,
+    pub change_address: String,
+    pub available_inputs: Vec<SnapshotOutput>,
+    pub fixed_outputs: Vec<SnapshotOutput>,
+}
+
+impl SelectionSnapshot {
+    pub fn new(
+        tx_number: u64,
+        stake_key: u64,
+        change_address: &Address,
+        available_inputs: &UTxOStore,
+        fixed_outputs: &[UTxOBuilder],
+    ) -> Self {
+        Self {
+            tx_number,
+            stake_key,
+            change_address: change_address.as_ref().to_string(),
+            available_inputs: available_inputs
+                .iter_ordered_by_wmain()
+                .map(|utxo| {
+                    SnapshotOutput::from(&UTxOBuilder::new(
+                        utxo.address.clone(),
+                        utxo.value.clone(),
+                        utxo.assets.clone(),
+                    ))
+                })
+                .collect(),
+            fixed_outputs: fixed_outputs.iter().map(SnapshotOutput::from).collect(),
+        }
+    }
+
+    // pointers are not kept, the inputs get the ones of the outputs of the snapshot's tx number
+    pub fn available_inputs(&self) -> anyhow::Result<UTxOStore> {
+        let builders = self
+            .available_inputs
+            .iter()
+            .map(UTxOBuilder::from)
+            .collect();
+        let mut store = UTxOStore::new().thaw();
+        for utxo in builders_to_utxo_details(self.tx_number, builders)? {
+            store.insert(utxo)?;
+        }
+        Ok(store.freeze())
+    }
+
+    pub fn fixed_outputs(&self) -> Vec<UTxOBuilder> {
+        self.fixed_outputs.iter().map(UTxOBuilder::from).collect()
+    }
+
+    pub fn change_address(&self) -> Address {
+        Address::new(self.change_address.clone())
+    }
+}
+
+pub struct SelectionSnapshotWriter {
+    file: BufWriter<File>,
+    sample_rate: u64,
+    min_inputs: usize,
+    seen: u64,
+    written: u64,
+}
+
+impl SelectionSnapshotWriter {
+    pub fn create(config: SelectionSnapshotConfig) -> anyhow::Result<Self> {
+        if config.sample_rate == 0 {
+            return Err(anyhow!("snapshot sample rate must be greater than zero"));
+        }
+        Ok(Self {
+            file: BufWriter::new(File::create(config.path)?),
+            sample_rate: config.sample_rate,
+            min_inputs: config.min_inputs,
+            seen: 0,
+            written: 0,
+        })
+    }
+
+    pub fn add(
+        &mut self,
+        tx_number: u64,
+        stake_key: u64,
+        change_address: &Address,
+        available_inputs: &UTxOStore,
+        fixed_outputs: &[UTxOBuilder],
+    ) -> anyhow::Result<()> {
+        if available_inputs.len() < self.min_inputs {
+            return Ok(());
+        }
+        self.seen += 1;
+        if (self.seen - 1) % self.sample_rate != 0 {
+            return Ok(());
+        }
+        let snapshot = SelectionSnapshot::new(
+            tx_number,
+            stake_key,
+            change_address,
+            available_inputs,
+            fixed_outputs,
+        );
+        self.file
+            .write_all(format!("{}\n", serde_json::to_string(&snapshot)?).as_bytes())?;
+        self.written += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<u64> {
+        self.file.flush()?;
+        Ok(self.written)
+    }
+}
+
+pub fn read_snapshots(path: PathBuf) -> anyhow::Result<Vec<SelectionSnapshot>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_utils::output_utils::builders_to_utxo_details;
+    use crate::bench_utils::selection_snapshot::{
+        read_snapshots, SelectionSnapshotConfig, SelectionSnapshotWriter, SnapshotOutput,
+    };
+    use dcspark_core::tx::{TransactionAsset, UTxOBuilder};
+    use dcspark_core::{Address, AssetName, PolicyId, TokenId, UTxOStore, Value};
+
+    fn store(values: &[u64]) -> UTxOStore {
+        let builders = values
+            .iter()
+            .map(|value| {
+                UTxOBuilder::new(
+                    Address::new("1:1"),
+                    Value::from(*value),
+                    vec![TransactionAsset {
+                        policy_id: PolicyId::new("2"),
+                        asset_name: AssetName::new("3"),
+                        fingerprint: TokenId::new("2:3"),
+                        quantity: Value::from(5),
+                    }],
+                )
+            })
+            .collect();
+        let mut store = UTxOStore::new().thaw();
+        for utxo in builders_to_utxo_details(1, builders).unwrap() {
+            store.insert(utxo).unwrap();
+        }
+        store.freeze()
+    }
+
+    #[test]
+    fn snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("selection_snapshots.jsonl");
+        let config: SelectionSnapshotConfig = serde_yaml::from_str(&format!(
+            "path: {}\nsample_rate: 2\nmin_inputs: 2",
+            path.display()
+        ))
+        .unwrap();
+        let mut writer = SelectionSnapshotWriter::create(config).unwrap();
+        let output = UTxOBuilder::new(Address::new("4:4"), Value::from(7), vec![]);
+        let change = Address::new("1:1");
+        // too small, then the first, skipped and third eligible selections
+        writer.add(1, 1, &change, &store(&[1]), &[]).unwrap();
+        writer
+            .add(2, 1, &change, &store(&[1, 2]), &[output.clone()])
+            .unwrap();
+        writer.add(3, 1, &change, &store(&[1, 2]), &[]).unwrap();
+        writer.add(4, 1, &change, &store(&[1, 2, 3]), &[]).unwrap();
+        assert_eq!(writer.finish().unwrap(), 2);
+
+        let snapshots = read_snapshots(path).unwrap();
+        assert_eq!(
+            snapshots.iter().map(|s| s.tx_number).collect::<Vec<_>>(),
+            vec![2, 4]
+        );
+        assert_eq!(
+            snapshots[0].fixed_outputs,
+            vec![SnapshotOutput::from(&output)]
+        );
+        assert_eq!(snapshots[0].change_address().as_ref(), "1:1");
+        let inputs = snapshots[1].available_inputs().unwrap();
+        assert_eq!(inputs.len(), 3);
+        assert!(inputs
+            .iter_ordered_by_wmain()
+            .all(|utxo| utxo.assets[0].fingerprint == TokenId::new("2:3")));
+    }
+}
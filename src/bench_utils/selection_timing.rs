@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectionTimingSummary {
+    pub selections: usize,
+    pub total: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+// each power of two of nanoseconds is split into this many buckets, so percentiles are off by 1/8 at most
+const SUB_BUCKETS: u64 = 8;
+const SUB_BUCKET_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+
+fn bucket_index(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS {
+        return nanos as usize;
+    }
+    let exponent = u64::BITS - 1 - nanos.leading_zeros();
+    let sub_bucket = (nanos >> (exponent - SUB_BUCKET_BITS)) - SUB_BUCKETS;
+    ((exponent - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS + sub_bucket) as usize
+}
+
+// largest duration of the bucket
+fn bucket_upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    let lower = (SUB_BUCKETS + index % SUB_BUCKETS) << shift;
+    lower + ((1 << shift) - 1)
+}
+
+// wall-clock time of every selection, limits checks and retries with split outputs included.
+// durations are counted in log-scale buckets, so the memory doesn't grow with the number of selections
+#[derive(Default)]
+pub struct SelectionTiming {
+    selections: usize,
+    total: Duration,
+    max: Duration,
+    buckets: Vec<u64>,
+}
+
+impl SelectionTiming {
+    pub fn add(&mut self, duration: Duration) {
+        self.selections += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+
+        let index = bucket_index(u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX));
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, 0);
+        }
+        self.buckets[index] += 1;
+    }
+
+    // upper bound of the bucket holding the duration of the given rank, never above the max
+    fn percentile(&self, p: usize) -> Duration {
+        if self.selections == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((self.selections - 1) * p / 100) as u64;
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen > rank {
+                return Duration::from_nanos(bucket_upper_bound(index)).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn summary(&self) -> SelectionTimingSummary {
+        SelectionTimingSummary {
+            selections: self.selections,
+            total: self.total,
+            mean: self.total / self.selections.max(1) as u32,
+            p50: self.percentile(50),
+            p90: self.percentile(90),
+            p99: self.percentile(99),
+            max: self.max,
+        }
+    }
+
+    pub fn to_report(&self) -> String {
+        let summary = self.summary();
+        format!(
+            "selection time total: {:?}, mean: {:?}, p50: {:?}, p90: {:?}, p99: {:?}, max: {:?}\n",
+            summary.total, summary.mean, summary.p50, summary.p90, summary.p99, summary.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_utils::selection_timing::{bucket_index, bucket_upper_bound, SelectionTiming};
+    use std::time::Duration;
+
+    #[test]
+    fn buckets() {
+        for nanos in [0, 1, 7, 8, 9, 15, 16, 17, 1000, 3_000_000, u64::MAX] {
+            let index = bucket_index(nanos);
+            assert!(bucket_upper_bound(index) >= nanos);
+            assert!(bucket_upper_bound(index) - nanos <= nanos / 8);
+            if index > 0 {
+                assert!(bucket_upper_bound(index - 1) < nanos);
+            }
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let mut timing = SelectionTiming::default();
+        assert_eq!(timing.summary().mean, Duration::ZERO);
+        assert_eq!(timing.summary().p50, Duration::ZERO);
+
+        for millis in [4, 1, 3, 2, 10] {
+            timing.add(Duration::from_millis(millis));
+        }
+        let summary = timing.summary();
+        assert_eq!(summary.selections, 5);
+        assert_eq!(summary.total, Duration::from_millis(20));
+        assert_eq!(summary.mean, Duration::from_millis(4));
+        assert_eq!(summary.max, Duration::from_millis(10));
+
+        let close_to = |duration: Duration, millis: u64| {
+            duration >= Duration::from_millis(millis)
+                && duration <= Duration::from_millis(millis) * 9 / 8
+        };
+        assert!(close_to(summary.p50, 3));
+        assert!(close_to(summary.p99, 4));
+    }
+}
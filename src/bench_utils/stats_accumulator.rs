@@ -1,34 +1,193 @@
-use std::collections::HashMap;
+use crate::parquet_export::{infer_fields, ParquetRecordWriter};
+use anyhow::anyhow;
+use arrow::datatypes::{DataType, Field};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 
-#[derive(Default)]
-pub struct StatsAccumulator<T: ToString> {
-    points: HashMap<u64, Vec<(u64, T)>>,
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum StatsSampling {
+    // a point per line of the events file
+    #[default]
+    EveryLine,
+    EveryNLines(u64),
+    // a point when the value of the key differs from the last written one
+    OnChange,
+    // a point after the events which have inputs or outputs of the key
+    OnTouch,
 }
 
-impl<T: ToString> StatsAccumulator<T> {
-    pub fn add_stats(&mut self, stake_key: u64, point: u64, data: T) {
-        let point_of_stake = self.points.entry(stake_key).or_insert(vec![]);
-        point_of_stake.push((point, data));
+pub trait StatsRecord: Serialize + Default + PartialEq {
+    // names of the serialized fields, in the order of the csv and parquet columns
+    fn columns() -> &'static [&'static str];
+
+    // records of a key with different series (e.g. tokens) are sampled independently
+    fn series(&self) -> Option<&str> {
+        None
     }
+}
 
-    pub fn dump_stats(&self, path: PathBuf, format: String) -> anyhow::Result<()> {
-        let mut stats = File::create(path)?;
-        stats.write_all(format!("stake_key,index,{}\n", format).as_bytes())?;
-        for (stake_key, points) in self.points.iter() {
-            for (index, data) in points {
-                stats.write_all(
-                    format!("{},{},{}\n", stake_key, index, data.to_string()).as_bytes(),
-                )?;
-            }
+// points are written as they come, a csv row per point: stake_key,index,<fields of T>
+pub struct StatsAccumulator<T: StatsRecord> {
+    file: BufWriter<File>,
+    sampling: StatsSampling,
+    // last written values, kept only for `OnChange`
+    last: HashMap<(u64, Option<String>), T>,
+    touched: HashSet<u64>,
+    parquet: Option<ParquetRecordWriter>,
+}
+
+impl<T: StatsRecord> StatsAccumulator<T> {
+    pub fn create(path: PathBuf, sampling: StatsSampling) -> anyhow::Result<Self> {
+        if sampling == StatsSampling::EveryNLines(0) {
+            return Err(anyhow!("stats sampling interval must be greater than zero"));
+        }
+        // columns that don't match the fields of the record fail before anything is written
+        record_values(&T::default())?;
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(format!("stake_key,index,{}\n", T::columns().join(",")).as_bytes())?;
+        Ok(Self {
+            file,
+            sampling,
+            last: HashMap::default(),
+            touched: HashSet::default(),
+            parquet: None,
+        })
+    }
+
+    // the points are also written to a parquet file
+    pub fn with_parquet(mut self, path: PathBuf) -> anyhow::Result<Self> {
+        let mut fields = vec![
+            Field::new("stake_key", DataType::UInt64, false),
+            Field::new("index", DataType::UInt64, false),
+        ];
+        fields.extend(infer_fields(&record_values(&T::default())?)?);
+        self.parquet = Some(ParquetRecordWriter::create(path, fields)?);
+        Ok(self)
+    }
+
+    // staking keys of the last processed event
+    pub fn touch(&mut self, stake_keys: HashSet<u64>) {
+        self.touched = stake_keys;
+    }
+
+    // false when the point is dropped whatever its data is, so it doesn't need to be built
+    pub fn samples(&self, stake_key: u64, point: u64) -> bool {
+        match &self.sampling {
+            StatsSampling::EveryLine | StatsSampling::OnChange => true,
+            StatsSampling::EveryNLines(lines) => point % lines == 0,
+            StatsSampling::OnTouch => self.touched.contains(&stake_key),
+        }
+    }
+
+    pub fn add_stats(&mut self, stake_key: u64, point: u64, data: T) -> anyhow::Result<()> {
+        let should_write = self.samples(stake_key, point)
+            && (self.sampling != StatsSampling::OnChange
+                || self.last.get(&last_key(stake_key, &data)) != Some(&data));
+        if should_write {
+            self.write(stake_key, point, data)?;
+        }
+        Ok(())
+    }
+
+    // last point of the key is written whatever the sampling is
+    pub fn add_final_stats(&mut self, stake_key: u64, point: u64, data: T) -> anyhow::Result<()> {
+        if self.sampling == StatsSampling::OnChange
+            && self.last.get(&last_key(stake_key, &data)) == Some(&data)
+        {
+            return Ok(());
+        }
+        self.write(stake_key, point, data)
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.file.flush()?;
+        if let Some(parquet) = self.parquet {
+            parquet.finish()?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, stake_key: u64, point: u64, data: T) -> anyhow::Result<()> {
+        let values = record_values(&data)?;
+        self.file.write_all(
+            format!(
+                "{},{},{}\n",
+                stake_key,
+                point,
+                csv_values(&values)?.join(",")
+            )
+            .as_bytes(),
+        )?;
+        if let Some(parquet) = self.parquet.as_mut() {
+            let mut row = Map::new();
+            row.insert("stake_key".to_string(), stake_key.into());
+            row.insert("index".to_string(), point.into());
+            row.extend(
+                values
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value)),
+            );
+            parquet.write_row(row)?;
+        }
+        if self.sampling == StatsSampling::OnChange {
+            self.last.insert(last_key(stake_key, &data), data);
         }
         Ok(())
     }
 }
 
-#[derive(Debug, Default, Clone)]
+fn last_key<T: StatsRecord>(stake_key: u64, data: &T) -> (u64, Option<String>) {
+    (stake_key, data.series().map(str::to_string))
+}
+
+fn record_fields<T: Serialize>(data: &T) -> anyhow::Result<Map<String, Value>> {
+    match serde_json::to_value(data)? {
+        Value::Object(fields) => Ok(fields),
+        _ => Err(anyhow!("stats records must be structs")),
+    }
+}
+
+// fields of the record in the order of its columns, every field has to be listed
+fn record_values<T: StatsRecord>(data: &T) -> anyhow::Result<Vec<(&'static str, Value)>> {
+    let mut fields = record_fields(data)?;
+    let values = T::columns()
+        .iter()
+        .map(|name| {
+            fields
+                .remove(*name)
+                .map(|value| (*name, value))
+                .ok_or_else(|| anyhow!("stats column {} is not a field of the record", name))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if let Some(name) = fields.keys().next() {
+        return Err(anyhow!("stats field {} is not listed in the columns", name));
+    }
+    Ok(values)
+}
+
+fn csv_values(fields: &[(&str, Value)]) -> anyhow::Result<Vec<String>> {
+    fields
+        .iter()
+        .map(|(name, value)| match value {
+            Value::Null => Ok(String::new()),
+            Value::Bool(value) => Ok(value.to_string()),
+            Value::Number(value) => Ok(value.to_string()),
+            Value::String(value) if value.contains([',', '"', '\n']) => {
+                Ok(format!("\"{}\"", value.replace('"', "\"\"")))
+            }
+            Value::String(value) => Ok(value.clone()),
+            _ => Err(anyhow!("stats field {} is not a scalar", name)),
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
 pub struct BalanceStats {
     pub ada_computed: i64,
     pub ada_actual: i64,
@@ -36,21 +195,201 @@ pub struct BalanceStats {
     pub fee_actual: i64,
 }
 
-impl ToString for BalanceStats {
-    fn to_string(&self) -> String {
-        format!(
-            "{},{},{},{}",
-            self.ada_computed, self.ada_actual, self.fee_computed, self.fee_actual
-        )
+impl StatsRecord for BalanceStats {
+    fn columns() -> &'static [&'static str] {
+        &["ada_computed", "ada_actual", "fee_computed", "fee_actual"]
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct UtxoCountStats {
+    pub utxo_count: u64,
+}
+
+impl StatsRecord for UtxoCountStats {
+    fn columns() -> &'static [&'static str] {
+        &["utxo_count"]
+    }
+}
+
+// native token of the key, utxos are the ones holding the token
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct TokenStats {
+    pub token: String,
+    pub balance_computed: i64,
+    pub balance_actual: i64,
+    pub utxos_computed: u64,
+    pub utxos_actual: u64,
+}
+
+impl StatsRecord for TokenStats {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "token",
+            "balance_computed",
+            "balance_actual",
+            "utxos_computed",
+            "utxos_actual",
+        ]
+    }
+
+    fn series(&self) -> Option<&str> {
+        Some(&self.token)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::bench_utils::stats_accumulator::BalanceStats;
+    use crate::bench_utils::stats_accumulator::{
+        csv_values, record_fields, record_values, BalanceStats, StatsAccumulator, StatsRecord,
+        StatsSampling, TokenStats, UtxoCountStats,
+    };
+    use serde::Serialize;
 
     #[test]
     fn check_serialize() {
-        assert_eq!(BalanceStats::default().to_string(), String::from("0,0,0,0"));
+        assert_eq!(
+            csv_values(&record_values(&BalanceStats::default()).unwrap())
+                .unwrap()
+                .join(","),
+            String::from("0,0,0,0")
+        );
+        record_values(&UtxoCountStats::default()).unwrap();
+        record_values(&TokenStats::default()).unwrap();
+
+        #[derive(Serialize, Default, PartialEq)]
+        struct Named {
+            value: Option<u64>,
+            name: String,
+        }
+        impl StatsRecord for Named {
+            fn columns() -> &'static [&'static str] {
+                &["name", "value"]
+            }
+        }
+        let named = Named {
+            name: "a,\"b\"".to_string(),
+            value: None,
+        };
+        assert_eq!(
+            csv_values(&record_values(&named).unwrap()).unwrap(),
+            vec!["\"a,\"\"b\"\"\"", ""]
+        );
+        assert!(record_fields(&1u64).is_err());
+
+        #[derive(Serialize, Default, PartialEq)]
+        struct Unlisted {
+            value: u64,
+            extra: u64,
+        }
+        impl StatsRecord for Unlisted {
+            fn columns() -> &'static [&'static str] {
+                &["value"]
+            }
+        }
+        assert!(record_values(&Unlisted::default()).is_err());
+    }
+
+    fn written(sampling: StatsSampling, touched: &[(u64, Vec<u64>)]) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.csv");
+        let mut stats = StatsAccumulator::<UtxoCountStats>::create(path.clone(), sampling).unwrap();
+        // utxo count of key 1 before the line: 0, 0, 1, 1, 2
+        for (point, utxo_count) in [0, 0, 1, 1, 2].into_iter().enumerate() {
+            stats
+                .add_stats(1, point as u64, UtxoCountStats { utxo_count })
+                .unwrap();
+            let keys = touched
+                .iter()
+                .find(|(line, _)| *line == point as u64)
+                .map(|(_, keys)| keys.iter().cloned().collect())
+                .unwrap_or_default();
+            stats.touch(keys);
+        }
+        stats
+            .add_final_stats(1, 5, UtxoCountStats { utxo_count: 2 })
+            .unwrap();
+        stats.finish().unwrap();
+        let contents = std::fs::read_to_string(path).unwrap();
+        contents.lines().skip(1).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn sampling() {
+        assert_eq!(written(StatsSampling::EveryLine, &[]).len(), 6);
+        assert_eq!(
+            written(StatsSampling::EveryNLines(2), &[]),
+            vec!["1,0,0", "1,2,1", "1,4,2", "1,5,2"]
+        );
+        assert_eq!(
+            written(StatsSampling::OnChange, &[]),
+            vec!["1,0,0", "1,2,1", "1,4,2"]
+        );
+        // events at lines 1 and 3 touch the key, so the points after them are written
+        assert_eq!(
+            written(StatsSampling::OnTouch, &[(1, vec![1]), (3, vec![1, 2])]),
+            vec!["1,2,1", "1,4,2", "1,5,2"]
+        );
+    }
+
+    #[test]
+    fn samples_before_building() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = |sampling| {
+            StatsAccumulator::<TokenStats>::create(dir.path().join("stats.csv"), sampling).unwrap()
+        };
+        let every_two = stats(StatsSampling::EveryNLines(2));
+        assert!(every_two.samples(1, 2));
+        assert!(!every_two.samples(1, 3));
+        assert!(stats(StatsSampling::OnChange).samples(1, 3));
+        let mut on_touch = stats(StatsSampling::OnTouch);
+        on_touch.touch([2].into_iter().collect());
+        assert!(on_touch.samples(2, 3));
+        assert!(!on_touch.samples(1, 3));
+    }
+
+    #[test]
+    fn rejects_zero_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.csv");
+        assert!(
+            StatsAccumulator::<BalanceStats>::create(path, StatsSampling::EveryNLines(0)).is_err()
+        );
+    }
+
+    #[test]
+    fn tokens_change_independently() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.csv");
+        let mut stats =
+            StatsAccumulator::<TokenStats>::create(path.clone(), StatsSampling::OnChange).unwrap();
+        let token = |token: &str, utxos: u64| TokenStats {
+            token: token.to_string(),
+            utxos_computed: utxos,
+            ..Default::default()
+        };
+        for (point, records) in [
+            vec![token("a", 1), token("b", 1)],
+            vec![token("a", 1), token("b", 2)],
+            vec![token("a", 1), token("b", 2)],
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            for record in records {
+                stats.add_stats(1, point as u64, record).unwrap();
+            }
+        }
+        stats.finish().unwrap();
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert_eq!(
+            contents.lines().collect::<Vec<_>>(),
+            vec![
+                "stake_key,index,token,balance_computed,balance_actual,utxos_computed,utxos_actual",
+                "1,0,a,0,0,1,0",
+                "1,0,b,0,0,1,0",
+                "1,1,b,0,0,2,0",
+            ]
+        );
     }
 }
@@ -0,0 +1,167 @@
+use crate::bench_utils::stats_accumulator::TokenStats;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+const LARGEST_DIFFERENCES: usize = 20;
+
+// positive when the algorithm spread the token over more utxos than the history did
+pub fn utxo_difference(stats: &TokenStats) -> i64 {
+    stats.utxos_computed as i64 - stats.utxos_actual as i64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenSpreadSummary {
+    pub tokens: usize,
+    pub fragmented: usize,
+    pub consolidated: usize,
+    pub unchanged: usize,
+    pub utxos_computed: u64,
+    pub utxos_actual: u64,
+    // utxos per held token
+    pub mean_spread_computed: f64,
+    pub mean_spread_actual: f64,
+}
+
+// native tokens of the keys at the end of the run
+#[derive(Default)]
+pub struct TokenSpreadAccumulator {
+    records: Vec<(u64, TokenStats)>,
+}
+
+impl TokenSpreadAccumulator {
+    // tokens that neither the algorithm nor the history hold anymore are skipped
+    pub fn add(&mut self, stake_key: u64, stats: TokenStats) {
+        if stats.utxos_computed > 0 || stats.utxos_actual > 0 {
+            self.records.push((stake_key, stats));
+        }
+    }
+
+    pub fn summary(&self) -> TokenSpreadSummary {
+        let stats = || self.records.iter().map(|(_, stats)| stats);
+        let held_computed = stats().filter(|s| s.utxos_computed > 0).count();
+        let held_actual = stats().filter(|s| s.utxos_actual > 0).count();
+        let utxos_computed = stats().map(|s| s.utxos_computed).sum();
+        let utxos_actual = stats().map(|s| s.utxos_actual).sum();
+        TokenSpreadSummary {
+            tokens: self.records.len(),
+            fragmented: stats().filter(|s| utxo_difference(s) > 0).count(),
+            consolidated: stats().filter(|s| utxo_difference(s) < 0).count(),
+            unchanged: stats().filter(|s| utxo_difference(s) == 0).count(),
+            utxos_computed,
+            utxos_actual,
+            mean_spread_computed: utxos_computed as f64 / held_computed.max(1) as f64,
+            mean_spread_actual: utxos_actual as f64 / held_actual.max(1) as f64,
+        }
+    }
+
+    // utxos holding the token summed over all keys: token -> (computed, actual)
+    pub fn by_token(&self) -> BTreeMap<String, (u64, u64)> {
+        let mut tokens = BTreeMap::<String, (u64, u64)>::new();
+        for (_, stats) in self.records.iter() {
+            let entry = tokens.entry(stats.token.clone()).or_default();
+            entry.0 += stats.utxos_computed;
+            entry.1 += stats.utxos_actual;
+        }
+        tokens
+    }
+
+    pub fn largest_differences(&self, count: usize) -> Vec<&(u64, TokenStats)> {
+        let mut records: Vec<_> = self.records.iter().collect();
+        records.sort_by_key(|(_, stats)| std::cmp::Reverse(utxo_difference(stats).abs()));
+        records.truncate(count);
+        records
+    }
+
+    pub fn to_report(&self) -> String {
+        let summary = self.summary();
+        format!(
+            "native tokens: {}, fragmented: {}, consolidated: {}, unchanged: {}\n",
+            summary.tokens, summary.fragmented, summary.consolidated, summary.unchanged
+        )
+    }
+
+    pub fn print_report(&self, path: PathBuf) -> anyhow::Result<()> {
+        let mut file = File::create(path)?;
+        let summary = self.summary();
+
+        file.write_all(format!("tokens: {}\n", summary.tokens).as_bytes())?;
+        file.write_all(format!("fragmented: {}\n", summary.fragmented).as_bytes())?;
+        file.write_all(format!("consolidated: {}\n", summary.consolidated).as_bytes())?;
+        file.write_all(format!("unchanged: {}\n", summary.unchanged).as_bytes())?;
+        file.write_all(
+            format!(
+                "utxos holding tokens computed: {}, actual: {}\n",
+                summary.utxos_computed, summary.utxos_actual
+            )
+            .as_bytes(),
+        )?;
+        file.write_all(
+            format!(
+                "utxos per held token computed: {:.2}, actual: {:.2}\n",
+                summary.mean_spread_computed, summary.mean_spread_actual
+            )
+            .as_bytes(),
+        )?;
+
+        file.write_all("\nby token (utxos computed, actual):\n".as_bytes())?;
+        for (token, (computed, actual)) in self.by_token() {
+            file.write_all(format!("{token}: {computed}, {actual}\n").as_bytes())?;
+        }
+
+        file.write_all("\nlargest differences:\n".as_bytes())?;
+        for (stake_key, stats) in self.largest_differences(LARGEST_DIFFERENCES) {
+            file.write_all(
+                format!(
+                    "stake key: {}, token: {}, utxos computed: {}, actual: {}, balance computed: {}, actual: {}\n",
+                    stake_key,
+                    stats.token,
+                    stats.utxos_computed,
+                    stats.utxos_actual,
+                    stats.balance_computed,
+                    stats.balance_actual
+                )
+                .as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_utils::stats_accumulator::TokenStats;
+    use crate::bench_utils::token_spread::TokenSpreadAccumulator;
+
+    fn stats(token: &str, utxos_computed: u64, utxos_actual: u64) -> TokenStats {
+        TokenStats {
+            token: token.to_string(),
+            utxos_computed,
+            utxos_actual,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let mut acc = TokenSpreadAccumulator::default();
+        acc.add(1, stats("a", 3, 1));
+        acc.add(1, stats("b", 1, 2));
+        acc.add(2, stats("a", 1, 1));
+        // spent both by the algorithm and the history
+        acc.add(2, stats("b", 0, 0));
+
+        let summary = acc.summary();
+        assert_eq!(summary.tokens, 3);
+        assert_eq!(summary.fragmented, 1);
+        assert_eq!(summary.consolidated, 1);
+        assert_eq!(summary.unchanged, 1);
+        assert_eq!(summary.utxos_computed, 5);
+        assert_eq!(summary.utxos_actual, 4);
+        assert!((summary.mean_spread_computed - 5.0 / 3.0).abs() < 1e-9);
+
+        assert_eq!(acc.by_token().get("a"), Some(&(4, 2)));
+        assert_eq!(acc.largest_differences(1), vec![&(1, stats("a", 3, 1))]);
+    }
+}
@@ -0,0 +1,396 @@
+use crate::bench_utils::address_mapper::CardanoDataMapper;
+use crate::bench_utils::network_parameters::CardanoNetworkParameters;
+use crate::tx_event::byron_index;
+use anyhow::anyhow;
+use dcspark_core::tx::{TransactionAsset, UTxOBuilder, UTxODetails};
+use dcspark_core::{Address, Regulated, Value};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+// body map header, fee, ttl and witness set headers
+const TX_OVERHEAD_SIZE: usize = 24;
+// array header + tx hash bytes + output index
+const INPUT_SIZE: usize = 1 + 2 + 32 + 3;
+const POLICY_ID_SIZE: usize = 2 + 28;
+const BASE_ADDRESS_SIZE: usize = 2 + 57;
+const ENTERPRISE_ADDRESS_SIZE: usize = 2 + 29;
+// legacy daedalus addresses, icarus ones are smaller
+const BYRON_ADDRESS_SIZE: usize = 2 + 76;
+// asset names are stored as hex, we take the max length if the name can't be decoded
+const MAX_ASSET_NAME_SIZE: usize = 32;
+
+fn default_witness_size() -> usize {
+    // array header + vkey bytes + signature bytes
+    1 + 34 + 66
+}
+
+fn default_max_splits() -> usize {
+    1
+}
+
+// the sizes default to the network parameters of the cml estimator
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransactionLimitsConfig {
+    #[serde(default)]
+    pub max_tx_size: Option<usize>,
+    #[serde(default)]
+    pub max_value_size: Option<usize>,
+    // size of the witnesses added per distinct payment key of the inputs
+    #[serde(default = "default_witness_size")]
+    pub witness_size: usize,
+    // oversized payments are split into at most `max_splits` transactions
+    #[serde(default = "default_max_splits")]
+    pub max_splits: usize,
+}
+
+impl TransactionLimitsConfig {
+    pub fn resolve(
+        self,
+        parameters: Option<&CardanoNetworkParameters>,
+    ) -> anyhow::Result<TransactionLimits> {
+        let max_tx_size = resolve_size(
+            "max_tx_size",
+            self.max_tx_size,
+            parameters.map(|parameters| parameters.max_tx_size as usize),
+        )?;
+        let max_value_size = resolve_size(
+            "max_value_size",
+            self.max_value_size,
+            parameters.map(|parameters| parameters.max_value_size as usize),
+        )?;
+        Ok(TransactionLimits {
+            max_tx_size,
+            max_value_size,
+            witness_size: self.witness_size,
+            max_splits: self.max_splits,
+        })
+    }
+}
+
+fn resolve_size(
+    name: &str,
+    configured: Option<usize>,
+    network: Option<usize>,
+) -> anyhow::Result<usize> {
+    match (configured, network) {
+        (Some(configured), Some(network)) => {
+            // a configured size overrides the network parameters, e.g. to test smaller limits
+            if configured != network {
+                tracing::warn!(
+                    "{} is {} while the network parameters have {}, using {}",
+                    name,
+                    configured,
+                    network,
+                    configured
+                );
+            }
+            Ok(configured)
+        }
+        (Some(size), None) | (None, Some(size)) => Ok(size),
+        (None, None) => Err(anyhow!(
+            "{} must be set unless the cml estimator network parameters are used",
+            name
+        )),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TransactionLimits {
+    pub max_tx_size: usize,
+    pub max_value_size: usize,
+    pub witness_size: usize,
+    pub max_splits: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitViolation {
+    TransactionTooLarge { size: usize, max_size: usize },
+    ValueTooLarge { size: usize, max_size: usize },
+}
+
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitViolation::TransactionTooLarge { size, max_size } => write!(
+                f,
+                "transaction size {} exceeds max tx size {}",
+                size, max_size
+            ),
+            LimitViolation::ValueTooLarge { size, max_size } => write!(
+                f,
+                "output value size {} exceeds max value size {}",
+                size, max_size
+            ),
+        }
+    }
+}
+
+impl TransactionLimits {
+    pub fn check<DataMapper: CardanoDataMapper>(
+        &self,
+        inputs: &[UTxODetails],
+        outputs: &[UTxOBuilder],
+        mapper: &mut DataMapper,
+    ) -> anyhow::Result<Option<LimitViolation>> {
+        for output in outputs.iter() {
+            let size = value_size(&output.value, &output.assets);
+            if size > self.max_value_size {
+                return Ok(Some(LimitViolation::ValueTooLarge {
+                    size,
+                    max_size: self.max_value_size,
+                }));
+            }
+        }
+
+        let size = self.estimate_tx_size(inputs, outputs, mapper)?;
+        if size > self.max_tx_size {
+            return Ok(Some(LimitViolation::TransactionTooLarge {
+                size,
+                max_size: self.max_tx_size,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    pub fn estimate_tx_size<DataMapper: CardanoDataMapper>(
+        &self,
+        inputs: &[UTxODetails],
+        outputs: &[UTxOBuilder],
+        mapper: &mut DataMapper,
+    ) -> anyhow::Result<usize> {
+        let mut size = TX_OVERHEAD_SIZE;
+
+        size += array_header_size(inputs.len()) + inputs.len() * INPUT_SIZE;
+
+        size += array_header_size(outputs.len());
+        for output in outputs.iter() {
+            size += output_size(&output.address, &output.value, &output.assets, mapper)?;
+        }
+
+        let mut payment_keys = HashSet::new();
+        for input in inputs.iter() {
+            payment_keys.insert(
+                mapper
+                    .map_address_to_indices(input.address.clone())?
+                    .map(|(pk, _)| pk),
+            );
+        }
+        size += array_header_size(payment_keys.len()) + payment_keys.len() * self.witness_size;
+
+        Ok(size)
+    }
+}
+
+pub fn output_size<DataMapper: CardanoDataMapper>(
+    address: &Address,
+    value: &Value<Regulated>,
+    assets: &[TransactionAsset],
+    mapper: &mut DataMapper,
+) -> anyhow::Result<usize> {
+    let address_size = match mapper.map_address_to_indices(address.clone())? {
+        Some((_, None)) => ENTERPRISE_ADDRESS_SIZE,
+        Some(address) if byron_index(address).is_some() => BYRON_ADDRESS_SIZE,
+        _ => BASE_ADDRESS_SIZE,
+    };
+    Ok(1 + address_size + value_size(value, assets))
+}
+
+pub fn value_size(value: &Value<Regulated>, assets: &[TransactionAsset]) -> usize {
+    let coin_size = uint_size(value_to_u64(value));
+    if assets.is_empty() {
+        return coin_size;
+    }
+
+    let mut by_policy = BTreeMap::<String, Vec<&TransactionAsset>>::new();
+    for asset in assets.iter() {
+        by_policy
+            .entry(asset.policy_id.to_string())
+            .or_default()
+            .push(asset);
+    }
+
+    let mut size = 1 + coin_size + array_header_size(by_policy.len());
+    for assets in by_policy.values() {
+        size += POLICY_ID_SIZE + array_header_size(assets.len());
+        for asset in assets.iter() {
+            let name_size = asset_name_size(asset);
+            size += array_header_size(name_size) + name_size;
+            size += uint_size(value_to_u64(&asset.quantity));
+        }
+    }
+    size
+}
+
+pub fn asset_name_size(asset: &TransactionAsset) -> usize {
+    hex::decode(asset.asset_name.as_ref())
+        .map(|name| name.len())
+        .unwrap_or(MAX_ASSET_NAME_SIZE)
+}
+
+fn value_to_u64(value: &Value<Regulated>) -> u64 {
+    u64::from_str(value.to_string().as_str()).unwrap_or(u64::MAX)
+}
+
+// cbor header size of unsigned int, arrays, maps and bytes follow the same rules
+fn uint_size(value: u64) -> usize {
+    if value < 24 {
+        1
+    } else if value < 0x100 {
+        2
+    } else if value < 0x10000 {
+        3
+    } else if value < 0x100000000 {
+        5
+    } else {
+        9
+    }
+}
+
+fn array_header_size(len: usize) -> usize {
+    uint_size(len as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_utils::address_mapper::StringAddressMapper;
+    use crate::bench_utils::network_parameters::CardanoNetworkParameters;
+    use crate::bench_utils::transaction_limits::{
+        uint_size, value_size, LimitViolation, TransactionLimits, TransactionLimitsConfig,
+    };
+    use dcspark_core::tx::{
+        TransactionAsset, TransactionId, UTxOBuilder, UTxODetails, UtxoPointer,
+    };
+    use dcspark_core::{Address, AssetName, OutputIndex, PolicyId, TokenId, Value};
+    use std::sync::Arc;
+
+    fn limits(max_tx_size: usize, max_value_size: usize) -> TransactionLimits {
+        TransactionLimits {
+            max_tx_size,
+            max_value_size,
+            witness_size: 101,
+            max_splits: 1,
+        }
+    }
+
+    fn input(index: u64, pk: u64) -> UTxODetails {
+        UTxODetails {
+            pointer: UtxoPointer {
+                transaction_id: TransactionId::new("0"),
+                output_index: OutputIndex::new(index),
+            },
+            address: Address::new(format!("{}:1", pk)),
+            value: Value::from(1_000_000),
+            assets: vec![],
+            metadata: Arc::new(Default::default()),
+            extra: None,
+        }
+    }
+
+    fn asset(policy: u8, name: u8) -> TransactionAsset {
+        TransactionAsset {
+            policy_id: PolicyId::new(hex::encode([policy; 28])),
+            asset_name: AssetName::new(hex::encode([name; 4])),
+            fingerprint: TokenId::new(format!("{}:{}", policy, name)),
+            quantity: Value::from(1000),
+        }
+    }
+
+    #[test]
+    fn uint_sizes() {
+        assert_eq!(uint_size(0), 1);
+        assert_eq!(uint_size(23), 1);
+        assert_eq!(uint_size(24), 2);
+        assert_eq!(uint_size(1_000_000), 5);
+        assert_eq!(uint_size(u64::MAX), 9);
+    }
+
+    #[test]
+    fn value_with_assets_is_larger() {
+        let value = Value::from(1_000_000);
+        assert_eq!(value_size(&value, &[]), 5);
+        let one_policy = value_size(&value, &[asset(0, 0), asset(0, 1)]);
+        let two_policies = value_size(&value, &[asset(0, 0), asset(1, 1)]);
+        assert!(one_policy > 5);
+        assert!(two_policies > one_policy);
+    }
+
+    #[test]
+    fn detects_violations() {
+        let mut mapper = StringAddressMapper::default();
+        let inputs = (0..10).map(|i| input(i, i)).collect::<Vec<_>>();
+        let outputs = vec![UTxOBuilder::new(
+            Address::new("5:5"),
+            Value::from(1_000_000),
+            vec![asset(0, 0), asset(1, 1)],
+        )];
+
+        let size = limits(0, 0)
+            .estimate_tx_size(&inputs, &outputs, &mut mapper)
+            .unwrap();
+        assert!(limits(size, 5000)
+            .check(&inputs, &outputs, &mut mapper)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            limits(size - 1, 5000)
+                .check(&inputs, &outputs, &mut mapper)
+                .unwrap(),
+            Some(LimitViolation::TransactionTooLarge {
+                size,
+                max_size: size - 1
+            })
+        );
+        assert!(matches!(
+            limits(size, 10)
+                .check(&inputs, &outputs, &mut mapper)
+                .unwrap(),
+            Some(LimitViolation::ValueTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn sizes_default_to_network_parameters() {
+        let parameters: CardanoNetworkParameters = serde_yaml::from_str(
+            r#"
+coefficient: "44"
+constant: "155381"
+pool_deposit: "500000000"
+key_deposit: "2000000"
+max_value_size: 5000
+max_tx_size: 16384
+coins_per_utxo_byte: "4310"
+ex_unit_mem_price_numerator: "577"
+ex_unit_mem_price_denominator: "10000"
+ex_unit_step_price_numerator: "721"
+ex_unit_step_price_denominator: "10000000"
+collateral_percentage: 150
+max_collateral_inputs: 3
+"#,
+        )
+        .unwrap();
+        let config =
+            |yaml: &str| -> TransactionLimitsConfig { serde_yaml::from_str(yaml).unwrap() };
+
+        let limits = config("max_splits: 3").resolve(Some(&parameters)).unwrap();
+        assert_eq!(limits.max_tx_size, 16384);
+        assert_eq!(limits.max_value_size, 5000);
+        assert_eq!(limits.max_splits, 3);
+
+        assert!(config("max_tx_size: 16384")
+            .resolve(Some(&parameters))
+            .is_ok());
+        let limits = config("max_tx_size: 8000")
+            .resolve(Some(&parameters))
+            .unwrap();
+        assert_eq!((limits.max_tx_size, limits.max_value_size), (8000, 5000));
+        assert!(config("max_tx_size: 8000").resolve(None).is_err());
+        let limits = config("max_tx_size: 8000\nmax_value_size: 4000")
+            .resolve(None)
+            .unwrap();
+        assert_eq!((limits.max_tx_size, limits.max_value_size), (8000, 4000));
+    }
+}
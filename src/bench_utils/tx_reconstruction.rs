@@ -0,0 +1,559 @@
+use crate::utils::value_to_u64;
+use anyhow::anyhow;
+use cardano_multiplatform_lib::builders::input_builder::SingleInputBuilder;
+use cardano_multiplatform_lib::builders::output_builder::SingleOutputBuilderResult;
+use cardano_multiplatform_lib::builders::tx_builder::{
+    TransactionBuilder, TransactionBuilderConfig,
+};
+use cardano_multiplatform_lib::crypto::TransactionHash;
+use cardano_multiplatform_lib::ledger::common::value::BigNum;
+use cardano_multiplatform_lib::metadata::AuxiliaryData;
+use cardano_multiplatform_lib::{
+    Assets, MultiAsset, PolicyID, TransactionInput, TransactionOutput,
+};
+use dcspark_core::tx::{CardanoPaymentCredentials, TransactionAsset, UTxOBuilder, UTxODetails};
+use dcspark_core::{Address, Regulated, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const WORST_OFFENDERS: usize = 20;
+
+fn default_cbor_sample_rate() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TxReconstructionConfig {
+    pub report_path: PathBuf,
+    // bodies of the sampled transactions, one json line per transaction
+    #[serde(default)]
+    pub cbor_dump_path: Option<PathBuf>,
+    // every n-th reconstructed transaction is dumped
+    #[serde(default = "default_cbor_sample_rate")]
+    pub cbor_sample_rate: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ReconstructionRecord {
+    pub tx_number: u64,
+    // size with the fake witnesses of the builder
+    pub size: usize,
+    // size estimated by the transaction limits, if they are set
+    pub estimated_size: Option<usize>,
+    pub fee: u64,
+    // min fee of the builder for the same transaction
+    pub min_fee: u64,
+}
+
+impl ReconstructionRecord {
+    pub fn fee_difference(&self) -> i64 {
+        self.fee as i64 - self.min_fee as i64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconstructionSummary {
+    pub reconstructed: usize,
+    pub failed: u64,
+    pub skipped_split: u64,
+    pub oversized: usize,
+    pub underpaid: usize,
+    pub mean_size: f64,
+    pub max_size: usize,
+    // mean of algorithm fee - builder min fee
+    pub mean_fee_difference: f64,
+    // mean of estimated - actual size over the transactions with an estimation
+    pub mean_size_error: f64,
+}
+
+#[derive(Serialize)]
+struct DumpedTransaction {
+    tx_number: u64,
+    size: usize,
+    fee: u64,
+    body: String,
+}
+
+// rebuilds the selections with the cml transaction builder to check that they are valid transactions
+pub struct TxReconstruction {
+    builder_config: TransactionBuilderConfig,
+    credentials: CardanoPaymentCredentials,
+    auxiliary_data: Option<AuxiliaryData>,
+    max_tx_size: usize,
+    config: TxReconstructionConfig,
+    cbor_dump: Option<File>,
+    reconstructed: usize,
+    oversized: usize,
+    underpaid: usize,
+    size_sum: u64,
+    max_size: usize,
+    fee_difference_sum: i64,
+    // sum and count of estimated - actual size
+    size_error_sum: i64,
+    estimated: usize,
+    // the least underpaid of the kept records is on top, so it is evicted first
+    most_underpaid: BinaryHeap<(i64, ReconstructionRecord)>,
+    // tx number and error of the failed reconstructions
+    failures: Vec<(u64, String)>,
+    failed: u64,
+    skipped_split: u64,
+}
+
+impl TxReconstruction {
+    pub fn new(
+        config: TxReconstructionConfig,
+        builder_config: TransactionBuilderConfig,
+        credentials: CardanoPaymentCredentials,
+        auxiliary_data: Option<AuxiliaryData>,
+        max_tx_size: usize,
+    ) -> anyhow::Result<Self> {
+        if config.cbor_sample_rate == 0 {
+            return Err(anyhow!("cbor sample rate must be greater than zero"));
+        }
+        let cbor_dump = config
+            .cbor_dump_path
+            .clone()
+            .map(File::create)
+            .transpose()?;
+        Ok(Self {
+            builder_config,
+            credentials,
+            auxiliary_data,
+            max_tx_size,
+            config,
+            cbor_dump,
+            reconstructed: 0,
+            oversized: 0,
+            underpaid: 0,
+            size_sum: 0,
+            max_size: 0,
+            fee_difference_sum: 0,
+            size_error_sum: 0,
+            estimated: 0,
+            most_underpaid: BinaryHeap::new(),
+            failures: vec![],
+            failed: 0,
+            skipped_split: 0,
+        })
+    }
+
+    // split selections are merged into one, so their transactions can't be rebuilt
+    pub fn skip_split_selection(&mut self) {
+        self.skipped_split += 1;
+    }
+
+    pub fn reconstruct(
+        &mut self,
+        tx_number: u64,
+        inputs: &[UTxODetails],
+        outputs: &[UTxOBuilder],
+        fee: &Value<Regulated>,
+        estimated_size: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let record = match self.build(tx_number, inputs, outputs, fee, estimated_size) {
+            Ok(record) => record,
+            Err(err) => {
+                tracing::warn!(
+                    "can't reconstruct transaction: {}, tx_number: {}",
+                    err,
+                    tx_number
+                );
+                self.failed += 1;
+                if self.failures.len() < WORST_OFFENDERS {
+                    self.failures.push((tx_number, err.to_string()));
+                }
+                return Ok(());
+            }
+        };
+        self.add_record(record);
+        Ok(())
+    }
+
+    fn add_record(&mut self, record: ReconstructionRecord) {
+        self.reconstructed += 1;
+        if record.size > self.max_tx_size {
+            self.oversized += 1;
+        }
+        self.size_sum += record.size as u64;
+        self.max_size = self.max_size.max(record.size);
+        self.fee_difference_sum += record.fee_difference();
+        if let Some(estimated) = record.estimated_size {
+            self.size_error_sum += estimated as i64 - record.size as i64;
+            self.estimated += 1;
+        }
+        if record.fee_difference() < 0 {
+            self.underpaid += 1;
+            self.most_underpaid.push((record.fee_difference(), record));
+            if self.most_underpaid.len() > WORST_OFFENDERS {
+                self.most_underpaid.pop();
+            }
+        }
+    }
+
+    fn build(
+        &mut self,
+        tx_number: u64,
+        inputs: &[UTxODetails],
+        outputs: &[UTxOBuilder],
+        fee: &Value<Regulated>,
+        estimated_size: Option<usize>,
+    ) -> anyhow::Result<ReconstructionRecord> {
+        let mut builder = TransactionBuilder::new(&self.builder_config);
+        if let Some(auxiliary_data) = &self.auxiliary_data {
+            builder.set_auxiliary_data(auxiliary_data);
+        }
+
+        for input in inputs.iter() {
+            let transaction_id =
+                TransactionHash::from_bytes(hex::decode(input.pointer.transaction_id.as_ref())?)
+                    .map_err(|err| anyhow!("can't create tx hash: {}", err))?;
+            let index = u64::from_str(input.pointer.output_index.to_string().as_str())?;
+            let input_builder = SingleInputBuilder::new(
+                &TransactionInput::new(&transaction_id, &BigNum::from(index)),
+                &to_cml_output(&input.address, &input.value, &input.assets)?,
+            );
+            let input = match &self.credentials {
+                CardanoPaymentCredentials::PaymentKey => input_builder.payment_key(),
+                CardanoPaymentCredentials::NativeScript {
+                    native_script,
+                    witness_info,
+                } => input_builder.native_script(native_script, witness_info),
+                _ => {
+                    return Err(anyhow!(
+                        "only payment key and native script inputs are supported"
+                    ))
+                }
+            }
+            .map_err(|err| anyhow!("can't create input: {}", err))?;
+            builder
+                .add_input(&input)
+                .map_err(|err| anyhow!("can't add input: {}", err))?;
+        }
+
+        for output in outputs.iter() {
+            builder
+                .add_output(&SingleOutputBuilderResult::new(&to_cml_output(
+                    &output.address,
+                    &output.value,
+                    &output.assets,
+                )?))
+                .map_err(|err| anyhow!("can't add output: {}", err))?;
+        }
+
+        let fee = value_to_u64(fee)?;
+        builder.set_fee(&BigNum::from(fee));
+        let min_fee = builder
+            .min_fee(false)
+            .map_err(|err| anyhow!("can't compute min fee: {}", err))?;
+        let size = builder
+            .full_size()
+            .map_err(|err| anyhow!("can't compute tx size: {}", err))?;
+        let body = builder
+            .build_body()
+            .map_err(|err| anyhow!("can't build tx body: {}", err))?;
+
+        if let Some(file) = self.cbor_dump.as_mut() {
+            if self.reconstructed as u64 % self.config.cbor_sample_rate == 0 {
+                let dumped = DumpedTransaction {
+                    tx_number,
+                    size,
+                    fee,
+                    body: hex::encode(body.to_bytes()),
+                };
+                file.write_all(format!("{}\n", serde_json::to_string(&dumped)?).as_bytes())?;
+            }
+        }
+
+        Ok(ReconstructionRecord {
+            tx_number,
+            size,
+            estimated_size,
+            fee,
+            min_fee: u64::from(min_fee),
+        })
+    }
+
+    pub fn summary(&self) -> ReconstructionSummary {
+        let count = self.reconstructed.max(1) as f64;
+        ReconstructionSummary {
+            reconstructed: self.reconstructed,
+            failed: self.failed,
+            skipped_split: self.skipped_split,
+            oversized: self.oversized,
+            underpaid: self.underpaid,
+            mean_size: self.size_sum as f64 / count,
+            max_size: self.max_size,
+            mean_fee_difference: self.fee_difference_sum as f64 / count,
+            mean_size_error: self.size_error_sum as f64 / self.estimated.max(1) as f64,
+        }
+    }
+
+    // most underpaid first
+    pub fn most_underpaid(&self) -> Vec<&ReconstructionRecord> {
+        let mut records: Vec<_> = self.most_underpaid.iter().collect();
+        records.sort();
+        records.into_iter().map(|(_, record)| record).collect()
+    }
+
+    pub fn print_report(&self) -> anyhow::Result<()> {
+        let mut file = File::create(self.config.report_path.clone())?;
+        let summary = self.summary();
+
+        file.write_all(format!("reconstructed: {}\n", summary.reconstructed).as_bytes())?;
+        file.write_all(format!("failed: {}\n", summary.failed).as_bytes())?;
+        file.write_all(
+            format!("skipped split selections: {}\n", summary.skipped_split).as_bytes(),
+        )?;
+        file.write_all(
+            format!(
+                "oversized (max tx size {}): {}\n",
+                self.max_tx_size, summary.oversized
+            )
+            .as_bytes(),
+        )?;
+        file.write_all(format!("underpaid: {}\n", summary.underpaid).as_bytes())?;
+        file.write_all(
+            format!(
+                "size mean: {:.2}, max: {}\n",
+                summary.mean_size, summary.max_size
+            )
+            .as_bytes(),
+        )?;
+        file.write_all(
+            format!("mean fee - min fee: {:.2}\n", summary.mean_fee_difference).as_bytes(),
+        )?;
+        file.write_all(
+            format!(
+                "mean estimated - actual size: {:.2}\n",
+                summary.mean_size_error
+            )
+            .as_bytes(),
+        )?;
+
+        file.write_all("most underpaid:\n".as_bytes())?;
+        for record in self.most_underpaid() {
+            file.write_all(
+                format!(
+                    "tx_number: {}, fee: {}, min fee: {}, size: {}\n",
+                    record.tx_number, record.fee, record.min_fee, record.size
+                )
+                .as_bytes(),
+            )?;
+        }
+
+        file.write_all("failures:\n".as_bytes())?;
+        for (tx_number, err) in self.failures.iter() {
+            file.write_all(format!("tx_number: {}, error: {}\n", tx_number, err).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+pub fn to_cml_output(
+    address: &Address,
+    value: &Value<Regulated>,
+    assets: &[TransactionAsset],
+) -> anyhow::Result<TransactionOutput> {
+    let address = match cardano_multiplatform_lib::address::Address::from_bech32(address.as_ref()) {
+        Ok(address) => address,
+        Err(_) => cardano_multiplatform_lib::address::ByronAddress::from_base58(address.as_ref())
+            .map_err(|err| anyhow!("can't parse address: {}, err: {}", address, err))?
+            .to_address(),
+    };
+
+    let mut cml_value = cardano_multiplatform_lib::ledger::common::value::Value::new(
+        &BigNum::from(value_to_u64(value)?),
+    );
+    if !assets.is_empty() {
+        let mut policies = BTreeMap::<String, Assets>::new();
+        for asset in assets.iter() {
+            let name =
+                cardano_multiplatform_lib::AssetName::new(hex::decode(asset.asset_name.as_ref())?)
+                    .map_err(|err| anyhow!("can't create asset name: {}", err))?;
+            policies
+                .entry(asset.policy_id.to_string())
+                .or_insert_with(Assets::new)
+                .insert(&name, &BigNum::from(value_to_u64(&asset.quantity)?));
+        }
+        let mut multiasset = MultiAsset::new();
+        for (policy_id, assets) in policies.iter() {
+            let policy_id = PolicyID::from_hex(policy_id)
+                .map_err(|err| anyhow!("can't decode policy id: {}, err: {}", policy_id, err))?;
+            multiasset.insert(&policy_id, assets);
+        }
+        cml_value.set_multiasset(&multiasset);
+    }
+
+    Ok(TransactionOutput::new(&address, &cml_value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_utils::tx_reconstruction::{
+        TxReconstruction, TxReconstructionConfig, WORST_OFFENDERS,
+    };
+    use cardano_multiplatform_lib::builders::tx_builder::TransactionBuilderConfigBuilder;
+    use cardano_multiplatform_lib::ledger::alonzo::fees::LinearFee;
+    use cardano_multiplatform_lib::ledger::common::value::BigNum;
+    use cardano_multiplatform_lib::plutus::ExUnitPrices;
+    use cardano_multiplatform_lib::UnitInterval;
+    use dcspark_core::tx::{
+        CardanoPaymentCredentials, TransactionId, UTxOBuilder, UTxODetails, UtxoPointer,
+    };
+    use dcspark_core::{Address, OutputIndex, Value};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    const ADDRESS: &str = "addr1qx2kd28nq8ac5prwg32hhvudlwggpgfp8utlyqxu6wqgz62f79qsdmm5dsknt9ecr5w468r9ey0fxwkdrwh08ly3tu9sy0f4qd";
+
+    fn reconstruction(report_path: PathBuf) -> TxReconstruction {
+        let config = TransactionBuilderConfigBuilder::new()
+            .fee_algo(&LinearFee::new(&BigNum::from(44), &BigNum::from(155381)))
+            .pool_deposit(&BigNum::from(500000000))
+            .key_deposit(&BigNum::from(2000000))
+            .max_value_size(5000)
+            .max_tx_size(16384)
+            .coins_per_utxo_byte(&BigNum::from(4310))
+            .ex_unit_prices(&ExUnitPrices::new(
+                &UnitInterval::new(&BigNum::from(577), &BigNum::from(10000)),
+                &UnitInterval::new(&BigNum::from(721), &BigNum::from(10000000)),
+            ))
+            .collateral_percentage(150)
+            .max_collateral_inputs(3)
+            .build()
+            .unwrap();
+        TxReconstruction::new(
+            TxReconstructionConfig {
+                report_path,
+                cbor_dump_path: None,
+                cbor_sample_rate: 1,
+            },
+            config,
+            CardanoPaymentCredentials::PaymentKey,
+            None,
+            16384,
+        )
+        .unwrap()
+    }
+
+    fn input(value: u64) -> UTxODetails {
+        UTxODetails {
+            pointer: UtxoPointer {
+                transaction_id: TransactionId::new("aa".repeat(32)),
+                output_index: OutputIndex::new(0),
+            },
+            address: Address::new(ADDRESS),
+            value: Value::from(value),
+            assets: vec![],
+            metadata: Arc::new(Default::default()),
+            extra: None,
+        }
+    }
+
+    #[test]
+    fn reconstructs_balanced_selection() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut reconstruction = reconstruction(dir.path().join("report.txt"));
+        let outputs = vec![
+            UTxOBuilder::new(Address::new(ADDRESS), Value::from(5_000_000), vec![]),
+            UTxOBuilder::new(Address::new(ADDRESS), Value::from(4_800_000), vec![]),
+        ];
+        reconstruction
+            .reconstruct(
+                0,
+                &[input(10_000_000)],
+                &outputs,
+                &Value::from(200_000),
+                Some(300),
+            )
+            .unwrap();
+        // one lovelace is missing, so the body can't be built
+        reconstruction
+            .reconstruct(
+                1,
+                &[input(9_999_999)],
+                &outputs,
+                &Value::from(200_000),
+                None,
+            )
+            .unwrap();
+        // the fee is below the min fee of the builder
+        let outputs = vec![UTxOBuilder::new(
+            Address::new(ADDRESS),
+            Value::from(9_999_000),
+            vec![],
+        )];
+        reconstruction
+            .reconstruct(2, &[input(10_000_000)], &outputs, &Value::from(1_000), None)
+            .unwrap();
+        reconstruction.skip_split_selection();
+
+        let summary = reconstruction.summary();
+        assert_eq!(summary.reconstructed, 2);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.skipped_split, 1);
+        assert_eq!(summary.underpaid, 1);
+        assert_eq!(summary.oversized, 0);
+        assert!(summary.max_size > 0);
+        reconstruction.print_report().unwrap();
+    }
+
+    #[test]
+    fn keeps_most_underpaid() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut reconstruction = reconstruction(dir.path().join("report.txt"));
+        for tx_number in 0..(WORST_OFFENDERS as u64 + 5) {
+            let fee = 1_000 + tx_number;
+            let outputs = vec![UTxOBuilder::new(
+                Address::new(ADDRESS),
+                Value::from(10_000_000 - fee),
+                vec![],
+            )];
+            reconstruction
+                .reconstruct(
+                    tx_number,
+                    &[input(10_000_000)],
+                    &outputs,
+                    &Value::from(fee),
+                    None,
+                )
+                .unwrap();
+        }
+        assert_eq!(reconstruction.summary().underpaid, WORST_OFFENDERS + 5);
+        let most_underpaid: Vec<u64> = reconstruction
+            .most_underpaid()
+            .into_iter()
+            .map(|record| record.tx_number)
+            .collect();
+        assert_eq!(
+            most_underpaid,
+            (0..WORST_OFFENDERS as u64).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_addresses() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut reconstruction = reconstruction(dir.path().join("report.txt"));
+        let outputs = vec![UTxOBuilder::new(
+            Address::new("0:1"),
+            Value::from(9_800_000),
+            vec![],
+        )];
+        reconstruction
+            .reconstruct(
+                0,
+                &[input(10_000_000)],
+                &outputs,
+                &Value::from(200_000),
+                None,
+            )
+            .unwrap();
+        assert_eq!(reconstruction.summary().failed, 1);
+    }
+}
@@ -121,26 +121,34 @@ impl UTxOStoreAccumulator {
         self.utxos_by_stake_key.remove(&staking_key);
     }
 
+    // utxos of the keys of interest which are not banned
+    pub fn converged_utxos(&self) -> impl Iterator<Item = (u64, &UTxOStore)> + '_ {
+        self.utxos_by_stake_key
+            .iter()
+            .filter(|(sk, _)| {
+                let criteria = self.criteria.as_ref().borrow();
+                criteria.is_whitelisted(**sk) && !criteria.is_banned(**sk)
+            })
+            .map(|(sk, utxos)| (*sk, utxos))
+    }
+
     pub fn print_utxos(&self, path: PathBuf) -> anyhow::Result<()> {
         let mut file = File::create(path)?;
 
-        for (sk, utxos) in self.utxos_by_stake_key.iter() {
-            let criteria = self.criteria.as_ref().borrow();
-            if criteria.is_whitelisted(*sk) && !criteria.is_banned(*sk) {
-                assert_eq!(utxos.len(), utxos.iter_ordered_by_wmain().count());
-                let mut greater_than_10 = 0;
-                let mut less_than_10 = 0;
-                for utxo in utxos.iter_ordered_by_wmain() {
-                    file.write_all(format!("{}, {:?}\n", utxo.value, utxo.assets).as_bytes())?;
-                    if utxo.value > Value::<Regulated>::from(10_000_000) {
-                        greater_than_10 += 1;
-                    } else {
-                        less_than_10 += 1;
-                    }
+        for (_, utxos) in self.converged_utxos() {
+            assert_eq!(utxos.len(), utxos.iter_ordered_by_wmain().count());
+            let mut greater_than_10 = 0;
+            let mut less_than_10 = 0;
+            for utxo in utxos.iter_ordered_by_wmain() {
+                file.write_all(format!("{}, {:?}\n", utxo.value, utxo.assets).as_bytes())?;
+                if utxo.value > Value::<Regulated>::from(10_000_000) {
+                    greater_than_10 += 1;
+                } else {
+                    less_than_10 += 1;
                 }
-                file.write_all(format!("total greater than 10: {}\n", greater_than_10).as_bytes())?;
-                file.write_all(format!("total less than 10: {}\n", less_than_10).as_bytes())?;
             }
+            file.write_all(format!("total greater than 10: {}\n", greater_than_10).as_bytes())?;
+            file.write_all(format!("total less than 10: {}\n", less_than_10).as_bytes())?;
         }
 
         Ok(())
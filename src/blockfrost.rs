@@ -0,0 +1,642 @@
+use crate::generation_utils::{ban_addresses_for_events, build_event};
+use crate::mapper::DataMapper;
+use crate::tx_event::{TxAsset, TxEvent, TxOutput};
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use cardano_multiplatform_lib::address::{PointerAddress, StakeCredential};
+use cardano_multiplatform_lib::PolicyID;
+use dcspark_core::tx::TransactionId;
+use dcspark_core::{BlockNumber, Regulated};
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const LOVELACE_UNIT: &str = "lovelace";
+const POLICY_ID_HEX_LENGTH: usize = 56;
+pub const PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct BlockfrostResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait BlockfrostBackend {
+    // `path` is relative to the api root, e.g. `txs/{hash}/utxos`
+    async fn get(&self, path: &str) -> anyhow::Result<BlockfrostResponse>;
+}
+
+pub struct HttpBackend {
+    client: Client,
+    endpoint: String,
+}
+
+impl HttpBackend {
+    pub fn new(endpoint: String, key: &str) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            "project_id",
+            HeaderValue::from_str(key)
+                .context("The project_id (authentication key) is not in a valid format")?,
+        );
+
+        let client = Client::builder()
+            .default_headers(headers)
+            .build()
+            .context("Failed to build HTTP Client")?;
+        Ok(Self { client, endpoint })
+    }
+}
+
+#[async_trait]
+impl BlockfrostBackend for HttpBackend {
+    async fn get(&self, path: &str) -> anyhow::Result<BlockfrostResponse> {
+        let response = self
+            .client
+            .get(format!("{}/api/v0/{}", self.endpoint, path))
+            .send()
+            .await
+            .context("Failed to send request to blockfrost endpoint")?;
+        let status = response.status().as_u16();
+        let body = response
+            .text()
+            .await
+            .context("Failed to read blockfrost response")?;
+        Ok(BlockfrostResponse { status, body })
+    }
+}
+
+// serves the responses from json files: `txs/{hash}/utxos` is read from `<dir>/txs_{hash}_utxos.json`.
+// missing files are served as 404 responses
+pub struct FixtureBackend {
+    dir: PathBuf,
+}
+
+impl FixtureBackend {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn fixture_path(&self, path: &str) -> PathBuf {
+        let name = path.replace(['/', '?', '&', '='], "_");
+        self.dir.join(format!("{}.json", name))
+    }
+}
+
+#[async_trait]
+impl BlockfrostBackend for FixtureBackend {
+    async fn get(&self, path: &str) -> anyhow::Result<BlockfrostResponse> {
+        let path = self.fixture_path(path);
+        if !path.is_file() {
+            return Ok(BlockfrostResponse {
+                status: 404,
+                body: r#"{"status_code":404,"error":"Not Found","message":"The requested component has not been found."}"#.to_string(),
+            });
+        }
+        Ok(BlockfrostResponse {
+            status: 200,
+            body: std::fs::read_to_string(path)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub struct BlockfrostTransaction {
+    pub tx_hash: TransactionId,
+    pub tx_index: u64,
+    pub block_time: u64,
+    pub block_height: BlockNumber,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockFrostError {
+    pub status_code: usize,
+    pub error: String,
+    pub message: String,
+}
+
+impl fmt::Display for BlockFrostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.status_code, self.error, self.message)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockfrostAccountAddress {
+    pub address: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockfrostAmount {
+    pub unit: String,
+    pub quantity: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockfrostUtxo {
+    pub address: String,
+    pub amount: Vec<BlockfrostAmount>,
+    pub output_index: u64,
+    #[serde(default)]
+    pub collateral: bool,
+    #[serde(default)]
+    pub reference: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockfrostTxUtxos {
+    pub hash: String,
+    pub inputs: Vec<BlockfrostUtxo>,
+    pub outputs: Vec<BlockfrostUtxo>,
+}
+
+fn default_valid_contract() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockfrostTxDetails {
+    pub hash: String,
+    pub block_height: u64,
+    pub fees: String,
+    #[serde(default = "default_valid_contract")]
+    pub valid_contract: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestPolicy {
+    pub retries: u64,
+    // requests are spread evenly to stay within the budget, unlimited if not set
+    pub requests_per_second: Option<f64>,
+    pub backoff_base: Duration,
+    pub backoff_max: Duration,
+}
+
+impl RequestPolicy {
+    pub fn new(retries: u64, requests_per_second: Option<f64>) -> Self {
+        Self {
+            retries,
+            requests_per_second,
+            backoff_base: Duration::from_millis(500),
+            backoff_max: Duration::from_secs(60),
+        }
+    }
+
+    // exponential backoff with jitter: random delay between half and full exponential delay
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff_base
+            .saturating_mul(1u32 << attempt.min(16))
+            .min(self.backoff_max);
+        rand::thread_rng().gen_range(delay / 2..=delay)
+    }
+}
+
+pub struct BlockfrostClient<Backend: BlockfrostBackend> {
+    backend: Backend,
+    policy: RequestPolicy,
+    last_request: Mutex<Option<Instant>>,
+}
+
+impl<Backend: BlockfrostBackend> BlockfrostClient<Backend> {
+    pub fn new(backend: Backend, policy: RequestPolicy) -> Self {
+        Self {
+            backend,
+            policy,
+            last_request: Mutex::new(None),
+        }
+    }
+
+    pub async fn account_addresses(
+        &self,
+        stake_address: &str,
+        page: u64,
+    ) -> anyhow::Result<Vec<BlockfrostAccountAddress>> {
+        Ok(self
+            .get(format!(
+                "accounts/{}/addresses?page={}&count={}",
+                stake_address, page, PAGE_SIZE
+            ))
+            .await?
+            .unwrap_or_default())
+    }
+
+    // payment addresses of the list and all the addresses of the stake address
+    pub async fn resolve_addresses(
+        &self,
+        addresses: &[String],
+        stake_address: Option<&str>,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut result = addresses.to_vec();
+        if let Some(stake_address) = stake_address {
+            let mut page = 1;
+            loop {
+                let addresses = self.account_addresses(stake_address, page).await?;
+                let count = addresses.len();
+                result.extend(addresses.into_iter().map(|address| address.address));
+                if count < PAGE_SIZE {
+                    break;
+                }
+                page += 1;
+            }
+        }
+        let mut seen = HashSet::new();
+        result.retain(|address| seen.insert(address.clone()));
+        Ok(result)
+    }
+
+    pub async fn address_transactions(
+        &self,
+        address: &str,
+        page: u64,
+    ) -> anyhow::Result<Vec<BlockfrostTransaction>> {
+        // blockfrost returns 404 for the addresses without transactions
+        Ok(self
+            .get(format!(
+                "addresses/{}/transactions?page={}&count={}",
+                address, page, PAGE_SIZE
+            ))
+            .await?
+            .unwrap_or_default())
+    }
+
+    pub async fn tx_utxos(&self, hash: &str) -> anyhow::Result<BlockfrostTxUtxos> {
+        self.get(format!("txs/{}/utxos", hash))
+            .await?
+            .ok_or_else(|| anyhow!("tx is missing: {}", hash))
+    }
+
+    pub async fn tx_details(&self, hash: &str) -> anyhow::Result<BlockfrostTxDetails> {
+        self.get(format!("txs/{}", hash))
+            .await?
+            .ok_or_else(|| anyhow!("tx is missing: {}", hash))
+    }
+
+    // transactions of all the addresses in the chain order, without duplicates
+    pub async fn addresses_transactions(
+        &self,
+        addresses: &[String],
+    ) -> anyhow::Result<Vec<BlockfrostTransaction>> {
+        let mut result = vec![];
+        for address in addresses.iter() {
+            let mut page = 1;
+            loop {
+                let transactions = self.address_transactions(address, page).await?;
+                let count = transactions.len();
+                result.extend(transactions);
+                if count < PAGE_SIZE {
+                    break;
+                }
+                page += 1;
+            }
+        }
+        result.sort_by_key(|tx| (tx.block_height, tx.tx_index));
+        result.dedup_by(|a, b| a.tx_hash == b.tx_hash);
+        Ok(result)
+    }
+
+    async fn throttle(&self) {
+        if let Some(requests_per_second) = self.policy.requests_per_second {
+            let interval = Duration::from_secs_f64(1.0 / requests_per_second);
+            let mut last_request = self.last_request.lock().await;
+            if let Some(last_request) = *last_request {
+                let elapsed = last_request.elapsed();
+                if elapsed < interval {
+                    tokio::time::sleep(interval - elapsed).await;
+                }
+            }
+            *last_request = Some(Instant::now());
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: String) -> anyhow::Result<Option<T>> {
+        let mut attempt = 0;
+        loop {
+            self.throttle().await;
+            let error = match self.backend.get(&path).await {
+                Ok(response) if response.status == 200 => {
+                    return serde_json::from_str(&response.body)
+                        .map(Some)
+                        .with_context(|| format!("Unexpected response for {}", path));
+                }
+                Ok(response) if response.status == 404 => return Ok(None),
+                Ok(response) => {
+                    let error = parse_error(&response);
+                    match response.status {
+                        // rate limit exceeded or server error
+                        429 | 500..=599 => anyhow!(error),
+                        // the client is banned for flooding the api, requests extend the ban
+                        418 => return Err(anyhow!("blockfrost banned the client: {}", error)),
+                        _ => return Err(anyhow!("{}, path: {}", error, path)),
+                    }
+                }
+                Err(err) => err,
+            };
+
+            attempt += 1;
+            if attempt >= self.policy.retries.max(1) {
+                return Err(error.context(format!("retries limit reached for {}", path)));
+            }
+            let delay = self.policy.backoff(attempt as u32);
+            tracing::warn!("error: {}, path: {}, retrying in {:?}", error, path, delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+fn parse_error(response: &BlockfrostResponse) -> String {
+    match serde_json::from_str::<BlockFrostError>(&response.body) {
+        Ok(error) => error.to_string(),
+        Err(_) => format!("{}: {}", response.status, response.body),
+    }
+}
+
+pub fn blockfrost_tx_to_events(
+    utxos: &BlockfrostTxUtxos,
+    details: &BlockfrostTxDetails,
+    stake_address_to_num: &mut DataMapper<StakeCredential>,
+    payment_address_to_num: &mut DataMapper<StakeCredential>,
+    policy_id_to_num: &mut DataMapper<PolicyID>,
+    asset_name_to_num: &mut DataMapper<String>,
+    banned_addresses: &mut HashSet<(u64, Option<u64>)>,
+) -> anyhow::Result<Option<TxEvent>> {
+    if !details.valid_contract {
+        return Err(anyhow!(
+            "tx {} failed script validation, only collateral was spent",
+            details.hash
+        ));
+    }
+
+    let mut parse = |utxo: &BlockfrostUtxo| {
+        parse_utxo(
+            utxo,
+            stake_address_to_num,
+            payment_address_to_num,
+            policy_id_to_num,
+            asset_name_to_num,
+        )
+    };
+    let input_events = utxos
+        .inputs
+        .iter()
+        .filter(|input| !input.collateral && !input.reference)
+        .map(&mut parse)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // collateral return output is marked as collateral
+    let output_events = utxos
+        .outputs
+        .iter()
+        .filter(|output| !output.collateral)
+        .map(&mut parse)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let has_banned_addresses = input_events
+        .iter()
+        .any(|input| input.is_byron() || input.is_banned(banned_addresses));
+    if has_banned_addresses {
+        ban_addresses_for_events(&input_events, banned_addresses)?;
+    }
+
+    let fee = dcspark_core::Value::<Regulated>::from(u64::from_str(&details.fees)?);
+    Ok(build_event(
+        has_banned_addresses,
+        input_events,
+        output_events,
+        fee,
+        banned_addresses,
+    ))
+}
+
+fn parse_utxo(
+    utxo: &BlockfrostUtxo,
+    stake_address_to_num: &mut DataMapper<StakeCredential>,
+    payment_address_to_num: &mut DataMapper<StakeCredential>,
+    policy_id_to_num: &mut DataMapper<PolicyID>,
+    asset_name_to_num: &mut DataMapper<String>,
+) -> anyhow::Result<TxOutput> {
+    let address = map_address(&utxo.address, stake_address_to_num, payment_address_to_num);
+
+    let mut value = dcspark_core::Value::<Regulated>::zero();
+    let mut assets = Vec::new();
+    for amount in utxo.amount.iter() {
+        let quantity = u64::from_str(&amount.quantity)
+            .with_context(|| format!("Can't parse quantity of {}", amount.unit))?;
+        if amount.unit == LOVELACE_UNIT {
+            value = dcspark_core::Value::<Regulated>::from(quantity);
+            continue;
+        }
+        if amount.unit.len() < POLICY_ID_HEX_LENGTH {
+            return Err(anyhow!("unexpected unit: {}", amount.unit));
+        }
+        let (policy_id, asset_name) = amount.unit.split_at(POLICY_ID_HEX_LENGTH);
+        let policy_id = PolicyID::from_hex(policy_id)
+            .map_err(|err| anyhow!("can't decode policy id: {}, err: {}", policy_id, err))?;
+        assets.push(TxAsset {
+            asset_id: (
+                policy_id_to_num.add_if_not_presented(policy_id),
+                asset_name_to_num.add_if_not_presented(asset_name.to_string()),
+            ),
+            value: dcspark_core::Value::<Regulated>::from(quantity),
+        });
+    }
+
+    Ok(TxOutput {
+        address,
+        value,
+        assets,
+    })
+}
+
+// byron and pointer addresses are unknown: there is no certificate data to resolve pointers
+fn map_address(
+    address: &str,
+    stake_address_to_num: &mut DataMapper<StakeCredential>,
+    payment_address_to_num: &mut DataMapper<StakeCredential>,
+) -> Option<(u64, Option<u64>)> {
+    let address = cardano_multiplatform_lib::address::Address::from_bech32(address).ok()?;
+    if PointerAddress::from_address(&address).is_some() {
+        return None;
+    }
+    let payment = address.payment_cred()?;
+    Some((
+        payment_address_to_num.add_if_not_presented(payment),
+        address
+            .staking_cred()
+            .map(|staking| stake_address_to_num.add_if_not_presented(staking)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockfrost::{
+        blockfrost_tx_to_events, BlockfrostBackend, BlockfrostClient, BlockfrostResponse,
+        FixtureBackend, RequestPolicy,
+    };
+    use crate::mapper::DataMapper;
+    use crate::tx_event::TxEvent;
+    use async_trait::async_trait;
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    // replies with the given statuses in order, `[]` body for 200
+    struct ScriptedBackend {
+        statuses: Mutex<Vec<u16>>,
+    }
+
+    #[async_trait]
+    impl BlockfrostBackend for ScriptedBackend {
+        async fn get(&self, _path: &str) -> anyhow::Result<BlockfrostResponse> {
+            let status = self.statuses.lock().unwrap().remove(0);
+            Ok(BlockfrostResponse {
+                status,
+                body: if status == 200 {
+                    "[]".to_string()
+                } else {
+                    format!(
+                        r#"{{"status_code":{},"error":"Error","message":"message"}}"#,
+                        status
+                    )
+                },
+            })
+        }
+    }
+
+    fn scripted_client(statuses: Vec<u16>, retries: u64) -> BlockfrostClient<ScriptedBackend> {
+        let mut policy = RequestPolicy::new(retries, None);
+        policy.backoff_base = Duration::from_millis(1);
+        BlockfrostClient::new(
+            ScriptedBackend {
+                statuses: Mutex::new(statuses),
+            },
+            policy,
+        )
+    }
+
+    const BASE_ADDRESS: &str = "addr1qx2kd28nq8ac5prwg32hhvudlwggpgfp8utlyqxu6wqgz62f79qsdmm5dsknt9ecr5w468r9ey0fxwkdrwh08ly3tu9sy0f4qd";
+    const SCRIPT_ADDRESS: &str = "addr1w8pydstdswmdqmg2rdt59dzql3zgfp9pt8sulnjgalycwdsj9js7w";
+    const POLICY_ID: &str = "29d222ce763455e3d7a09a665ce554f00ac89d2e99a1a83d267170c6";
+
+    #[test]
+    fn fixture_paths() {
+        let backend = FixtureBackend::new(PathBuf::from("fixtures"));
+        assert_eq!(
+            backend.fixture_path("addresses/addr1/transactions?page=2"),
+            PathBuf::from("fixtures/addresses_addr1_transactions_page_2.json")
+        );
+    }
+
+    #[test]
+    fn backoff_is_bounded() {
+        let policy = RequestPolicy::new(5, None);
+        for attempt in 0..40 {
+            let delay = policy.backoff(attempt);
+            assert!(delay <= policy.backoff_max);
+            assert!(
+                delay
+                    >= (policy.backoff_base * 2u32.pow(attempt.min(6))).min(policy.backoff_max) / 2
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limited_requests() {
+        let client = scripted_client(vec![429, 503, 200], 3);
+        assert!(client
+            .address_transactions("addr", 1)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let client = scripted_client(vec![429, 429, 200], 2);
+        assert!(client.address_transactions("addr", 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let client = scripted_client(vec![418, 200], 3);
+        let err = client.address_transactions("addr", 1).await.unwrap_err();
+        assert!(err.to_string().contains("banned"));
+
+        let client = scripted_client(vec![400, 200], 3);
+        let err = client.address_transactions("addr", 1).await.unwrap_err();
+        assert!(err.to_string().contains("400 Error: message"));
+    }
+
+    #[tokio::test]
+    async fn fixture_tx_to_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = FixtureBackend::new(dir.path().to_path_buf());
+        std::fs::write(
+            backend.fixture_path("txs/aa/utxos"),
+            format!(
+                r#"{{"hash":"aa","inputs":[
+                {{"address":"{BASE_ADDRESS}","amount":[{{"unit":"lovelace","quantity":"10000000"}},{{"unit":"{POLICY_ID}4d494e","quantity":"5"}}],"tx_hash":"bb","output_index":0,"collateral":false,"reference":false}},
+                {{"address":"{BASE_ADDRESS}","amount":[{{"unit":"lovelace","quantity":"7000000"}}],"tx_hash":"bb","output_index":1,"collateral":true,"reference":false}}],
+                "outputs":[
+                {{"address":"{SCRIPT_ADDRESS}","amount":[{{"unit":"lovelace","quantity":"2000000"}},{{"unit":"{POLICY_ID}4d494e","quantity":"5"}}],"output_index":0,"collateral":false}},
+                {{"address":"{BASE_ADDRESS}","amount":[{{"unit":"lovelace","quantity":"7800000"}}],"output_index":1,"collateral":false}}]}}"#
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            backend.fixture_path("txs/aa"),
+            r#"{"hash":"aa","block_height":10,"fees":"200000","valid_contract":true}"#,
+        )
+        .unwrap();
+
+        let client = BlockfrostClient::new(backend, RequestPolicy::new(1, None));
+        let utxos = client.tx_utxos("aa").await.unwrap();
+        let details = client.tx_details("aa").await.unwrap();
+        assert!(client.tx_details("cc").await.is_err());
+        assert!(client
+            .address_transactions(BASE_ADDRESS, 1)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut stake_address_to_num = DataMapper::new();
+        let mut payment_address_to_num = DataMapper::new();
+        let mut policy_id_to_num = DataMapper::new();
+        let mut asset_name_to_num = DataMapper::new();
+        let mut banned_addresses = HashSet::new();
+        let event = blockfrost_tx_to_events(
+            &utxos,
+            &details,
+            &mut stake_address_to_num,
+            &mut payment_address_to_num,
+            &mut policy_id_to_num,
+            &mut asset_name_to_num,
+            &mut banned_addresses,
+        )
+        .unwrap()
+        .unwrap();
+
+        match event {
+            TxEvent::Full { from, to, fee } => {
+                assert_eq!(from.len(), 1);
+                assert_eq!(from[0].address, Some((0, Some(0))));
+                assert_eq!(from[0].assets.len(), 1);
+                assert_eq!(from[0].assets[0].asset_id, (0, 0));
+                assert_eq!(to.len(), 2);
+                assert_eq!(to[0].address, Some((1, None)));
+                assert_eq!(to[1].address, Some((0, Some(0))));
+                assert_eq!(fee, dcspark_core::Value::from(200000));
+            }
+            TxEvent::Partial { .. } => panic!("expected full event"),
+        }
+        assert!(banned_addresses.is_empty());
+        assert_eq!(asset_name_to_num.get(&"4d494e".to_string()), Some(0));
+    }
+}
@@ -1,30 +1,137 @@
 use std::path::PathBuf;
 
-use anyhow::anyhow;
-use cardano_multiplatform_lib::address::StakeCredential;
+use anyhow::{anyhow, Context};
+use cardano_multiplatform_lib::address::{ByronAddress, Pointer, PointerAddress, StakeCredential};
 
 use crate::mapper::DataMapper;
-use crate::tx_event::{TxAsset, TxEvent, TxOutput};
+use crate::tx_event::{byron_address_pair, TxAsset, TxEvent, TxOutput};
+use crate::utils::{checked_value_to_i64, dump_hashmap_to_file, read_hashmap_from_file};
+use crate::utxo_index::UtxoIndex;
 use cardano_multiplatform_lib::PolicyID;
+use pallas_traverse::{Era, MultiEraTx};
 
 use dcspark_core::Regulated;
+use serde::{Deserialize, Serialize};
 
 use entity::prelude::TransactionModel;
 
+use std::cmp::max;
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum UnparsedReason {
+    CborDecode,
+    MissingInput,
+    OutputParse,
+}
+
+// transaction that couldn't be converted into an event, kept to be retried later
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UnparsedTransaction {
+    pub reason: UnparsedReason,
+    pub error: String,
+    pub tx: TransactionModel,
+}
+
+impl UnparsedTransaction {
+    pub fn new(reason: UnparsedReason, error: &anyhow::Error, tx: &TransactionModel) -> Self {
+        Self {
+            reason,
+            error: format!("{:?}", error),
+            tx: tx.clone(),
+        }
+    }
+}
+
+// resolves pointer addresses to the stake credentials of the registration certificates.
+// certificates are registered only when the slots of the blocks are known
+#[derive(Default)]
+pub struct PointerResolver {
+    block_slots: HashMap<i32, u64>,
+    registrations: HashMap<(u64, u64, u64), StakeCredential>,
+    unresolved: u64,
+}
+
+impl PointerResolver {
+    pub fn set_block_slots(&mut self, block_slots: HashMap<i32, u64>) {
+        self.block_slots = block_slots;
+    }
+
+    pub fn register_certificates(
+        &mut self,
+        tx: &TransactionModel,
+        body: &cardano_multiplatform_lib::TransactionBody,
+    ) {
+        let (slot, certs) = match (self.block_slots.get(&tx.block_id), body.certs()) {
+            (Some(slot), Some(certs)) => (*slot, certs),
+            _ => return,
+        };
+        for cert_index in 0..certs.len() {
+            if let Some(registration) = certs.get(cert_index).as_stake_registration() {
+                self.registrations.insert(
+                    (slot, tx.tx_index as u64, cert_index as u64),
+                    registration.stake_credential(),
+                );
+            }
+        }
+    }
+
+    pub fn resolve(&mut self, pointer: &Pointer) -> Option<StakeCredential> {
+        let result = self.get((
+            u64::from(pointer.slot()),
+            u64::from(pointer.tx_index()),
+            u64::from(pointer.cert_index()),
+        ));
+        if result.is_none() {
+            self.unresolved += 1;
+        }
+        result
+    }
+
+    pub fn get(&self, pointer: (u64, u64, u64)) -> Option<StakeCredential> {
+        self.registrations.get(&pointer).cloned()
+    }
+
+    pub fn total_unresolved(&self) -> u64 {
+        self.unresolved
+    }
+
+    pub fn dump_to_file(&self, path: PathBuf) -> anyhow::Result<()> {
+        let registrations: HashMap<(u64, u64, u64), String> = self
+            .registrations
+            .iter()
+            .map(|(pointer, credential)| (*pointer, hex::encode(credential.to_bytes())))
+            .collect();
+        dump_hashmap_to_file(&registrations, path)
+    }
+
+    pub fn load_from_file(path: PathBuf) -> anyhow::Result<Self> {
+        let registrations: HashMap<(u64, u64, u64), String> = read_hashmap_from_file(path)?;
+        let mut result = Self::default();
+        for (pointer, credential) in registrations.into_iter() {
+            let credential = StakeCredential::from_bytes(hex::decode(credential)?)
+                .map_err(|err| anyhow!("can't decode stake credential: {:?}", err))?;
+            result.registrations.insert(pointer, credential);
+        }
+        Ok(result)
+    }
+}
 
 #[allow(clippy::too_many_arguments)]
-pub fn carp_tx_to_events(
+pub fn carp_tx_to_events<Index: UtxoIndex>(
     tx: &TransactionModel,
-    previous_outputs: &mut HashMap<String, HashMap<u64, TxOutput>>,
+    previous_outputs: &mut Index,
     stake_address_to_num: &mut DataMapper<StakeCredential>,
     payment_address_to_num: &mut DataMapper<StakeCredential>,
     policy_id_to_num: &mut DataMapper<PolicyID>,
     asset_name_to_num: &mut DataMapper<String>,
+    mut byron_address_to_num: Option<&mut DataMapper<String>>,
+    pointer_resolver: &mut PointerResolver,
     banned_addresses: &mut HashSet<(u64, Option<u64>)>,
-    unparsed_transactions: &mut Vec<TransactionModel>,
+    unparsed_transactions: &mut Vec<UnparsedTransaction>,
 ) -> anyhow::Result<Option<TxEvent>> {
     let payload: &Vec<u8> = &tx.payload;
     let tx_hash = hex::encode(tx.hash.clone());
@@ -33,43 +140,42 @@ pub fn carp_tx_to_events(
             let body = parsed.body();
             // inputs handle
             let inputs = body.inputs();
+            let inputs = (0..inputs.len())
+                .map(|index| {
+                    let input = inputs.get(index);
+                    (input.transaction_id().to_hex(), u64::from(input.index()))
+                })
+                .collect::<Vec<_>>();
 
-            let (has_banned_addresses, input_events) = match get_input_intents(
-                &tx_hash,
-                tx.id as u64,
-                inputs,
-                previous_outputs,
-                banned_addresses,
-            ) {
-                Ok(output) => output,
-                Err(err) => {
-                    tracing::warn!("error occurred while trying to get inputs: {:?}", err);
-                    unparsed_transactions.push(tx.clone());
-                    return Ok(None);
-                }
-            };
-
-            if has_banned_addresses {
-                ban_addresses_for_events(&input_events, banned_addresses)?;
-            }
+            // outputs can point to the stake registrations of the same tx
+            pointer_resolver.register_certificates(tx, &body);
 
-            // outputs handle
+            // outputs are parsed first, so the inputs are not spent by a tx that can't be parsed
             let outputs = body.outputs();
             let output_events = match get_output_intents(
-                &tx_hash,
                 outputs,
-                previous_outputs,
                 payment_address_to_num,
                 stake_address_to_num,
                 policy_id_to_num,
                 asset_name_to_num,
+                byron_address_to_num.as_deref_mut(),
+                pointer_resolver,
             ) {
                 Ok(result) => result,
                 Err(err) => {
                     tracing::warn!("error occurred while trying to get outputs: {:?}", err);
-                    unparsed_transactions.push(tx.clone());
-                    for input in input_events.into_iter() {
-                        if let Some(addr) = input.address {
+                    unparsed_transactions.push(UnparsedTransaction::new(
+                        UnparsedReason::OutputParse,
+                        &err,
+                        tx,
+                    ));
+                    // the owners of the inputs are banned, the inputs stay unspent for a retry
+                    for (input_tx_id, input_tx_index) in inputs.iter() {
+                        if let Some(TxOutput {
+                            address: Some(addr),
+                            ..
+                        }) = previous_outputs.get(input_tx_id, *input_tx_index)?
+                        {
                             banned_addresses.insert(addr);
                         }
                     }
@@ -77,66 +183,222 @@ pub fn carp_tx_to_events(
                 }
             };
 
-            let event = if has_banned_addresses {
-                let output_events: Vec<TxOutput> = output_events
-                    .into_iter()
-                    .filter(|output| !output.is_byron() && !output.is_banned(banned_addresses))
-                    .collect();
-                if output_events.is_empty() {
-                    None
-                } else {
-                    Some(TxEvent::Partial { to: output_events })
+            let (has_banned_addresses, input_events) = match get_input_intents(
+                &tx_hash,
+                tx.id as u64,
+                inputs,
+                previous_outputs,
+                banned_addresses,
+            )? {
+                Ok(output) => output,
+                Err(err) => {
+                    tracing::warn!("error occurred while trying to get inputs: {:?}", err);
+                    unparsed_transactions.push(UnparsedTransaction::new(
+                        UnparsedReason::MissingInput,
+                        &err,
+                        tx,
+                    ));
+                    return Ok(None);
                 }
-            } else {
-                Some(TxEvent::Full {
-                    to: output_events,
-                    fee: dcspark_core::Value::<Regulated>::from(u64::from(body.fee())),
-                    from: input_events,
-                })
             };
 
-            if let Some(event) = event {
-                match &event {
-                    TxEvent::Full { to, fee, from } => {
-                        let mut input_value = dcspark_core::Value::zero();
-                        let mut output_value = dcspark_core::Value::zero();
-                        for to in to.iter() {
-                            output_value += &to.value;
+            if has_banned_addresses {
+                ban_addresses_for_events(&input_events, banned_addresses)?;
+            }
+
+            register_outputs(&tx_hash, &output_events, previous_outputs)?;
+
+            return Ok(build_event(
+                has_banned_addresses,
+                input_events,
+                output_events,
+                dcspark_core::Value::<Regulated>::from(u64::from(body.fee())),
+                banned_addresses,
+            ));
+        }
+        Err(err) => {
+            let (reason, err) = if let Some(byron_address_to_num) = byron_address_to_num {
+                match byron_tx_to_events(
+                    tx,
+                    &tx_hash,
+                    previous_outputs,
+                    byron_address_to_num,
+                    banned_addresses,
+                )? {
+                    Ok(event) => return Ok(event),
+                    Err((reason, byron_err)) => {
+                        tracing::warn!(
+                            "Can't parse tx: {:?}, err: {:?}, byron err: {:?}",
+                            tx_hash.clone(),
+                            err,
+                            byron_err
+                        );
+                        match reason {
+                            UnparsedReason::CborDecode => (
+                                reason,
+                                anyhow!("can't decode tx: {:?}, {:?}", err, byron_err),
+                            ),
+                            _ => (reason, byron_err),
                         }
-                        output_value += fee;
-                        for from in from.iter() {
-                            input_value += &from.value;
+                    }
+                }
+            } else {
+                tracing::warn!("Can't parse tx: {:?}, err: {:?}", tx_hash.clone(), err);
+                (
+                    UnparsedReason::CborDecode,
+                    anyhow!("can't decode tx: {:?}", err),
+                )
+            };
+            unparsed_transactions.push(UnparsedTransaction::new(reason, &err, tx));
+        }
+    }
+    Ok(None)
+}
+
+// byron era transactions only have ada outputs to byron addresses and no explicit fee.
+// the outer error is an index failure, the inner one describes why the tx can't be parsed
+fn byron_tx_to_events<Index: UtxoIndex>(
+    tx: &TransactionModel,
+    tx_hash: &str,
+    previous_outputs: &mut Index,
+    byron_address_to_num: &mut DataMapper<String>,
+    banned_addresses: &mut HashSet<(u64, Option<u64>)>,
+) -> anyhow::Result<Result<Option<TxEvent>, (UnparsedReason, anyhow::Error)>> {
+    let parsed = match MultiEraTx::decode(Era::Byron, &tx.payload) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            return Ok(Err((
+                UnparsedReason::CborDecode,
+                anyhow!("can't decode byron tx: {:?}", err),
+            )))
+        }
+    };
+
+    // outputs are parsed first, so the inputs are not spent by a tx that can't be parsed
+    let mut output_events = Vec::new();
+    for output in parsed.outputs().iter() {
+        let address = match output.address() {
+            Ok(pallas_addresses::Address::Byron(address)) => Some(byron_address_pair(
+                byron_address_to_num.add_if_not_presented(address.to_base58()),
+            )),
+            Ok(_) => None,
+            Err(err) => {
+                return Ok(Err((
+                    UnparsedReason::OutputParse,
+                    anyhow!("can't parse byron output address: {:?}", err),
+                )))
+            }
+        };
+        output_events.push(TxOutput {
+            address,
+            value: dcspark_core::Value::<Regulated>::from(output.lovelace_amount()),
+            assets: vec![],
+        });
+    }
+
+    let inputs = parsed
+        .inputs()
+        .iter()
+        .map(|input| (input.hash().to_string(), input.index()))
+        .collect::<Vec<_>>();
+    let (has_banned_addresses, input_events) = match get_input_intents(
+        &tx_hash.to_string(),
+        tx.id as u64,
+        inputs,
+        previous_outputs,
+        banned_addresses,
+    )? {
+        Ok(result) => result,
+        Err(err) => return Ok(Err((UnparsedReason::MissingInput, err))),
+    };
+
+    if has_banned_addresses {
+        ban_addresses_for_events(&input_events, banned_addresses)?;
+    }
+
+    register_outputs(tx_hash, &output_events, previous_outputs)?;
+
+    let input_value = input_events
+        .iter()
+        .map(|input| checked_value_to_i64(&input.value))
+        .sum::<anyhow::Result<i64>>()?;
+    let output_value = output_events
+        .iter()
+        .map(|output| checked_value_to_i64(&output.value))
+        .sum::<anyhow::Result<i64>>()?;
+    // outputs exceeding inputs make the fee negative, such transactions are banned by the balance check
+    let fee = dcspark_core::Value::<Regulated>::from(max(input_value - output_value, 0) as u64);
+
+    Ok(Ok(build_event(
+        has_banned_addresses,
+        input_events,
+        output_events,
+        fee,
+        banned_addresses,
+    )))
+}
+
+pub(crate) fn build_event(
+    has_banned_addresses: bool,
+    input_events: Vec<TxOutput>,
+    output_events: Vec<TxOutput>,
+    fee: dcspark_core::Value<Regulated>,
+    banned_addresses: &mut HashSet<(u64, Option<u64>)>,
+) -> Option<TxEvent> {
+    let event = if has_banned_addresses {
+        let output_events: Vec<TxOutput> = output_events
+            .into_iter()
+            .filter(|output| !output.is_byron() && !output.is_banned(banned_addresses))
+            .collect();
+        if output_events.is_empty() {
+            None
+        } else {
+            Some(TxEvent::Partial { to: output_events })
+        }
+    } else {
+        Some(TxEvent::Full {
+            to: output_events,
+            fee,
+            from: input_events,
+        })
+    };
+
+    if let Some(event) = event {
+        match &event {
+            TxEvent::Full { to, fee, from } => {
+                let mut input_value = dcspark_core::Value::zero();
+                let mut output_value = dcspark_core::Value::zero();
+                for to in to.iter() {
+                    output_value += &to.value;
+                }
+                output_value += fee;
+                for from in from.iter() {
+                    input_value += &from.value;
+                }
+                if input_value != output_value {
+                    for input in from.iter() {
+                        if let Some(addr) = input.address {
+                            banned_addresses.insert(addr);
                         }
-                        if input_value != output_value {
-                            for input in from.iter() {
-                                if let Some(addr) = input.address {
-                                    banned_addresses.insert(addr);
-                                }
-                            }
-                            for output in to.iter() {
-                                if let Some(addr) = output.address {
-                                    banned_addresses.insert(addr);
-                                }
-                            }
-                            return Ok(None);
+                    }
+                    for output in to.iter() {
+                        if let Some(addr) = output.address {
+                            banned_addresses.insert(addr);
                         }
                     }
-                    TxEvent::Partial { .. } => {}
+                    return None;
                 }
-                return Ok(Some(event));
             }
+            TxEvent::Partial { .. } => {}
         }
-        Err(err) => {
-            tracing::warn!("Can't parse tx: {:?}, err: {:?}", tx_hash.clone(), err);
-            unparsed_transactions.push(tx.clone());
-        }
+        return Some(event);
     }
-    Ok(None)
+    None
 }
 
 pub fn dump_unparsed_transactions_to_file(
     path: PathBuf,
-    txs: Vec<TransactionModel>,
+    txs: Vec<UnparsedTransaction>,
 ) -> anyhow::Result<()> {
     let mut output = File::create(path)?;
     output.write_all(format!("{}\n", txs.len()).as_bytes())?;
@@ -146,31 +408,264 @@ pub fn dump_unparsed_transactions_to_file(
     Ok(())
 }
 
+pub fn read_unparsed_transactions_from_file(
+    path: PathBuf,
+) -> anyhow::Result<Vec<UnparsedTransaction>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let count: usize = match lines.next() {
+        Some(count) => serde_json::from_str(count?.as_str())?,
+        None => {
+            return Err(anyhow!(
+                "Can't parse first line: expected to see count of values"
+            ))
+        }
+    };
+    let mut result = Vec::with_capacity(count);
+    for (num, line) in lines.enumerate() {
+        let tx: UnparsedTransaction = serde_json::from_str(line?.as_str())
+            .context(format!("Transaction at line: {}", num + 2))?;
+        result.push(tx);
+    }
+    if result.len() != count {
+        return Err(anyhow!("Data corrupted: lines count mismatch"));
+    }
+    Ok(result)
+}
+
+// raw addresses of the outputs, pallas is used for the transactions cml can't decode
+pub fn unparsed_output_addresses(tx: &TransactionModel) -> anyhow::Result<Vec<Vec<u8>>> {
+    if let Ok(parsed) = cardano_multiplatform_lib::Transaction::from_bytes(tx.payload.clone()) {
+        let outputs = parsed.body().outputs();
+        return Ok((0..outputs.len())
+            .map(|index| outputs.get(index).address().to_bytes())
+            .collect());
+    }
+    for era in [
+        Era::Babbage,
+        Era::Alonzo,
+        Era::Mary,
+        Era::Allegra,
+        Era::Shelley,
+        Era::Byron,
+    ] {
+        if let Ok(parsed) = MultiEraTx::decode(era, &tx.payload) {
+            let mut result = Vec::new();
+            for output in parsed.outputs().iter() {
+                match output.address() {
+                    Ok(address) => result.push(address.to_vec()),
+                    Err(err) => tracing::warn!(
+                        "can't decode output address of tx {}: {:?}",
+                        hex::encode(&tx.hash),
+                        err
+                    ),
+                }
+            }
+            return Ok(result);
+        }
+    }
+    Err(anyhow!("can't decode tx: {}", hex::encode(&tx.hash)))
+}
+
+// unspent outputs are kept as `[tx_hash, index, output]` lines, the output json contains ':'
+pub fn dump_previous_outputs_to_file<Index: UtxoIndex>(
+    previous_outputs: &Index,
+    path: PathBuf,
+) -> anyhow::Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+    output.write_all(format!("{}\n", previous_outputs.output_count()).as_bytes())?;
+    for entry in previous_outputs.outputs() {
+        let (tx_hash, index, tx_output) = entry?;
+        output.write_all(
+            format!("{}\n", serde_json::to_string(&(tx_hash, index, tx_output))?).as_bytes(),
+        )?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+pub fn read_previous_outputs_from_file(
+    path: PathBuf,
+) -> anyhow::Result<HashMap<String, HashMap<u64, TxOutput>>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let count: usize = match lines.next() {
+        Some(count) => serde_json::from_str(count?.as_str())?,
+        None => {
+            return Err(anyhow!(
+                "Can't parse first line: expected to see count of values"
+            ))
+        }
+    };
+    let mut result = HashMap::<String, HashMap<u64, TxOutput>>::new();
+    let mut read: usize = 0;
+    for (num, line) in lines.enumerate() {
+        let (tx_hash, index, tx_output): (String, u64, TxOutput) =
+            serde_json::from_str(line?.as_str()).context(format!("Output at line: {}", num + 2))?;
+        result.entry(tx_hash).or_default().insert(index, tx_output);
+        read += 1;
+    }
+    if read != count {
+        return Err(anyhow!("Data corrupted: lines count mismatch"));
+    }
+    Ok(result)
+}
+
+// what `clean_events` did to the events, the addresses are counted once per affected event
+#[derive(Clone, Debug, Default)]
+pub struct CleaningStats {
+    pub total: u64,
+    pub unchanged: u64,
+    // full events spending from banned or unknown addresses
+    pub converted_to_partial: u64,
+    // full events with banned outputs, the outputs became unknown
+    pub outputs_hidden: u64,
+    pub partial_outputs_removed: u64,
+    pub dropped: u64,
+    pub affected_by_address: HashMap<(u64, Option<u64>), u64>,
+}
+
+impl CleaningStats {
+    fn count_banned(
+        &mut self,
+        outputs: &[TxOutput],
+        banned_addresses: &HashSet<(u64, Option<u64>)>,
+    ) {
+        let affected: HashSet<(u64, Option<u64>)> = outputs
+            .iter()
+            .filter(|output| output.is_banned(banned_addresses))
+            .filter_map(|output| output.address)
+            .collect();
+        for address in affected {
+            *self.affected_by_address.entry(address).or_default() += 1;
+        }
+    }
+}
+
 pub fn clean_events(
     events_output_path: PathBuf,
     cleaned_events_output_path: PathBuf,
     banned_addresses: &HashSet<(u64, Option<u64>)>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<CleaningStats> {
+    let cleaned_file = File::create(cleaned_events_output_path)?;
+    clean_events_to_file(events_output_path, cleaned_file, banned_addresses, 0)
+}
+
+// cleans the events appended after the first `first_event` ones and appends them to the cleaned file
+pub fn clean_events_tail(
+    events_output_path: PathBuf,
+    cleaned_events_output_path: PathBuf,
+    banned_addresses: &HashSet<(u64, Option<u64>)>,
+    first_event: usize,
+) -> anyhow::Result<CleaningStats> {
+    let cleaned_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(cleaned_events_output_path)?;
+    clean_events_to_file(
+        events_output_path,
+        cleaned_file,
+        banned_addresses,
+        first_event,
+    )
+}
+
+// drops the events after the first `count` ones, returns the number of dropped events
+pub fn truncate_events(events_path: PathBuf, count: usize) -> anyhow::Result<usize> {
+    let mut reader = BufReader::new(File::open(events_path.clone())?);
+    let mut kept_bytes: u64 = 0;
+    let mut line = String::new();
+    for _ in 0..count {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || !line.ends_with('\n') {
+            return Err(anyhow!(
+                "events file {:?} has less than {} events",
+                events_path,
+                count
+            ));
+        }
+        kept_bytes += read as u64;
+    }
+    let mut dropped = 0;
+    for line in reader.lines() {
+        line?;
+        dropped += 1;
+    }
+    OpenOptions::new()
+        .write(true)
+        .open(events_path)?
+        .set_len(kept_bytes)?;
+    Ok(dropped)
+}
+
+pub fn count_events(events_path: PathBuf) -> anyhow::Result<usize> {
+    let mut count = 0;
+    for line in BufReader::new(File::open(events_path)?).lines() {
+        line?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+// any of the first `events_limit` events spends from or pays to one of the addresses
+pub fn events_contain_addresses(
+    events_path: PathBuf,
+    events_limit: usize,
+    addresses: &HashSet<(u64, Option<u64>)>,
+) -> anyhow::Result<bool> {
+    let lines = BufReader::new(File::open(events_path)?).lines();
+    for line in lines.take(events_limit) {
+        let event: TxEvent = serde_json::from_str(line?.as_str())?;
+        let found = match &event {
+            TxEvent::Partial { to } => to.iter().any(|output| output.is_banned(addresses)),
+            TxEvent::Full { to, from, .. } => to
+                .iter()
+                .chain(from.iter())
+                .any(|output| output.is_banned(addresses)),
+        };
+        if found {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn clean_events_to_file(
+    events_output_path: PathBuf,
+    mut cleaned_file: File,
+    banned_addresses: &HashSet<(u64, Option<u64>)>,
+    first_event: usize,
+) -> anyhow::Result<CleaningStats> {
     let file = File::open(events_output_path)?;
-    let mut cleaned_file = File::create(cleaned_events_output_path)?;
+    let mut stats = CleaningStats::default();
 
     let reader = BufReader::new(file);
-    let lines = reader.lines();
+    let lines = reader.lines().skip(first_event);
     for (num, line) in lines.enumerate() {
         let event: TxEvent = serde_json::from_str(line?.as_str())?;
+        stats.total += 1;
         let event = match event {
             TxEvent::Partial { to } => {
+                stats.count_banned(&to, banned_addresses);
+                let total_outputs = to.len();
                 let to: Vec<TxOutput> = to
                     .into_iter()
                     .filter(|output| !output.is_byron() && !output.is_banned(banned_addresses))
                     .collect();
                 if !to.is_empty() {
+                    if to.len() == total_outputs {
+                        stats.unchanged += 1;
+                    } else {
+                        stats.partial_outputs_removed += 1;
+                    }
                     Some(TxEvent::Partial { to })
                 } else {
+                    stats.dropped += 1;
                     None
                 }
             }
             TxEvent::Full { to, fee, from } => {
+                stats.count_banned(&from, banned_addresses);
+                stats.count_banned(&to, banned_addresses);
                 if from
                     .iter()
                     .any(|input| input.is_byron() || input.is_banned(banned_addresses))
@@ -180,11 +675,18 @@ pub fn clean_events(
                         .filter(|output| !output.is_byron() && !output.is_banned(banned_addresses))
                         .collect();
                     if !new_to.is_empty() {
+                        stats.converted_to_partial += 1;
                         Some(TxEvent::Partial { to: new_to })
                     } else {
+                        stats.dropped += 1;
                         None
                     }
                 } else {
+                    if to.iter().any(|output| output.is_banned(banned_addresses)) {
+                        stats.outputs_hidden += 1;
+                    } else {
+                        stats.unchanged += 1;
+                    }
                     let new_to: Vec<TxOutput> = to
                         .into_iter()
                         .map(|mut output| {
@@ -210,75 +712,73 @@ pub fn clean_events(
         }
     }
 
-    Ok(())
+    Ok(stats)
 }
 
-fn get_input_intents(
+// the outer error is an index failure, the inner one is a missing input
+fn get_input_intents<Index: UtxoIndex>(
     tx_hash: &String,
     tx_id: u64,
-    inputs: cardano_multiplatform_lib::TransactionInputs,
-    previous_outputs: &mut HashMap<String, HashMap<u64, TxOutput>>,
+    inputs: Vec<(String, u64)>,
+    previous_outputs: &mut Index,
     banned_addresses: &HashSet<(u64, Option<u64>)>,
-) -> anyhow::Result<(bool, Vec<TxOutput>)> {
+) -> anyhow::Result<anyhow::Result<(bool, Vec<TxOutput>)>> {
     let mut has_byron_inputs = false;
 
     // try to parse input addresses and put in the set
     let mut parsed_inputs = Vec::new();
     let mut inputs_pointers = HashSet::<(String, u64)>::new();
-    let mut seen_tx_ids = Vec::new();
-
-    for input_index in 0..inputs.len() {
-        let input = inputs.get(input_index);
-        let input_tx_id = input.transaction_id().to_hex();
-        let input_tx_index = u64::from(input.index());
-
-        // try to find output that is now used as an input
-        if let Some(outputs) = &mut previous_outputs.get_mut(&input_tx_id) {
-            // we remove the spent input from the list
-            if let Some(output) = outputs.remove(&input_tx_index) {
-                inputs_pointers.insert((input_tx_id.clone(), input_tx_index));
-                parsed_inputs.push(output);
-            } else {
-                if inputs_pointers.contains(&(input_tx_id.clone(), input_tx_index)) {
-                    tracing::info!("Found tx using same output as an input multiple times: {:?}@{:?}, current tx: {:?}, id: {:?}",
-                        input_tx_id,
-                        input_tx_index,
-                        tx_hash,
-                        tx_id,
-                    );
-                    continue;
-                }
-                // invalid transaction
-                tracing::warn!(
-                    "Can't find matching output for used input: {:?}@{:?}, current tx: {:?}, id: {:?}",
-                    input_tx_id,
-                    input_tx_index,
-                    tx_hash,
-                    tx_id,
-                );
-                return Err(anyhow!(
-                    "Can't find matching output for used input: {:?}@{:?}, current tx: {:?}, id: {:?}",
-                    input.transaction_id().to_hex(),
-                    input.index(),
-                    tx_hash,
-                    tx_id,
-                ));
-            }
+    let mut spent = Vec::new();
+    let mut missing_input = None;
+
+    for (input_tx_id, input_tx_index) in inputs.into_iter() {
+        if inputs_pointers.contains(&(input_tx_id.clone(), input_tx_index)) {
+            tracing::info!("Found tx using same output as an input multiple times: {:?}@{:?}, current tx: {:?}, id: {:?}",
+                input_tx_id,
+                input_tx_index,
+                tx_hash,
+                tx_id,
+            );
+            continue;
+        }
+        // try to find output that is now used as an input, we remove the spent input from the index
+        if let Some(output) = previous_outputs.remove(&input_tx_id, input_tx_index)? {
+            inputs_pointers.insert((input_tx_id.clone(), input_tx_index));
+            spent.push((input_tx_id, input_tx_index));
+            parsed_inputs.push(output);
+        } else if spent
+            .iter()
+            .any(|(spent_tx_id, _)| spent_tx_id == &input_tx_id)
+            || previous_outputs.contains_transaction(&input_tx_id)?
+        {
+            // invalid transaction
+            tracing::warn!(
+                "Can't find matching output for used input: {:?}@{:?}, current tx: {:?}, id: {:?}",
+                input_tx_id,
+                input_tx_index,
+                tx_hash,
+                tx_id,
+            );
+            missing_input = Some(anyhow!(
+                "Can't find matching output for used input: {:?}@{:?}, current tx: {:?}, id: {:?}",
+                input_tx_id,
+                input_tx_index,
+                tx_hash,
+                tx_id,
+            ));
+            break;
         } else {
-            has_byron_inputs = true; // might be byron address or sth
+            // output created before the first fetched transaction: byron era unless replayed from genesis
+            has_byron_inputs = true;
         }
-
-        seen_tx_ids.push(input_tx_id);
     }
 
-    for seen_id in seen_tx_ids {
-        if previous_outputs
-            .get(&seen_id)
-            .map(|outputs| outputs.is_empty())
-            .unwrap_or(false)
-        {
-            previous_outputs.remove(&seen_id);
+    if let Some(err) = missing_input {
+        // the transaction can be retried later, so the spent outputs are not lost
+        for ((spent_tx_id, spent_index), output) in spent.into_iter().zip(parsed_inputs) {
+            previous_outputs.insert(&spent_tx_id, spent_index, output)?;
         }
+        return Ok(Err(err));
     }
 
     let has_banned_addresses = parsed_inputs.iter().any(|input| {
@@ -286,83 +786,169 @@ fn get_input_intents(
             || (input.address.is_some() && banned_addresses.contains(&input.address.unwrap()))
     });
 
-    Ok((has_byron_inputs || has_banned_addresses, parsed_inputs))
+    Ok(Ok((
+        has_byron_inputs || has_banned_addresses,
+        parsed_inputs,
+    )))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn get_output_intents(
-    tx_hash: &str,
     outputs: cardano_multiplatform_lib::TransactionOutputs,
-    previous_outputs: &mut HashMap<String, HashMap<u64, TxOutput>>,
     payment_address_mapping: &mut DataMapper<StakeCredential>,
     stake_address_mapping: &mut DataMapper<StakeCredential>,
     policy_to_num: &mut DataMapper<PolicyID>,
     asset_name_to_num: &mut DataMapper<String>,
+    byron_address_to_num: Option<&mut DataMapper<String>>,
+    pointer_resolver: &mut PointerResolver,
 ) -> anyhow::Result<Vec<TxOutput>> {
+    let mut byron_address_to_num = byron_address_to_num;
     let mut parsed_outputs = Vec::new();
     for output_index in 0..outputs.len() {
-        let output = outputs.get(output_index);
+        parsed_outputs.push(parse_output(
+            &outputs.get(output_index),
+            payment_address_mapping,
+            stake_address_mapping,
+            policy_to_num,
+            asset_name_to_num,
+            byron_address_to_num.as_deref_mut(),
+            pointer_resolver,
+        ));
+    }
+
+    Ok(parsed_outputs)
+}
+
+// outputs spent by the listed transactions but created by the transactions which are not fetched
+pub fn carp_output_to_tx_output(
+    payload: &[u8],
+    payment_address_mapping: &mut DataMapper<StakeCredential>,
+    stake_address_mapping: &mut DataMapper<StakeCredential>,
+    policy_to_num: &mut DataMapper<PolicyID>,
+    asset_name_to_num: &mut DataMapper<String>,
+    byron_address_to_num: Option<&mut DataMapper<String>>,
+    pointer_resolver: &mut PointerResolver,
+) -> anyhow::Result<TxOutput> {
+    let output = cardano_multiplatform_lib::TransactionOutput::from_bytes(payload.to_vec())
+        .map_err(|err| anyhow!("can't parse output: {:?}", err))?;
+    Ok(parse_output(
+        &output,
+        payment_address_mapping,
+        stake_address_mapping,
+        policy_to_num,
+        asset_name_to_num,
+        byron_address_to_num,
+        pointer_resolver,
+    ))
+}
+
+// inputs of the transaction, empty if the transaction can't be decoded
+pub fn carp_tx_inputs(tx: &TransactionModel) -> Vec<(String, u64)> {
+    if let Ok(parsed) = cardano_multiplatform_lib::Transaction::from_bytes(tx.payload.clone()) {
+        let inputs = parsed.body().inputs();
+        return (0..inputs.len())
+            .map(|index| {
+                let input = inputs.get(index);
+                (input.transaction_id().to_hex(), u64::from(input.index()))
+            })
+            .collect();
+    }
+    match MultiEraTx::decode(Era::Byron, &tx.payload) {
+        Ok(parsed) => parsed
+            .inputs()
+            .iter()
+            .map(|input| (input.hash().to_string(), input.index()))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
 
-        let address = output.address();
-        let address = match address.payment_cred() {
-            None => {
-                // this is byron output
-                None
+fn parse_output(
+    output: &cardano_multiplatform_lib::TransactionOutput,
+    payment_address_mapping: &mut DataMapper<StakeCredential>,
+    stake_address_mapping: &mut DataMapper<StakeCredential>,
+    policy_to_num: &mut DataMapper<PolicyID>,
+    asset_name_to_num: &mut DataMapper<String>,
+    byron_address_to_num: Option<&mut DataMapper<String>>,
+    pointer_resolver: &mut PointerResolver,
+) -> TxOutput {
+    let address = output.address();
+    let address = match address.payment_cred() {
+        None => {
+            // this is byron output
+            match (byron_address_to_num, ByronAddress::from_address(&address)) {
+                (Some(mapper), Some(byron)) => Some(byron_address_pair(
+                    mapper.add_if_not_presented(byron.to_base58()),
+                )),
+                _ => None,
             }
-            Some(payment) => {
-                let payment_mapping = payment_address_mapping.add_if_not_presented(payment);
-                let staking_mapping = address
-                    .staking_cred()
-                    .map(|staking| stake_address_mapping.add_if_not_presented(staking));
-                Some((payment_mapping, staking_mapping))
+        }
+        Some(payment) => {
+            let staking = match PointerAddress::from_address(&address) {
+                Some(pointer_address) => pointer_resolver
+                    .resolve(&pointer_address.stake_pointer())
+                    .map(Some),
+                None => Some(address.staking_cred()),
+            };
+            match staking {
+                // unknown pointer: the output can't be attributed to any wallet
+                None => None,
+                Some(staking) => {
+                    let payment_mapping = payment_address_mapping.add_if_not_presented(payment);
+                    let staking_mapping =
+                        staking.map(|staking| stake_address_mapping.add_if_not_presented(staking));
+                    Some((payment_mapping, staking_mapping))
+                }
             }
-        };
+        }
+    };
 
-        let amount = output.amount();
-        let value = dcspark_core::Value::<Regulated>::from(u64::from(amount.coin()));
-        let mut assets = Vec::new();
-
-        if let Some(multiasset) = amount.multiasset() {
-            let policy_ids = multiasset.keys();
-            for policy_id_index in 0..policy_ids.len() {
-                let policy_id = policy_ids.get(policy_id_index);
-                if let Some(assets_by_policy_id) = multiasset.get(&policy_id) {
-                    let asset_names = assets_by_policy_id.keys();
-                    for asset_name_id in 0..asset_names.len() {
-                        let asset_name = asset_names.get(asset_name_id);
-                        let asset_value = assets_by_policy_id.get(&asset_name);
-                        if let Some(asset_value) = asset_value {
-                            let policy_mapping =
-                                policy_to_num.add_if_not_presented(policy_id.clone());
-                            let asset_name_mapping = asset_name_to_num
-                                .add_if_not_presented(hex::encode(asset_name.name()));
-                            assets.push(TxAsset {
-                                asset_id: (policy_mapping, asset_name_mapping),
-                                value: dcspark_core::Value::<Regulated>::from(u64::from(
-                                    asset_value,
-                                )),
-                            })
-                        }
+    let amount = output.amount();
+    let value = dcspark_core::Value::<Regulated>::from(u64::from(amount.coin()));
+    let mut assets = Vec::new();
+
+    if let Some(multiasset) = amount.multiasset() {
+        let policy_ids = multiasset.keys();
+        for policy_id_index in 0..policy_ids.len() {
+            let policy_id = policy_ids.get(policy_id_index);
+            if let Some(assets_by_policy_id) = multiasset.get(&policy_id) {
+                let asset_names = assets_by_policy_id.keys();
+                for asset_name_id in 0..asset_names.len() {
+                    let asset_name = asset_names.get(asset_name_id);
+                    let asset_value = assets_by_policy_id.get(&asset_name);
+                    if let Some(asset_value) = asset_value {
+                        let policy_mapping = policy_to_num.add_if_not_presented(policy_id.clone());
+                        let asset_name_mapping =
+                            asset_name_to_num.add_if_not_presented(hex::encode(asset_name.name()));
+                        assets.push(TxAsset {
+                            asset_id: (policy_mapping, asset_name_mapping),
+                            value: dcspark_core::Value::<Regulated>::from(u64::from(asset_value)),
+                        })
                     }
                 }
             }
         }
-
-        parsed_outputs.push(TxOutput {
-            address,
-            value,
-            assets,
-        })
     }
 
-    let entry = previous_outputs.entry(tx_hash.to_owned()).or_default();
-    for (output_index, parsed_output) in parsed_outputs.iter().enumerate() {
-        entry.insert(output_index as u64, parsed_output.clone());
+    TxOutput {
+        address,
+        value,
+        assets,
     }
+}
 
-    Ok(parsed_outputs)
+fn register_outputs<Index: UtxoIndex>(
+    tx_hash: &str,
+    outputs: &[TxOutput],
+    previous_outputs: &mut Index,
+) -> anyhow::Result<()> {
+    for (output_index, output) in outputs.iter().enumerate() {
+        previous_outputs.insert(tx_hash, output_index as u64, output.clone())?;
+    }
+    Ok(())
 }
 
-fn ban_addresses_for_events(
+pub(crate) fn ban_addresses_for_events(
     events: &[TxOutput],
     banned_addresses: &mut HashSet<(u64, Option<u64>)>,
 ) -> anyhow::Result<()> {
@@ -373,3 +959,254 @@ fn ban_addresses_for_events(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::generation_utils::{
+        carp_tx_to_events, clean_events, clean_events_tail, count_events,
+        dump_previous_outputs_to_file, events_contain_addresses, get_input_intents,
+        read_previous_outputs_from_file, truncate_events, PointerResolver, UnparsedReason,
+    };
+    use crate::mapper::DataMapper;
+    use crate::tx_event::{TxEvent, TxOutput};
+    use cardano_multiplatform_lib::crypto::TransactionHash;
+    use cardano_multiplatform_lib::ledger::common::value::BigNum;
+    use cardano_multiplatform_lib::{
+        Transaction, TransactionBody, TransactionInput, TransactionInputs, TransactionOutput,
+        TransactionOutputs, TransactionWitnessSet,
+    };
+    use dcspark_core::{Regulated, Value};
+    use entity::prelude::TransactionModel;
+    use std::collections::{HashMap, HashSet};
+    use std::io::Write;
+
+    fn output(value: u64) -> TxOutput {
+        TxOutput {
+            address: Some((value, None)),
+            value: Value::<Regulated>::from(value),
+            assets: vec![],
+        }
+    }
+
+    #[test]
+    fn missing_input_keeps_spent_outputs() {
+        let mut previous_outputs = HashMap::<String, HashMap<u64, TxOutput>>::new();
+        previous_outputs
+            .entry("aa".to_string())
+            .or_default()
+            .insert(0, output(1));
+
+        let result = get_input_intents(
+            &"bb".to_string(),
+            1,
+            vec![("aa".to_string(), 0), ("aa".to_string(), 1)],
+            &mut previous_outputs,
+            &HashSet::new(),
+        )
+        .unwrap();
+        assert!(result.is_err());
+        assert_eq!(previous_outputs["aa"][&0], output(1));
+
+        let (has_banned, inputs) = get_input_intents(
+            &"bb".to_string(),
+            1,
+            vec![("aa".to_string(), 0)],
+            &mut previous_outputs,
+            &HashSet::new(),
+        )
+        .unwrap()
+        .unwrap();
+        assert!(!has_banned);
+        assert_eq!(inputs, vec![output(1)]);
+        assert!(previous_outputs.is_empty());
+    }
+
+    const ADDRESS: &str = "addr1qx2kd28nq8ac5prwg32hhvudlwggpgfp8utlyqxu6wqgz62f79qsdmm5dsknt9ecr5w468r9ey0fxwkdrwh08ly3tu9sy0f4qd";
+
+    // shelley tx paying `value` to ADDRESS
+    fn transaction(id: i64, inputs: &[(&str, u64)], value: u64, fee: u64) -> TransactionModel {
+        let mut tx_inputs = TransactionInputs::new();
+        for (tx_hash, index) in inputs.iter() {
+            tx_inputs.add(&TransactionInput::new(
+                &TransactionHash::from_bytes(hex::decode(tx_hash).unwrap()).unwrap(),
+                &BigNum::from(*index),
+            ));
+        }
+        let mut tx_outputs = TransactionOutputs::new();
+        tx_outputs.add(&TransactionOutput::new(
+            &cardano_multiplatform_lib::address::Address::from_bech32(ADDRESS).unwrap(),
+            &cardano_multiplatform_lib::ledger::common::value::Value::new(&BigNum::from(value)),
+        ));
+        let body = TransactionBody::new(&tx_inputs, &tx_outputs, &BigNum::from(fee), None);
+        let tx = Transaction::new(&body, &TransactionWitnessSet::new(), None);
+        TransactionModel {
+            id,
+            hash: vec![id as u8; 32],
+            block_id: 1,
+            tx_index: 0,
+            payload: tx.to_bytes(),
+            is_valid: true,
+        }
+    }
+
+    #[test]
+    fn retried_transaction_becomes_full_event() {
+        let funding = "aa".repeat(32);
+        let mut previous_outputs = HashMap::<String, HashMap<u64, TxOutput>>::new();
+        previous_outputs
+            .entry(funding.clone())
+            .or_default()
+            .insert(0, output(1_000_000));
+
+        let mut stake_address_to_num = DataMapper::new();
+        let mut payment_address_to_num = DataMapper::new();
+        let mut policy_id_to_num = DataMapper::new();
+        let mut asset_name_to_num = DataMapper::new();
+        let mut pointer_resolver = PointerResolver::default();
+        let mut banned_addresses = HashSet::new();
+        let mut unparsed = vec![];
+        let tx = transaction(2, &[(&funding, 0), (&funding, 1)], 2_800_000, 200_000);
+
+        let mut process = |tx: &TransactionModel,
+                           previous_outputs: &mut HashMap<String, HashMap<u64, TxOutput>>,
+                           unparsed: &mut Vec<_>| {
+            carp_tx_to_events(
+                tx,
+                previous_outputs,
+                &mut stake_address_to_num,
+                &mut payment_address_to_num,
+                &mut policy_id_to_num,
+                &mut asset_name_to_num,
+                None,
+                &mut pointer_resolver,
+                &mut banned_addresses,
+                unparsed,
+            )
+            .unwrap()
+        };
+
+        // the second input is not known yet, the first one stays unspent
+        assert!(process(&tx, &mut previous_outputs, &mut unparsed).is_none());
+        assert_eq!(unparsed.len(), 1);
+        assert_eq!(unparsed[0].reason, UnparsedReason::MissingInput);
+        assert_eq!(previous_outputs[&funding][&0], output(1_000_000));
+
+        // e.g. the funding tx is retried as well
+        previous_outputs
+            .get_mut(&funding)
+            .unwrap()
+            .insert(1, output(2_000_000));
+        let mut remaining = vec![];
+        let event = process(&unparsed[0].tx, &mut previous_outputs, &mut remaining);
+        assert!(remaining.is_empty());
+        match event {
+            Some(TxEvent::Full { from, to, fee }) => {
+                assert_eq!(from, vec![output(1_000_000), output(2_000_000)]);
+                assert_eq!(to.len(), 1);
+                assert_eq!(to[0].value, Value::<Regulated>::from(2_800_000));
+                assert_eq!(fee, Value::<Regulated>::from(200_000));
+            }
+            event => panic!("expected a full event, got {:?}", event),
+        }
+        assert!(!previous_outputs.contains_key(&funding));
+        assert!(previous_outputs.contains_key(&hex::encode(vec![2u8; 32])));
+    }
+
+    #[test]
+    fn previous_outputs_roundtrip() {
+        let mut previous_outputs = HashMap::<String, HashMap<u64, TxOutput>>::new();
+        previous_outputs
+            .entry("aa".to_string())
+            .or_default()
+            .insert(0, output(1));
+        previous_outputs
+            .entry("aa".to_string())
+            .or_default()
+            .insert(3, output(2));
+        previous_outputs
+            .entry("bb".to_string())
+            .or_default()
+            .insert(1, output(3));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("previous_outputs.txt");
+        dump_previous_outputs_to_file(&previous_outputs, path.clone()).unwrap();
+        assert_eq!(
+            read_previous_outputs_from_file(path).unwrap(),
+            previous_outputs
+        );
+    }
+
+    #[test]
+    fn cleaning_stats() {
+        let events = vec![
+            TxEvent::Full {
+                from: vec![output(1)],
+                to: vec![output(2)],
+                fee: Value::<Regulated>::from(0),
+            },
+            TxEvent::Full {
+                from: vec![output(3)],
+                to: vec![output(1)],
+                fee: Value::<Regulated>::from(2),
+            },
+            TxEvent::Full {
+                from: vec![output(4)],
+                to: vec![output(3), output(1)],
+                fee: Value::<Regulated>::from(0),
+            },
+            TxEvent::Partial {
+                to: vec![output(1), output(5)],
+            },
+            TxEvent::Partial {
+                to: vec![output(3)],
+            },
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let events_path = dir.path().join("events.ev");
+        let cleaned_path = dir.path().join("cleaned.ev");
+        let mut file = std::fs::File::create(events_path.clone()).unwrap();
+        for event in events.iter() {
+            writeln!(file, "{}", serde_json::to_string(event).unwrap()).unwrap();
+        }
+        drop(file);
+
+        let banned = HashSet::from([(3, None)]);
+        assert!(events_contain_addresses(events_path.clone(), 2, &banned).unwrap());
+        assert!(!events_contain_addresses(events_path.clone(), 1, &banned).unwrap());
+
+        // the first events are already cleaned, the rest is added to the end
+        let _ = std::fs::remove_file(&cleaned_path);
+        let tail =
+            clean_events_tail(events_path.clone(), cleaned_path.clone(), &banned, 3).unwrap();
+        assert_eq!(tail.total, 2);
+        assert_eq!(count_events(cleaned_path.clone()).unwrap(), 1);
+
+        let stats = clean_events(events_path, cleaned_path.clone(), &banned).unwrap();
+        assert_eq!(count_events(cleaned_path).unwrap(), 4);
+        assert_eq!(stats.total, 5);
+        assert_eq!(stats.unchanged, 2);
+        assert_eq!(stats.converted_to_partial, 1);
+        assert_eq!(stats.outputs_hidden, 1);
+        assert_eq!(stats.partial_outputs_removed, 0);
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(stats.affected_by_address[&(3, None)], 3);
+    }
+
+    #[test]
+    fn truncates_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.ev");
+        // the last event was cut off by an interrupted run
+        std::fs::write(&path, "{\"a\":1}\n{\"a\":2}\n{\"a\":3}\n{\"a\"").unwrap();
+
+        assert_eq!(truncate_events(path.clone(), 2).unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\"a\":1}\n{\"a\":2}\n"
+        );
+        assert_eq!(truncate_events(path.clone(), 2).unwrap(), 0);
+        assert!(truncate_events(path.clone(), 3).is_err());
+        assert_eq!(count_events(path).unwrap(), 2);
+    }
+}
@@ -2,7 +2,13 @@ extern crate core;
 
 pub mod bench;
 pub mod bench_utils;
+pub mod blockfrost;
 pub mod generation_utils;
 pub mod mapper;
+pub mod network;
+pub mod parquet_export;
+pub mod plots;
+pub mod remapping;
 pub mod tx_event;
 pub mod utils;
+pub mod utxo_index;
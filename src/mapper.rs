@@ -1,12 +1,12 @@
 use anyhow::{anyhow, Context};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::cmp::max;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fs::File;
 use std::hash::Hash;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::path::PathBuf;
 use std::str::FromStr;
 
@@ -49,56 +49,398 @@ impl<T: Hash + Eq + Serialize + DeserializeOwned + Clone> DataMapper<T> {
         self.invert_mapping.get(&key)
     }
 
+    pub fn len(&self) -> usize {
+        self.mapping.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mapping.is_empty()
+    }
+
+    // adds the keys of the other mapper keeping the existing ids, returns the new ids of the other mapper ids
+    pub fn merge(&mut self, other: &DataMapper<T>) -> HashMap<u64, u64> {
+        let mut indices: Vec<&u64> = other.invert_mapping.keys().collect();
+        indices.sort();
+        indices
+            .into_iter()
+            .map(|index| {
+                let key = other.invert_mapping[index].clone();
+                (*index, self.add_if_not_presented(key))
+            })
+            .collect()
+    }
+
     pub fn dump_to_file(&self, path: PathBuf) -> anyhow::Result<()> {
-        let mut output = File::create(path)?;
-        output.write_all(format!("{}\n", self.mapping.len()).as_bytes())?;
-        for (k, v) in self.mapping.iter() {
-            output.write_all(format!("{}:{}\n", serde_json::to_string(k)?, v).as_bytes())?;
+        self.dump_to_file_in_format(path, MappingFormat::Text)
+    }
+
+    pub fn dump_to_file_in_format(
+        &self,
+        path: PathBuf,
+        format: MappingFormat,
+    ) -> anyhow::Result<()> {
+        let mut entries = Vec::with_capacity(self.invert_mapping.len());
+        for (index, key) in self.invert_mapping.iter() {
+            entries.push((serde_json::to_string(key)?, *index));
         }
-        Ok(())
+        entries.sort_by_key(|(_, index)| *index);
+        write_entries(&entries, path, format)
     }
 
+    // the format is detected by the header, so both text and binary files can be loaded
     pub fn load_from_file(path: PathBuf) -> anyhow::Result<Self> {
         let mut result = Self::new();
+        let entries = read_entries(path)?;
+        let total = entries.len();
+        let mut max_index: u64 = 0;
+        for (num, (key, index)) in entries.into_iter().enumerate() {
+            let data: T =
+                serde_json::from_str(key.as_str()).context(format!("Key of entry: {}", num))?;
+            if result.mapping.insert(data.clone(), index).is_some() {
+                return Err(anyhow!("Data corrupted: duplicate key of entry {}", num));
+            }
+            result.invert_mapping.insert(index, data);
+            max_index = max(max_index, index);
+        }
+        // ids of a loaded empty mapping start from zero, like the ids of a new one
+        result.current_mapping_index = if total == 0 { 0 } else { max_index + 1 };
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingFormat {
+    // `count` line followed by `json_key:index` lines
+    #[default]
+    Text,
+    // header, entries ordered by index and a crc32 checksum
+    Binary,
+}
+
+impl FromStr for MappingFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(MappingFormat::Text),
+            "binary" => Ok(MappingFormat::Binary),
+            _ => Err(anyhow!(
+                "unknown mapping format: {}, expected text or binary",
+                s
+            )),
+        }
+    }
+}
+
+const BINARY_MAGIC: &[u8; 8] = b"UTXOMAP\0";
+const BINARY_VERSION: u32 = 1;
+
+// rewrites the mapping in another format without decoding the keys
+pub fn convert_mapping_file(
+    input: PathBuf,
+    output: PathBuf,
+    format: MappingFormat,
+) -> anyhow::Result<()> {
+    let mut entries = read_entries(input)?;
+    entries.sort_by_key(|(_, index)| *index);
+    write_entries(&entries, output, format)
+}
+
+pub fn detect_mapping_format(path: PathBuf) -> anyhow::Result<MappingFormat> {
+    let mut magic = [0u8; 8];
+    let mut file = File::open(path)?;
+    let mut read = 0;
+    while read < magic.len() {
+        match file.read(&mut magic[read..])? {
+            0 => break,
+            bytes => read += bytes,
+        }
+    }
+    if read == magic.len() && &magic == BINARY_MAGIC {
+        Ok(MappingFormat::Binary)
+    } else {
+        Ok(MappingFormat::Text)
+    }
+}
+
+// json keys with their indices
+// the indices must be 0..count, text mappings of older versions are not ordered
+fn read_entries(path: PathBuf) -> anyhow::Result<Vec<(String, u64)>> {
+    let entries = match detect_mapping_format(path.clone())? {
+        MappingFormat::Text => read_text_entries(path)?,
+        MappingFormat::Binary => read_binary_entries(path)?,
+    };
+    let mut seen = vec![false; entries.len()];
+    for (_, index) in entries.iter() {
+        match seen.get_mut(*index as usize) {
+            Some(seen) if !*seen => *seen = true,
+            Some(_) => return Err(anyhow!("Data corrupted: duplicate index {}", index)),
+            None => return Err(anyhow!("Data corrupted: index {} is out of range", index)),
+        }
+    }
+    Ok(entries)
+}
+
+// the file is written next to the target and renamed, so the previous mapping survives a failed dump
+fn write_entries(
+    entries: &[(String, u64)],
+    path: PathBuf,
+    format: MappingFormat,
+) -> anyhow::Result<()> {
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut output = BufWriter::new(File::create(tmp_path.clone())?);
+    match format {
+        MappingFormat::Text => {
+            output.write_all(format!("{}\n", entries.len()).as_bytes())?;
+            for (key, index) in entries.iter() {
+                output.write_all(format!("{}:{}\n", key, index).as_bytes())?;
+            }
+        }
+        MappingFormat::Binary => {
+            let mut hasher = crc32fast::Hasher::new();
+            let mut write = |bytes: &[u8]| -> anyhow::Result<()> {
+                hasher.update(bytes);
+                output.write_all(bytes)?;
+                Ok(())
+            };
+            write(BINARY_MAGIC)?;
+            write(&BINARY_VERSION.to_le_bytes())?;
+            write(&(entries.len() as u64).to_le_bytes())?;
+            for (key, index) in entries.iter() {
+                write(&index.to_le_bytes())?;
+                write(&(key.len() as u32).to_le_bytes())?;
+                write(key.as_bytes())?;
+            }
+            let checksum = hasher.finalize();
+            output.write_all(&checksum.to_le_bytes())?;
+        }
+    }
+    output
+        .into_inner()
+        .map_err(|err| anyhow!("can't write mapping: {:?}", err))?
+        .sync_all()?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+fn read_text_entries(path: PathBuf) -> anyhow::Result<Vec<(String, u64)>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut lines = reader.lines();
+    let lines_count = if let Some(count) = lines.next() {
+        let count = count?;
+        let lines_count: usize = serde_json::from_str(count.as_str())?;
+        lines_count
+    } else {
+        return Err(anyhow!(
+            "Can't parse first line: expected to see count of values"
+        ));
+    };
 
-        let reader = BufReader::new(File::open(path)?);
-        let mut lines = reader.lines();
-        let lines_count = if let Some(count) = lines.next() {
-            let count = count?;
-            let lines_count: usize = serde_json::from_str(count.as_str())?;
-            lines_count
-        } else {
+    let mut result = Vec::new();
+    for (num, line) in lines.enumerate() {
+        let unwrapped = line?;
+        // the index is a number, so the last ':' separates it from the json key
+        let (key, index) = unwrapped
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("Can't parse {} line: index corrupted", num + 2))?;
+        let index = u64::from_str(index).context(format!("Index at line: {}", num + 2))?;
+        result.push((key.to_string(), index));
+    }
+    if result.len() != lines_count {
+        return Err(anyhow!("Data corrupted: lines count mismatch"));
+    }
+    Ok(result)
+}
+
+// index and key length of an entry
+const BINARY_ENTRY_HEADER_SIZE: u64 = 8 + 4;
+
+fn read_binary_entries(path: PathBuf) -> anyhow::Result<Vec<(String, u64)>> {
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut hasher = crc32fast::Hasher::new();
+    // sizes read from a corrupted file can't allocate more than the file has
+    let mut remaining = file_len;
+    let mut read = |size: usize| -> anyhow::Result<Vec<u8>> {
+        if size as u64 > remaining {
+            return Err(anyhow!("Data corrupted: unexpected end of file"));
+        }
+        remaining -= size as u64;
+        let mut buffer = vec![0u8; size];
+        reader
+            .read_exact(&mut buffer)
+            .context("Data corrupted: unexpected end of file")?;
+        hasher.update(&buffer);
+        Ok(buffer)
+    };
+
+    if read(BINARY_MAGIC.len())? != BINARY_MAGIC {
+        return Err(anyhow!("Not a binary mapping file"));
+    }
+    let version = u32::from_le_bytes(read(4)?.try_into().unwrap());
+    if version != BINARY_VERSION {
+        return Err(anyhow!("Unsupported mapping version: {}", version));
+    }
+    let count = u64::from_le_bytes(read(8)?.try_into().unwrap());
+
+    let mut result = Vec::with_capacity(count.min(file_len / BINARY_ENTRY_HEADER_SIZE) as usize);
+    for position in 0..count {
+        let index = u64::from_le_bytes(read(8)?.try_into().unwrap());
+        if index != position {
             return Err(anyhow!(
-                "Can't parse first line: expected to see count of values"
+                "Data corrupted: entry {} has index {}, expected dense ordered indices",
+                position,
+                index
             ));
-        };
+        }
+        let len = u32::from_le_bytes(read(4)?.try_into().unwrap());
+        let key = String::from_utf8(read(len as usize)?)?;
+        result.push((key, index));
+    }
 
-        let mut read: usize = 0;
-        let mut max_index: u64 = 0;
-        for (num, line) in lines.enumerate() {
-            let unwrapped = line?;
-            let mut split = unwrapped.split(':');
-            let data: T = if let Some(data) = split.next() {
-                serde_json::from_str(data).context(format!("Key at line: {}", num + 2))?
-            } else {
-                return Err(anyhow!("Can't parse {} line: key corrupted", num + 2));
-            };
-            let index: u64 = if let Some(data) = split.next() {
-                u64::from_str(data).context(format!("Index at line: {}", num + 2))?
-            } else {
-                return Err(anyhow!("Can't parse {} line: index corrupted", num + 2));
-            };
+    let checksum = hasher.finalize();
+    let mut expected = [0u8; 4];
+    reader
+        .read_exact(&mut expected)
+        .context("Data corrupted: checksum is missing")?;
+    if u32::from_le_bytes(expected) != checksum {
+        return Err(anyhow!("Data corrupted: checksum mismatch"));
+    }
+    if reader.read(&mut [0u8; 1])? != 0 {
+        return Err(anyhow!("Data corrupted: unexpected data after checksum"));
+    }
+    Ok(result)
+}
 
-            result.mapping.insert(data.clone(), index);
-            result.invert_mapping.insert(index, data);
+#[cfg(test)]
+mod tests {
+    use crate::mapper::{convert_mapping_file, detect_mapping_format, DataMapper, MappingFormat};
 
-            max_index = max(max_index, index);
-            read += 1;
+    #[test]
+    fn formats_roundtrip() {
+        let mut mapper = DataMapper::<String>::new();
+        // keys with ':' used to break the text format
+        for key in ["a", "b:c", "{\"d\":1}", "e"] {
+            mapper.add_if_not_presented(key.to_string());
         }
-        result.current_mapping_index = max_index + 1;
-        if read != lines_count {
-            return Err(anyhow!("Data corrupted: lines count mismatch"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let text = dir.path().join("mapping.mapping");
+        let binary = dir.path().join("mapping.bin");
+        let converted = dir.path().join("converted.mapping");
+
+        mapper.dump_to_file(text.clone()).unwrap();
+        mapper
+            .dump_to_file_in_format(binary.clone(), MappingFormat::Binary)
+            .unwrap();
+        assert_eq!(
+            detect_mapping_format(text.clone()).unwrap(),
+            MappingFormat::Text
+        );
+        assert_eq!(
+            detect_mapping_format(binary.clone()).unwrap(),
+            MappingFormat::Binary
+        );
+
+        for path in [text.clone(), binary.clone()] {
+            let mut loaded = DataMapper::<String>::load_from_file(path).unwrap();
+            assert_eq!(loaded.get(&"b:c".to_string()), Some(1));
+            assert_eq!(loaded.get_by_index(2).unwrap(), "{\"d\":1}");
+            assert_eq!(loaded.add_if_not_presented("f".to_string()), 4);
         }
-        Ok(result)
+
+        convert_mapping_file(binary.clone(), converted.clone(), MappingFormat::Text).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(converted).unwrap(),
+            std::fs::read_to_string(text).unwrap()
+        );
+    }
+
+    #[test]
+    fn binary_checksum() {
+        let mut mapper = DataMapper::<String>::new();
+        mapper.add_if_not_presented("a".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mapping.bin");
+        mapper
+            .dump_to_file_in_format(path.clone(), MappingFormat::Binary)
+            .unwrap();
+
+        let mut bytes = std::fs::read(path.clone()).unwrap();
+        let key_position = bytes.len() - 5;
+        bytes[key_position] = b'b';
+        std::fs::write(path.clone(), bytes).unwrap();
+        assert!(DataMapper::<String>::load_from_file(path).is_err());
+    }
+
+    #[test]
+    fn corrupted_count_is_rejected() {
+        let mut mapper = DataMapper::<String>::new();
+        mapper.add_if_not_presented("a".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mapping.bin");
+        mapper
+            .dump_to_file_in_format(path.clone(), MappingFormat::Binary)
+            .unwrap();
+
+        // the count follows the magic and the version
+        let mut bytes = std::fs::read(path.clone()).unwrap();
+        bytes[12..20].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(path.clone(), bytes).unwrap();
+        let err = DataMapper::<String>::load_from_file(path).unwrap_err();
+        assert!(err.to_string().contains("Data corrupted"));
+    }
+
+    #[test]
+    fn indices_must_be_dense() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mapping.mapping");
+        // older text mappings are not ordered
+        std::fs::write(&path, "2\n\"b\":1\n\"a\":0\n").unwrap();
+        assert_eq!(
+            DataMapper::<String>::load_from_file(path.clone())
+                .unwrap()
+                .get(&"b".to_string()),
+            Some(1)
+        );
+
+        std::fs::write(&path, "2\n\"a\":0\n\"b\":2\n").unwrap();
+        assert!(DataMapper::<String>::load_from_file(path.clone()).is_err());
+        std::fs::write(&path, "2\n\"a\":0\n\"b\":0\n").unwrap();
+        assert!(DataMapper::<String>::load_from_file(path.clone()).is_err());
+        std::fs::write(&path, "2\n\"a\":0\n\"a\":1\n").unwrap();
+        assert!(DataMapper::<String>::load_from_file(path).is_err());
+    }
+
+    #[test]
+    fn merge_keeps_ids() {
+        let mut base = DataMapper::<String>::new();
+        base.add_if_not_presented("a".to_string());
+        base.add_if_not_presented("b".to_string());
+        let mut other = DataMapper::<String>::new();
+        other.add_if_not_presented("c".to_string());
+        other.add_if_not_presented("a".to_string());
+
+        let remap = base.merge(&other);
+        assert_eq!(remap[&0], 2);
+        assert_eq!(remap[&1], 0);
+        assert_eq!(base.get(&"b".to_string()), Some(1));
+        assert_eq!(base.len(), 3);
+    }
+
+    #[test]
+    fn empty_mapping_starts_from_zero() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.mapping");
+        DataMapper::<String>::new()
+            .dump_to_file(path.clone())
+            .unwrap();
+        let mut loaded = DataMapper::<String>::load_from_file(path).unwrap();
+        assert_eq!(loaded.add_if_not_presented("a".to_string()), 0);
     }
 }
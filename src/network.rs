@@ -0,0 +1,155 @@
+use anyhow::anyhow;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::str::FromStr;
+
+// the derived impl is used through the numeric fallback below
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(remote = "Self")]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum NetworkProfile {
+    #[default]
+    Mainnet,
+    Preprod,
+    Preview,
+    Custom {
+        network_id: u8,
+        shelley_start_epoch: i32,
+        blockfrost_endpoint: String,
+        address_prefix: String,
+        stake_prefix: String,
+    },
+}
+
+impl NetworkProfile {
+    pub fn network_id(&self) -> u8 {
+        match self {
+            NetworkProfile::Mainnet => 1,
+            NetworkProfile::Preprod | NetworkProfile::Preview => 0,
+            NetworkProfile::Custom { network_id, .. } => *network_id,
+        }
+    }
+
+    // first epoch of the shelley era
+    pub fn shelley_start_epoch(&self) -> i32 {
+        match self {
+            NetworkProfile::Mainnet => 208,
+            NetworkProfile::Preprod => 4,
+            NetworkProfile::Preview => 0,
+            NetworkProfile::Custom {
+                shelley_start_epoch,
+                ..
+            } => *shelley_start_epoch,
+        }
+    }
+
+    pub fn blockfrost_endpoint(&self) -> String {
+        match self {
+            NetworkProfile::Mainnet => "https://cardano-mainnet.blockfrost.io".to_string(),
+            NetworkProfile::Preprod => "https://cardano-preprod.blockfrost.io".to_string(),
+            NetworkProfile::Preview => "https://cardano-preview.blockfrost.io".to_string(),
+            NetworkProfile::Custom {
+                blockfrost_endpoint,
+                ..
+            } => blockfrost_endpoint.clone(),
+        }
+    }
+
+    pub fn address_prefix(&self) -> String {
+        match self {
+            NetworkProfile::Mainnet => "addr".to_string(),
+            NetworkProfile::Preprod | NetworkProfile::Preview => "addr_test".to_string(),
+            NetworkProfile::Custom { address_prefix, .. } => address_prefix.clone(),
+        }
+    }
+
+    pub fn stake_prefix(&self) -> String {
+        match self {
+            NetworkProfile::Mainnet => "stake".to_string(),
+            NetworkProfile::Preprod | NetworkProfile::Preview => "stake_test".to_string(),
+            NetworkProfile::Custom { stake_prefix, .. } => stake_prefix.clone(),
+        }
+    }
+}
+
+// older configs have the network id instead of the profile name
+impl<'de> Deserialize<'de> for NetworkProfile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum NetworkProfileConfig {
+            NetworkId(u8),
+            Profile(#[serde(deserialize_with = "NetworkProfile::deserialize")] NetworkProfile),
+        }
+
+        match NetworkProfileConfig::deserialize(deserializer)? {
+            NetworkProfileConfig::NetworkId(1) => Ok(NetworkProfile::Mainnet),
+            // preprod and preview share the network id
+            NetworkProfileConfig::NetworkId(0) => Err(D::Error::custom(
+                "network id 0 is ambiguous, use the profile name: preprod or preview",
+            )),
+            NetworkProfileConfig::NetworkId(network_id) => Err(D::Error::custom(format!(
+                "unknown network id: {}, expected 1 (mainnet) or a profile name",
+                network_id
+            ))),
+            NetworkProfileConfig::Profile(profile) => Ok(profile),
+        }
+    }
+}
+
+impl FromStr for NetworkProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(NetworkProfile::Mainnet),
+            "preprod" => Ok(NetworkProfile::Preprod),
+            "preview" => Ok(NetworkProfile::Preview),
+            _ => Err(anyhow!(
+                "unknown network: {}, expected one of mainnet, preprod, preview",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::NetworkProfile;
+    use std::str::FromStr;
+
+    #[test]
+    fn profiles_work() {
+        assert_eq!(NetworkProfile::default(), NetworkProfile::Mainnet);
+        assert_eq!(NetworkProfile::Mainnet.network_id(), 1);
+        assert_eq!(NetworkProfile::Mainnet.shelley_start_epoch(), 208);
+        assert_eq!(NetworkProfile::Preprod.network_id(), 0);
+        assert_eq!(NetworkProfile::Preprod.address_prefix(), "addr_test");
+        assert_eq!(
+            NetworkProfile::from_str("preview").unwrap(),
+            NetworkProfile::Preview
+        );
+        assert!(NetworkProfile::from_str("testnet").is_err());
+
+        let preprod: NetworkProfile = serde_yaml::from_str("preprod").unwrap();
+        assert_eq!(preprod, NetworkProfile::Preprod);
+
+        let custom: NetworkProfile = serde_yaml::from_str(
+            "custom:\n  network_id: 0\n  shelley_start_epoch: 1\n  blockfrost_endpoint: http://localhost\n  address_prefix: addr_test\n  stake_prefix: stake_test",
+        )
+        .unwrap();
+        assert_eq!(custom.shelley_start_epoch(), 1);
+        assert_eq!(custom.blockfrost_endpoint(), "http://localhost");
+    }
+
+    #[test]
+    fn network_ids_are_accepted() {
+        let mainnet: NetworkProfile = serde_yaml::from_str("1").unwrap();
+        assert_eq!(mainnet, NetworkProfile::Mainnet);
+        let err = serde_yaml::from_str::<NetworkProfile>("0").unwrap_err();
+        assert!(err.to_string().contains("preprod or preview"));
+        assert!(serde_yaml::from_str::<NetworkProfile>("2").is_err());
+        assert!(serde_yaml::from_str::<NetworkProfile>("testnet").is_err());
+    }
+}
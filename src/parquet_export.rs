@@ -0,0 +1,449 @@
+use crate::tx_event::{byron_index, TxEvent, TxOutput};
+use crate::utils::value_to_u64;
+use anyhow::anyhow;
+use arrow::array::{
+    ArrayRef, BooleanBuilder, Float64Builder, Int64Builder, StringBuilder, UInt64Builder,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// rows kept in memory before they are written as a record batch
+const BATCH_ROWS: usize = 64 * 1024;
+
+// serializable records written as parquet rows, fields are matched with the columns by name
+pub struct ParquetRecordWriter {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    rows: Vec<Map<String, Value>>,
+}
+
+impl ParquetRecordWriter {
+    pub fn create(path: PathBuf, fields: Vec<Field>) -> anyhow::Result<Self> {
+        let schema = Arc::new(Schema::new(fields));
+        Ok(Self {
+            writer: ArrowWriter::try_new(File::create(path)?, schema.clone(), None)?,
+            schema,
+            rows: vec![],
+        })
+    }
+
+    pub fn write<T: Serialize>(&mut self, record: &T) -> anyhow::Result<()> {
+        match serde_json::to_value(record)? {
+            Value::Object(row) => self.write_row(row),
+            _ => Err(anyhow!("parquet records must be structs")),
+        }
+    }
+
+    pub fn write_row(&mut self, row: Map<String, Value>) -> anyhow::Result<()> {
+        self.rows.push(row);
+        if self.rows.len() >= BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let columns = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| column(field, &self.rows))
+            .collect::<anyhow::Result<Vec<ArrayRef>>>()?;
+        self.writer
+            .write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+// columns of the record, numbers are signed since the sign of a zero default is unknown
+pub fn infer_fields(record: &[(&str, Value)]) -> anyhow::Result<Vec<Field>> {
+    record
+        .iter()
+        .map(|(name, value)| {
+            let data_type = match value {
+                Value::Bool(_) => DataType::Boolean,
+                Value::Number(number) if number.is_f64() => DataType::Float64,
+                Value::Number(_) => DataType::Int64,
+                Value::String(_) => DataType::Utf8,
+                _ => return Err(anyhow!("can't infer the type of column {}", name)),
+            };
+            Ok(Field::new(*name, data_type, false))
+        })
+        .collect()
+}
+
+fn column(field: &Field, rows: &[Map<String, Value>]) -> anyhow::Result<ArrayRef> {
+    let values = rows
+        .iter()
+        .map(|row| row.get(field.name()).unwrap_or(&Value::Null));
+    let mismatch = |value: &Value| {
+        anyhow!(
+            "column {} expects {:?}, got {}",
+            field.name(),
+            field.data_type(),
+            value
+        )
+    };
+    Ok(match field.data_type() {
+        DataType::Boolean => {
+            let mut builder = BooleanBuilder::with_capacity(rows.len());
+            for value in values {
+                match value {
+                    Value::Null => builder.append_null(),
+                    value => builder.append_value(value.as_bool().ok_or_else(|| mismatch(value))?),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Int64 => {
+            let mut builder = Int64Builder::with_capacity(rows.len());
+            for value in values {
+                match value {
+                    Value::Null => builder.append_null(),
+                    value => builder.append_value(value.as_i64().ok_or_else(|| mismatch(value))?),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::UInt64 => {
+            let mut builder = UInt64Builder::with_capacity(rows.len());
+            for value in values {
+                match value {
+                    Value::Null => builder.append_null(),
+                    value => builder.append_value(value.as_u64().ok_or_else(|| mismatch(value))?),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Float64 => {
+            let mut builder = Float64Builder::with_capacity(rows.len());
+            for value in values {
+                match value {
+                    Value::Null => builder.append_null(),
+                    value => builder.append_value(value.as_f64().ok_or_else(|| mismatch(value))?),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Utf8 => {
+            let mut builder = StringBuilder::new();
+            for value in values {
+                match value {
+                    Value::Null => builder.append_null(),
+                    Value::String(value) => builder.append_value(value),
+                    value => builder.append_value(value.to_string()),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        data_type => return Err(anyhow!("unsupported column type {:?}", data_type)),
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct EventRow {
+    event_id: u64,
+    kind: &'static str,
+    fee: Option<u64>,
+    inputs: u64,
+    outputs: u64,
+}
+
+// inputs and outputs share the layout, byron addresses have no credentials
+#[derive(Debug, Serialize)]
+struct OutputRow {
+    event_id: u64,
+    index: u64,
+    payment_key: Option<u64>,
+    staking_key: Option<u64>,
+    byron_address: Option<u64>,
+    value: u64,
+    assets: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct AssetRow {
+    event_id: u64,
+    side: &'static str,
+    index: u64,
+    policy_id: u64,
+    asset_name: u64,
+    value: u64,
+}
+
+fn event_fields() -> Vec<Field> {
+    vec![
+        Field::new("event_id", DataType::UInt64, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("fee", DataType::UInt64, true),
+        Field::new("inputs", DataType::UInt64, false),
+        Field::new("outputs", DataType::UInt64, false),
+    ]
+}
+
+fn output_fields() -> Vec<Field> {
+    vec![
+        Field::new("event_id", DataType::UInt64, false),
+        Field::new("index", DataType::UInt64, false),
+        Field::new("payment_key", DataType::UInt64, true),
+        Field::new("staking_key", DataType::UInt64, true),
+        Field::new("byron_address", DataType::UInt64, true),
+        Field::new("value", DataType::UInt64, false),
+        Field::new("assets", DataType::UInt64, false),
+    ]
+}
+
+fn asset_fields() -> Vec<Field> {
+    vec![
+        Field::new("event_id", DataType::UInt64, false),
+        Field::new("side", DataType::Utf8, false),
+        Field::new("index", DataType::UInt64, false),
+        Field::new("policy_id", DataType::UInt64, false),
+        Field::new("asset_name", DataType::UInt64, false),
+        Field::new("value", DataType::UInt64, false),
+    ]
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct EventsExportSummary {
+    pub events: u64,
+    pub inputs: u64,
+    pub outputs: u64,
+    pub assets: u64,
+}
+
+// events, inputs, outputs and assets tables of the events file, the event id is its line number
+pub fn export_events(
+    events_path: PathBuf,
+    output_dir: PathBuf,
+) -> anyhow::Result<EventsExportSummary> {
+    std::fs::create_dir_all(&output_dir)?;
+    let mut events =
+        ParquetRecordWriter::create(output_dir.join("events.parquet"), event_fields())?;
+    let mut inputs =
+        ParquetRecordWriter::create(output_dir.join("inputs.parquet"), output_fields())?;
+    let mut outputs =
+        ParquetRecordWriter::create(output_dir.join("outputs.parquet"), output_fields())?;
+    let mut assets =
+        ParquetRecordWriter::create(output_dir.join("assets.parquet"), asset_fields())?;
+
+    let mut summary = EventsExportSummary::default();
+    for (event_id, line) in BufReader::new(File::open(events_path)?).lines().enumerate() {
+        let event_id = event_id as u64;
+        let (from, to, fee) = match serde_json::from_str(&line?)? {
+            TxEvent::Full { from, to, fee } => (from, to, Some(value_to_u64(&fee)?)),
+            TxEvent::Partial { to } => (vec![], to, None),
+        };
+        events.write(&EventRow {
+            event_id,
+            kind: if fee.is_some() { "full" } else { "partial" },
+            fee,
+            inputs: from.len() as u64,
+            outputs: to.len() as u64,
+        })?;
+        summary.events += 1;
+
+        for (index, input) in from.into_iter().enumerate() {
+            summary.assets += write_output(
+                event_id,
+                "input",
+                index as u64,
+                input,
+                &mut inputs,
+                &mut assets,
+            )?;
+            summary.inputs += 1;
+        }
+        for (index, output) in to.into_iter().enumerate() {
+            summary.assets += write_output(
+                event_id,
+                "output",
+                index as u64,
+                output,
+                &mut outputs,
+                &mut assets,
+            )?;
+            summary.outputs += 1;
+        }
+    }
+
+    events.finish()?;
+    inputs.finish()?;
+    outputs.finish()?;
+    assets.finish()?;
+    Ok(summary)
+}
+
+fn write_output(
+    event_id: u64,
+    side: &'static str,
+    index: u64,
+    output: TxOutput,
+    writer: &mut ParquetRecordWriter,
+    assets: &mut ParquetRecordWriter,
+) -> anyhow::Result<u64> {
+    let (payment_key, staking_key, byron_address) = match output.address {
+        None => (None, None, None),
+        Some(address) => match byron_index(address) {
+            Some(byron) => (None, None, Some(byron)),
+            None => (Some(address.0), address.1, None),
+        },
+    };
+    writer.write(&OutputRow {
+        event_id,
+        index,
+        payment_key,
+        staking_key,
+        byron_address,
+        value: value_to_u64(&output.value)?,
+        assets: output.assets.len() as u64,
+    })?;
+    for asset in output.assets.iter() {
+        assets.write(&AssetRow {
+            event_id,
+            side,
+            index,
+            policy_id: asset.asset_id.0,
+            asset_name: asset.asset_id.1,
+            value: value_to_u64(&asset.value)?,
+        })?;
+    }
+    Ok(output.assets.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parquet_export::{export_events, infer_fields, EventsExportSummary};
+    use crate::tx_event::{byron_address_pair, TxAsset, TxEvent, TxOutput};
+    use arrow::array::{Array, UInt64Array};
+    use arrow::datatypes::DataType;
+    use dcspark_core::{Regulated, Value};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::File;
+    use std::io::Write;
+
+    fn output(address: Option<(u64, Option<u64>)>, value: u64, assets: Vec<TxAsset>) -> TxOutput {
+        TxOutput {
+            address,
+            value: Value::<Regulated>::from(value),
+            assets,
+        }
+    }
+
+    #[test]
+    fn check_infer_fields() {
+        let record = [
+            ("stake_key", serde_json::json!(1)),
+            ("token", serde_json::json!("a")),
+            ("ratio", serde_json::json!(0.5)),
+            ("ok", serde_json::json!(true)),
+        ];
+        let fields = infer_fields(&record).unwrap();
+        let types: Vec<DataType> = fields.iter().map(|f| f.data_type().clone()).collect();
+        assert_eq!(
+            types,
+            vec![
+                DataType::Int64,
+                DataType::Utf8,
+                DataType::Float64,
+                DataType::Boolean
+            ]
+        );
+        assert!(infer_fields(&[("error", serde_json::Value::Null)]).is_err());
+    }
+
+    #[test]
+    fn check_export_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let events_path = dir.join("events.ev");
+
+        // token quantities use the whole u64 range
+        let asset = TxAsset {
+            asset_id: (2, 3),
+            value: Value::<Regulated>::from(u64::MAX),
+        };
+        let events = vec![
+            TxEvent::Partial {
+                to: vec![output(Some((1, Some(1))), 10, vec![asset.clone()])],
+            },
+            TxEvent::Full {
+                from: vec![output(Some((1, Some(1))), 10, vec![asset])],
+                to: vec![
+                    output(Some(byron_address_pair(4)), 7, vec![]),
+                    output(None, 1, vec![]),
+                ],
+                fee: Value::<Regulated>::from(2u64),
+            },
+        ];
+        let mut file = File::create(&events_path).unwrap();
+        for event in events.iter() {
+            file.write_all(format!("{}\n", serde_json::to_string(event).unwrap()).as_bytes())
+                .unwrap();
+        }
+        drop(file);
+
+        let summary = export_events(events_path, dir.join("tables")).unwrap();
+        assert_eq!(
+            summary,
+            EventsExportSummary {
+                events: 2,
+                inputs: 1,
+                outputs: 3,
+                assets: 2,
+            }
+        );
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(
+            File::open(dir.join("tables").join("outputs.parquet")).unwrap(),
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        let batches: Vec<_> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        let column = |name: &str| batch.column(batch.schema().index_of(name).unwrap()).clone();
+
+        let event_ids = column("event_id");
+        let event_ids = event_ids.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(event_ids.values().to_vec(), vec![0, 1, 1]);
+        let byron = column("byron_address");
+        let byron = byron.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert!(byron.is_null(0));
+        assert_eq!(byron.value(1), 4);
+        let staking = column("staking_key");
+        assert_eq!(staking.null_count(), 2);
+        let values = column("value");
+        let values = values.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(values.values().to_vec(), vec![10, 7, 1]);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(
+            File::open(dir.join("tables").join("assets.parquet")).unwrap(),
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        let batch = reader.map(|batch| batch.unwrap()).next().unwrap();
+        let values = batch.column(batch.schema().index_of("value").unwrap());
+        let values = values.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(values.values().to_vec(), vec![u64::MAX, u64::MAX]);
+    }
+}
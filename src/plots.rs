@@ -0,0 +1,333 @@
+use anyhow::anyhow;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const WIDTH: f64 = 900.0;
+const HEIGHT: f64 = 420.0;
+const MARGIN_LEFT: f64 = 80.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 50.0;
+const TICKS: usize = 5;
+// longer series are thinned out, the charts can't show more anyway
+const MAX_POINTS: usize = 2000;
+const COLORS: [&str; 8] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+];
+
+// csv dumped by `StatsAccumulator`: stake_key,index,<columns>
+#[derive(Debug, Default)]
+pub struct StatsTable {
+    pub columns: Vec<String>,
+    pub points: BTreeMap<u64, Vec<(u64, Vec<i64>)>>,
+}
+
+impl StatsTable {
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let mut lines = BufReader::new(File::open(&path)?).lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| anyhow!("empty stats file: {:?}", path))??;
+        let columns: Vec<String> = header.split(',').skip(2).map(str::to_string).collect();
+
+        let mut points = BTreeMap::<u64, Vec<(u64, Vec<i64>)>>::new();
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut parts = line.split(',');
+            let mut next = || {
+                parts
+                    .next()
+                    .ok_or_else(|| anyhow!("malformed stats line: {}", line))
+            };
+            let stake_key = u64::from_str(next()?)?;
+            let index = u64::from_str(next()?)?;
+            let values = (0..columns.len())
+                .map(|_| Ok(i64::from_str(next()?)?))
+                .collect::<anyhow::Result<Vec<i64>>>()?;
+            points.entry(stake_key).or_default().push((index, values));
+        }
+        for key_points in points.values_mut() {
+            key_points.sort_by_key(|(index, _)| *index);
+        }
+
+        Ok(Self { columns, points })
+    }
+
+    pub fn column(&self, name: &str) -> anyhow::Result<usize> {
+        self.columns
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| anyhow!("no column {} in the stats", name))
+    }
+
+    // points of the column for the key, divided by `scale`
+    pub fn series(
+        &self,
+        stake_key: u64,
+        column: &str,
+        scale: f64,
+    ) -> anyhow::Result<Vec<(f64, f64)>> {
+        let column = self.column(column)?;
+        Ok(self
+            .points
+            .get(&stake_key)
+            .map(|points| {
+                points
+                    .iter()
+                    .map(|(index, values)| (*index as f64, values[column] as f64 / scale))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Series {
+    pub label: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+impl Series {
+    pub fn new(label: impl Into<String>, points: Vec<(f64, f64)>) -> Self {
+        Self {
+            label: label.into(),
+            points,
+        }
+    }
+
+    // difference of two series with the same x coordinates
+    pub fn difference(label: impl Into<String>, left: &[(f64, f64)], right: &[(f64, f64)]) -> Self {
+        let right: BTreeMap<u64, f64> = right.iter().map(|(x, y)| (*x as u64, *y)).collect();
+        Self::new(
+            label,
+            left.iter()
+                .filter_map(|(x, y)| right.get(&(*x as u64)).map(|other| (*x, y - other)))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LineChart {
+    pub title: String,
+    pub x_label: String,
+    pub y_label: String,
+    pub series: Vec<Series>,
+}
+
+impl LineChart {
+    pub fn to_svg(&self) -> String {
+        let points = self.series.iter().flat_map(|series| series.points.iter());
+        let (mut min_x, mut max_x) = (f64::MAX, f64::MIN);
+        let (mut min_y, mut max_y) = (f64::MAX, f64::MIN);
+        for (x, y) in points {
+            min_x = min_x.min(*x);
+            max_x = max_x.max(*x);
+            min_y = min_y.min(*y);
+            max_y = max_y.max(*y);
+        }
+        if min_x > max_x {
+            (min_x, max_x, min_y, max_y) = (0.0, 1.0, 0.0, 1.0);
+        }
+        if max_x == min_x {
+            max_x = min_x + 1.0;
+        }
+        if max_y == min_y {
+            (min_y, max_y) = (min_y - 1.0, max_y + 1.0);
+        }
+
+        let plot_width = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+        let plot_height = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+        let to_x = |x: f64| MARGIN_LEFT + (x - min_x) / (max_x - min_x) * plot_width;
+        let to_y = |y: f64| MARGIN_TOP + (max_y - y) / (max_y - min_y) * plot_height;
+
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" font-family="sans-serif" font-size="12">"#
+        );
+        let _ = write!(
+            svg,
+            r#"<rect width="{WIDTH}" height="{HEIGHT}" fill="white"/><text x="{}" y="20" text-anchor="middle" font-size="16">{}</text>"#,
+            WIDTH / 2.0,
+            escape(&self.title)
+        );
+
+        for tick in 0..=TICKS {
+            let share = tick as f64 / TICKS as f64;
+            let x = min_x + (max_x - min_x) * share;
+            let y = min_y + (max_y - min_y) * share;
+            let _ = write!(
+                svg,
+                r##"<line x1="{x0:.1}" y1="{MARGIN_TOP}" x2="{x0:.1}" y2="{y1:.1}" stroke="#ddd"/><text x="{x0:.1}" y="{ty:.1}" text-anchor="middle">{}</text>"##,
+                format_tick(x),
+                x0 = to_x(x),
+                y1 = MARGIN_TOP + plot_height,
+                ty = MARGIN_TOP + plot_height + 16.0,
+            );
+            let _ = write!(
+                svg,
+                r##"<line x1="{MARGIN_LEFT}" y1="{y0:.1}" x2="{x1:.1}" y2="{y0:.1}" stroke="#ddd"/><text x="{tx:.1}" y="{y0:.1}" text-anchor="end" dominant-baseline="middle">{}</text>"##,
+                format_tick(y),
+                y0 = to_y(y),
+                x1 = MARGIN_LEFT + plot_width,
+                tx = MARGIN_LEFT - 6.0,
+            );
+        }
+        let _ = write!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="middle">{}</text><text x="16" y="{}" text-anchor="middle" transform="rotate(-90 16 {})">{}</text>"#,
+            MARGIN_LEFT + plot_width / 2.0,
+            HEIGHT - 10.0,
+            escape(&self.x_label),
+            MARGIN_TOP + plot_height / 2.0,
+            MARGIN_TOP + plot_height / 2.0,
+            escape(&self.y_label)
+        );
+
+        for (number, series) in self.series.iter().enumerate() {
+            let color = COLORS[number % COLORS.len()];
+            let points = thin_out(&series.points)
+                .iter()
+                .map(|(x, y)| format!("{:.1},{:.1}", to_x(*x), to_y(*y)))
+                .collect::<Vec<String>>()
+                .join(" ");
+            let _ = write!(
+                svg,
+                r#"<polyline fill="none" stroke="{color}" stroke-width="1.5" points="{points}"/>"#
+            );
+            let legend_y = MARGIN_TOP + 10.0 + 16.0 * number as f64;
+            let _ = write!(
+                svg,
+                r#"<rect x="{}" y="{}" width="12" height="3" fill="{color}"/><text x="{}" y="{}" dominant-baseline="middle">{}</text>"#,
+                MARGIN_LEFT + 10.0,
+                legend_y - 1.5,
+                MARGIN_LEFT + 28.0,
+                legend_y,
+                escape(&series.label)
+            );
+        }
+
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+// static html page with a section of charts per entry
+pub fn html_report(title: &str, sections: &[(String, Vec<LineChart>)]) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body style=\"font-family: sans-serif\">\n<h1>{title}</h1>\n",
+        title = escape(title)
+    );
+    for (name, charts) in sections.iter() {
+        let _ = writeln!(html, "<h2>{}</h2>", escape(name));
+        for chart in charts.iter() {
+            let _ = writeln!(html, "<div>{}</div>", chart.to_svg());
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn thin_out(points: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    if points.len() <= MAX_POINTS {
+        return points.to_vec();
+    }
+    let step = points.len().div_ceil(MAX_POINTS);
+    let mut thinned: Vec<(f64, f64)> = points.iter().step_by(step).cloned().collect();
+    if let Some(last) = points.last() {
+        thinned.push(*last);
+    }
+    thinned
+}
+
+fn format_tick(value: f64) -> String {
+    if value.abs() >= 1000.0 || value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plots::{html_report, thin_out, LineChart, Series, StatsTable, MAX_POINTS};
+
+    #[test]
+    fn load_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.csv");
+        std::fs::write(
+            &path,
+            "stake_key,index,ada_computed,ada_actual\n1,5,10,12\n1,2,3,4\n2,0,1,1\n\n",
+        )
+        .unwrap();
+        let table = StatsTable::load(path.clone()).unwrap();
+        assert_eq!(table.columns, vec!["ada_computed", "ada_actual"]);
+        assert_eq!(
+            table.series(1, "ada_actual", 2.0).unwrap(),
+            vec![(2.0, 2.0), (5.0, 6.0)]
+        );
+        assert!(table.series(3, "ada_actual", 1.0).unwrap().is_empty());
+        assert!(table.series(1, "utxo_count", 1.0).is_err());
+
+        std::fs::write(&path, "stake_key,index,ada_computed\n1,5\n").unwrap();
+        assert!(StatsTable::load(path).is_err());
+    }
+
+    #[test]
+    fn render_chart() {
+        let computed = vec![(0.0, 1.0), (1.0, 3.0), (2.0, 2.0)];
+        let actual = vec![(0.0, 1.0), (1.0, 2.0), (2.0, 2.0)];
+        let chart = LineChart {
+            title: "balance <ada>".to_string(),
+            x_label: "# of transactions".to_string(),
+            y_label: "ada".to_string(),
+            series: vec![
+                Series::new("computed", computed.clone()),
+                Series::difference("computed - actual", &computed, &actual),
+            ],
+        };
+        assert_eq!(
+            chart.series[1].points,
+            vec![(0.0, 0.0), (1.0, 1.0), (2.0, 0.0)]
+        );
+        let svg = chart.to_svg();
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        assert!(svg.contains("balance &lt;ada&gt;"));
+
+        let empty = LineChart {
+            series: vec![],
+            ..chart.clone()
+        };
+        assert!(!empty.to_svg().contains("NaN"));
+
+        let html = html_report("report", &[("key 1".to_string(), vec![chart])]);
+        assert!(html.contains("<h2>key 1</h2>"));
+    }
+
+    #[test]
+    fn thin_out_keeps_last_point() {
+        let points: Vec<(f64, f64)> = (0..MAX_POINTS * 3 + 1).map(|x| (x as f64, 0.0)).collect();
+        let thinned = thin_out(&points);
+        assert!(thinned.len() <= MAX_POINTS + 1);
+        assert_eq!(thinned.last(), points.last());
+    }
+}
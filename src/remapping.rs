@@ -0,0 +1,149 @@
+use crate::tx_event::{byron_address_pair, byron_index, TxAsset, TxEvent, TxOutput};
+use anyhow::anyhow;
+use std::collections::{HashMap, HashSet};
+
+// ids of one dataset in the merged mappings, see `DataMapper::merge`
+#[derive(Default, Debug)]
+pub struct DatasetRemapping {
+    pub payment: HashMap<u64, u64>,
+    pub staking: HashMap<u64, u64>,
+    pub policy: HashMap<u64, u64>,
+    pub asset_name: HashMap<u64, u64>,
+    // none when the dataset has no byron mapping
+    pub byron: Option<HashMap<u64, u64>>,
+}
+
+impl DatasetRemapping {
+    pub fn remap_address(&self, address: (u64, Option<u64>)) -> anyhow::Result<(u64, Option<u64>)> {
+        if let Some(index) = byron_index(address) {
+            let byron = self
+                .byron
+                .as_ref()
+                .ok_or_else(|| anyhow!("byron address {:?} without byron mapping", address))?;
+            return Ok(byron_address_pair(remap_id(byron, index, "byron address")?));
+        }
+        let (payment, staking) = address;
+        let payment = remap_id(&self.payment, payment, "payment key")?;
+        let staking = match staking {
+            Some(staking) => Some(remap_id(&self.staking, staking, "staking key")?),
+            None => None,
+        };
+        Ok((payment, staking))
+    }
+
+    pub fn remap_staking_key(&self, staking_key: u64) -> anyhow::Result<u64> {
+        remap_id(&self.staking, staking_key, "staking key")
+    }
+
+    pub fn remap_output(&self, output: TxOutput) -> anyhow::Result<TxOutput> {
+        let address = match output.address {
+            Some(address) => Some(self.remap_address(address)?),
+            None => None,
+        };
+        let mut assets = Vec::with_capacity(output.assets.len());
+        for asset in output.assets.into_iter() {
+            let (policy, asset_name) = asset.asset_id;
+            assets.push(TxAsset {
+                asset_id: (
+                    remap_id(&self.policy, policy, "policy id")?,
+                    remap_id(&self.asset_name, asset_name, "asset name")?,
+                ),
+                value: asset.value,
+            });
+        }
+        Ok(TxOutput {
+            address,
+            value: output.value,
+            assets,
+        })
+    }
+
+    pub fn remap_event(&self, event: TxEvent) -> anyhow::Result<TxEvent> {
+        let remap_outputs = |outputs: Vec<TxOutput>| -> anyhow::Result<Vec<TxOutput>> {
+            outputs
+                .into_iter()
+                .map(|output| self.remap_output(output))
+                .collect()
+        };
+        Ok(match event {
+            TxEvent::Partial { to } => TxEvent::Partial {
+                to: remap_outputs(to)?,
+            },
+            TxEvent::Full { to, fee, from } => TxEvent::Full {
+                to: remap_outputs(to)?,
+                fee,
+                from: remap_outputs(from)?,
+            },
+        })
+    }
+
+    pub fn remap_addresses(
+        &self,
+        addresses: &HashSet<(u64, Option<u64>)>,
+    ) -> anyhow::Result<HashSet<(u64, Option<u64>)>> {
+        addresses
+            .iter()
+            .map(|address| self.remap_address(*address))
+            .collect()
+    }
+}
+
+fn remap_id(ids: &HashMap<u64, u64>, id: u64, kind: &str) -> anyhow::Result<u64> {
+    ids.get(&id)
+        .cloned()
+        .ok_or_else(|| anyhow!("unknown {} id: {}", kind, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::remapping::DatasetRemapping;
+    use crate::tx_event::{byron_address_pair, TxAsset, TxEvent, TxOutput};
+    use dcspark_core::{Regulated, Value};
+    use std::collections::HashMap;
+
+    #[test]
+    fn remap_event() {
+        let remapping = DatasetRemapping {
+            payment: HashMap::from([(0, 5), (1, 6)]),
+            staking: HashMap::from([(0, 7)]),
+            policy: HashMap::from([(0, 1)]),
+            asset_name: HashMap::from([(0, 2)]),
+            byron: Some(HashMap::from([(0, 3)])),
+        };
+        let event = TxEvent::Full {
+            from: vec![TxOutput {
+                address: Some((0, Some(0))),
+                value: Value::<Regulated>::from(10),
+                assets: vec![TxAsset {
+                    asset_id: (0, 0),
+                    value: Value::<Regulated>::from(1),
+                }],
+            }],
+            to: vec![
+                TxOutput {
+                    address: Some((1, None)),
+                    value: Value::<Regulated>::from(5),
+                    assets: vec![],
+                },
+                TxOutput {
+                    address: Some(byron_address_pair(0)),
+                    value: Value::<Regulated>::from(5),
+                    assets: vec![],
+                },
+            ],
+            fee: Value::<Regulated>::from(0),
+        };
+
+        match remapping.remap_event(event).unwrap() {
+            TxEvent::Full { from, to, .. } => {
+                assert_eq!(from[0].address, Some((5, Some(7))));
+                assert_eq!(from[0].assets[0].asset_id, (1, 2));
+                assert_eq!(to[0].address, Some((6, None)));
+                assert_eq!(to[1].address, Some(byron_address_pair(3)));
+            }
+            TxEvent::Partial { .. } => panic!("expected full event"),
+        }
+
+        assert!(remapping.remap_address((2, None)).is_err());
+    }
+}
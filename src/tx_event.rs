@@ -24,6 +24,21 @@ impl From<TxAsset> for TransactionAsset {
     }
 }
 
+// byron addresses have no credentials: they are mapped by a separate mapper and use the same
+// shifted index as payment and staking key, so every byron address works as a separate wallet
+pub const BYRON_INDEX_OFFSET: u64 = 1 << 63;
+
+pub fn byron_address_pair(index: u64) -> (u64, Option<u64>) {
+    (BYRON_INDEX_OFFSET + index, Some(BYRON_INDEX_OFFSET + index))
+}
+
+pub fn byron_index(address: (u64, Option<u64>)) -> Option<u64> {
+    match address {
+        (pk, Some(sk)) if pk == sk && pk >= BYRON_INDEX_OFFSET => Some(pk - BYRON_INDEX_OFFSET),
+        _ => None,
+    }
+}
+
 pub fn address_from_pair(address: (u64, Option<u64>)) -> Address {
     if let Some(staking) = address.1 {
         Address::new(format!("{}_{}", address.0, staking))
@@ -77,9 +92,14 @@ impl TxOutput {
             .unwrap_or(false)
     }
 
+    // the address is unknown: byron address which wasn't mapped or the output wasn't found
     pub fn is_byron(&self) -> bool {
         self.address.is_none()
     }
+
+    pub fn is_byron_address(&self) -> bool {
+        self.address.and_then(byron_index).is_some()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -96,9 +116,23 @@ pub enum TxEvent {
     },
 }
 
+impl TxEvent {
+    // staking keys of the inputs and outputs
+    pub fn staking_keys(&self) -> HashSet<u64> {
+        let (from, to): (&[TxOutput], &[TxOutput]) = match self {
+            TxEvent::Full { from, to, .. } => (from, to),
+            TxEvent::Partial { to } => (&[], to),
+        };
+        from.iter()
+            .chain(to.iter())
+            .filter_map(|output| output.address.and_then(|(_, staking)| staking))
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::tx_event::{address_from_pair, pair_from_address};
+    use crate::tx_event::{address_from_pair, byron_address_pair, byron_index, pair_from_address};
     use dcspark_core::Address;
 
     #[test]
@@ -116,4 +150,13 @@ mod tests {
 
         assert!(pair_from_address(Address::new("byron")).is_none());
     }
+
+    #[test]
+    fn byron_addr_test() {
+        let addr = byron_address_pair(5);
+        assert_eq!(byron_index(addr), Some(5));
+        assert_eq!(pair_from_address(address_from_pair(addr)), Some(addr));
+        assert_eq!(byron_index((5, Some(5))), None);
+        assert_eq!(byron_index((5, None)), None);
+    }
 }
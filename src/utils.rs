@@ -1,5 +1,5 @@
 use anyhow::{anyhow, Context};
-use dcspark_core::{Balance, Regulated};
+use dcspark_core::{Balance, Regulated, Value};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::collections::{HashMap, HashSet};
@@ -120,3 +120,22 @@ pub fn balance_to_i64(balance: Balance<Regulated>) -> i64 {
         Balance::Excess(value) => i64::from_str(value.to_string().as_str()).unwrap(),
     }
 }
+
+// token quantities are u64, so they may not fit
+pub fn checked_balance_to_i64(balance: Balance<Regulated>) -> anyhow::Result<i64> {
+    Ok(match balance {
+        Balance::Debt(value) => -checked_value_to_i64(&value)?,
+        Balance::Balanced => 0,
+        Balance::Excess(value) => checked_value_to_i64(&value)?,
+    })
+}
+
+pub fn value_to_u64(value: &Value<Regulated>) -> anyhow::Result<u64> {
+    u64::from_str(value.to_string().as_str())
+        .map_err(|err| anyhow!("can't convert value {}: {}", value, err))
+}
+
+pub fn checked_value_to_i64(value: &Value<Regulated>) -> anyhow::Result<i64> {
+    i64::from_str(value.to_string().as_str())
+        .map_err(|err| anyhow!("value {} doesn't fit in i64: {}", value, err))
+}
@@ -0,0 +1,297 @@
+use crate::tx_event::TxOutput;
+use anyhow::anyhow;
+use lru::LruCache;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+
+// unspent outputs of the fetched transactions, keyed by hex tx hash and output index
+pub trait UtxoIndex {
+    fn insert(&mut self, tx_hash: &str, index: u64, output: TxOutput) -> anyhow::Result<()>;
+
+    fn remove(&mut self, tx_hash: &str, index: u64) -> anyhow::Result<Option<TxOutput>>;
+
+    fn get(&mut self, tx_hash: &str, index: u64) -> anyhow::Result<Option<TxOutput>>;
+
+    // the transaction still has unspent outputs
+    fn contains_transaction(&mut self, tx_hash: &str) -> anyhow::Result<bool>;
+
+    fn output_count(&self) -> usize;
+
+    // all unspent outputs as (tx hash, output index, output), in no particular order
+    fn outputs(&self) -> Box<dyn Iterator<Item = anyhow::Result<(String, u64, TxOutput)>> + '_>;
+
+    // the index is changed by the transactions up to this id, checked when a run is resumed
+    fn set_last_processed_tx(&mut self, _tx_id: i64) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl UtxoIndex for HashMap<String, HashMap<u64, TxOutput>> {
+    fn insert(&mut self, tx_hash: &str, index: u64, output: TxOutput) -> anyhow::Result<()> {
+        self.entry(tx_hash.to_string())
+            .or_default()
+            .insert(index, output);
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_hash: &str, index: u64) -> anyhow::Result<Option<TxOutput>> {
+        let outputs = match self.get_mut(tx_hash) {
+            Some(outputs) => outputs,
+            None => return Ok(None),
+        };
+        let output = outputs.remove(&index);
+        if outputs.is_empty() {
+            HashMap::remove(self, tx_hash);
+        }
+        Ok(output)
+    }
+
+    fn get(&mut self, tx_hash: &str, index: u64) -> anyhow::Result<Option<TxOutput>> {
+        Ok(HashMap::get(self, tx_hash).and_then(|outputs| outputs.get(&index).cloned()))
+    }
+
+    fn contains_transaction(&mut self, tx_hash: &str) -> anyhow::Result<bool> {
+        Ok(self.contains_key(tx_hash))
+    }
+
+    fn output_count(&self) -> usize {
+        self.values().map(|outputs| outputs.len()).sum()
+    }
+
+    fn outputs(&self) -> Box<dyn Iterator<Item = anyhow::Result<(String, u64, TxOutput)>> + '_> {
+        Box::new(self.iter().flat_map(|(tx_hash, outputs)| {
+            outputs
+                .iter()
+                .map(move |(index, output)| Ok((tx_hash.clone(), *index, output.clone())))
+        }))
+    }
+}
+
+// outputs are written through to the embedded store, the cache saves the reads of recent outputs
+pub struct OnDiskUtxoIndex {
+    db: sled::Db,
+    // progress of the fetch, kept apart from the outputs
+    meta: sled::Tree,
+    cache: LruCache<(String, u64), TxOutput>,
+}
+
+const LAST_PROCESSED_TX_KEY: &[u8] = b"last_processed_tx";
+
+impl OnDiskUtxoIndex {
+    // the index is kept in the path, so reopening it continues from the previous run
+    pub fn open(path: PathBuf, cache_size: usize) -> anyhow::Result<Self> {
+        let cache_size = NonZeroUsize::new(cache_size)
+            .ok_or_else(|| anyhow!("cache size must be greater than zero"))?;
+        let db = sled::open(path)?;
+        Ok(Self {
+            meta: db.open_tree("meta")?,
+            db,
+            cache: LruCache::new(cache_size),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.db.is_empty()
+    }
+
+    pub fn last_processed_tx(&self) -> anyhow::Result<Option<i64>> {
+        match self.meta.get(LAST_PROCESSED_TX_KEY)? {
+            Some(stored) => {
+                let bytes: [u8; 8] = stored
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| anyhow!("invalid last processed tx in the utxo index"))?;
+                Ok(Some(i64::from_be_bytes(bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn key(tx_hash: &str, index: u64) -> anyhow::Result<Vec<u8>> {
+        let mut key = hex::decode(tx_hash)?;
+        key.extend(index.to_be_bytes());
+        Ok(key)
+    }
+}
+
+impl UtxoIndex for OnDiskUtxoIndex {
+    fn insert(&mut self, tx_hash: &str, index: u64, output: TxOutput) -> anyhow::Result<()> {
+        self.db
+            .insert(Self::key(tx_hash, index)?, serde_json::to_vec(&output)?)?;
+        self.cache.put((tx_hash.to_string(), index), output);
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_hash: &str, index: u64) -> anyhow::Result<Option<TxOutput>> {
+        let cached = self.cache.pop(&(tx_hash.to_string(), index));
+        let stored = self.db.remove(Self::key(tx_hash, index)?)?;
+        match (cached, stored) {
+            (Some(output), _) => Ok(Some(output)),
+            (None, Some(stored)) => Ok(Some(serde_json::from_slice(&stored)?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn get(&mut self, tx_hash: &str, index: u64) -> anyhow::Result<Option<TxOutput>> {
+        let cache_key = (tx_hash.to_string(), index);
+        if let Some(output) = self.cache.get(&cache_key) {
+            return Ok(Some(output.clone()));
+        }
+        match self.db.get(Self::key(tx_hash, index)?)? {
+            Some(stored) => {
+                let output: TxOutput = serde_json::from_slice(&stored)?;
+                self.cache.put(cache_key, output.clone());
+                Ok(Some(output))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn contains_transaction(&mut self, tx_hash: &str) -> anyhow::Result<bool> {
+        // hashes have the same length, so the prefix matches the outputs of a single transaction
+        Ok(self
+            .db
+            .scan_prefix(hex::decode(tx_hash)?)
+            .next()
+            .transpose()?
+            .is_some())
+    }
+
+    // every change is written through, so the tree has all the outputs
+    fn output_count(&self) -> usize {
+        self.db.len()
+    }
+
+    fn outputs(&self) -> Box<dyn Iterator<Item = anyhow::Result<(String, u64, TxOutput)>> + '_> {
+        Box::new(self.db.iter().map(|entry| {
+            let (key, value) = entry?;
+            if key.len() < 8 {
+                return Err(anyhow!("invalid utxo index key: {}", hex::encode(&key)));
+            }
+            let (tx_hash, index) = key.split_at(key.len() - 8);
+            let mut index_bytes = [0u8; 8];
+            index_bytes.copy_from_slice(index);
+            Ok((
+                hex::encode(tx_hash),
+                u64::from_be_bytes(index_bytes),
+                serde_json::from_slice(&value)?,
+            ))
+        }))
+    }
+
+    fn set_last_processed_tx(&mut self, tx_id: i64) -> anyhow::Result<()> {
+        self.meta
+            .insert(LAST_PROCESSED_TX_KEY, &tx_id.to_be_bytes())?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum UtxoIndexConfig {
+    #[default]
+    InMemory,
+    OnDisk {
+        path: PathBuf,
+        // outputs kept in memory
+        cache_size: usize,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generation_utils::{dump_previous_outputs_to_file, read_previous_outputs_from_file};
+    use crate::tx_event::TxOutput;
+    use crate::utxo_index::{OnDiskUtxoIndex, UtxoIndex, UtxoIndexConfig};
+    use dcspark_core::{Regulated, Value};
+    use std::collections::HashMap;
+
+    fn output(value: u64) -> TxOutput {
+        TxOutput {
+            address: Some((value, None)),
+            value: Value::<Regulated>::from(value),
+            assets: vec![],
+        }
+    }
+
+    fn check_index<Index: UtxoIndex>(index: &mut Index) {
+        let first = "aa".repeat(32);
+        let second = "ab".repeat(32);
+
+        index.insert(&first, 0, output(1)).unwrap();
+        index.insert(&first, 1, output(2)).unwrap();
+        index.insert(&second, 0, output(3)).unwrap();
+        assert!(index.contains_transaction(&first).unwrap());
+        assert!(!index.contains_transaction(&"bb".repeat(32)).unwrap());
+
+        assert_eq!(index.get(&first, 1).unwrap(), Some(output(2)));
+        assert_eq!(index.remove(&first, 1).unwrap(), Some(output(2)));
+        assert_eq!(index.remove(&first, 1).unwrap(), None);
+        assert!(index.contains_transaction(&first).unwrap());
+
+        assert_eq!(index.remove(&first, 0).unwrap(), Some(output(1)));
+        assert!(!index.contains_transaction(&first).unwrap());
+        assert_eq!(index.get(&second, 0).unwrap(), Some(output(3)));
+        assert_eq!(index.output_count(), 1);
+        assert_eq!(
+            index.outputs().collect::<anyhow::Result<Vec<_>>>().unwrap(),
+            vec![(second, 0, output(3))]
+        );
+        index.flush().unwrap();
+    }
+
+    #[test]
+    fn in_memory_index() {
+        check_index(&mut HashMap::<String, HashMap<u64, TxOutput>>::new());
+    }
+
+    #[test]
+    fn on_disk_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("utxo_index");
+        // the cache is smaller than the number of outputs, so some of them are read from disk
+        let mut index = OnDiskUtxoIndex::open(path.clone(), 1).unwrap();
+        check_index(&mut index);
+        assert_eq!(index.last_processed_tx().unwrap(), None);
+        index.set_last_processed_tx(7).unwrap();
+        drop(index);
+
+        let mut reopened = OnDiskUtxoIndex::open(path, 1).unwrap();
+        assert_eq!(reopened.get(&"ab".repeat(32), 0).unwrap(), Some(output(3)));
+        assert_eq!(reopened.last_processed_tx().unwrap(), Some(7));
+        // the progress is not an output
+        assert_eq!(reopened.output_count(), 1);
+
+        // the dump is the same as the one of the in-memory index
+        let dump = dir.path().join("previous_outputs");
+        dump_previous_outputs_to_file(&reopened, dump.clone()).unwrap();
+        assert_eq!(
+            read_previous_outputs_from_file(dump).unwrap(),
+            HashMap::from([("ab".repeat(32), HashMap::from([(0, output(3))]))])
+        );
+    }
+
+    #[test]
+    fn config() {
+        let config: UtxoIndexConfig = serde_yaml::from_str("in_memory").unwrap();
+        assert!(matches!(config, UtxoIndexConfig::InMemory));
+        let config: UtxoIndexConfig =
+            serde_yaml::from_str("on_disk:\n  path: utxo_index\n  cache_size: 10").unwrap();
+        assert!(matches!(
+            config,
+            UtxoIndexConfig::OnDisk { cache_size: 10, .. }
+        ));
+    }
+}